@@ -1,12 +1,12 @@
 use parley::context::RangedBuilder;
-use parley::layout::Cursor;
+use parley::layout::{Cursor, Selection};
 use parley::style::Brush;
 use parley::*;
 use piet::kurbo::{Point, Rect, Size};
 use piet::*;
 
 use std::cell::RefCell;
-use std::ops::RangeBounds;
+use std::ops::{Bound, RangeBounds};
 use std::rc::Rc;
 
 pub use parley;
@@ -78,24 +78,52 @@ impl TextLayout for ParleyTextLayout {
     fn hit_test_point(&self, point: Point) -> HitTestPoint {
         let cursor = Cursor::from_point(&self.layout, point.x as f32, point.y as f32);
         let mut result = HitTestPoint::default();
-        let range = cursor.text_range();
-        // FIXME: this is horribly broken for BiDi text
-        if cursor.is_trailing() {
-            result.idx = range.end;
-        } else {
-            result.idx = range.start;
-        }
+        // The visual side of the hit cluster is mapped to a logical edge
+        // according to the direction of its run, so the left half of a
+        // right-to-left cluster yields the end of its text range.
+        result.idx = cursor.text_position();
         result.is_inside = cursor.is_inside();
         result
     }
 
     fn hit_test_text_position(&self, idx: usize) -> HitTestPosition {
+        // The caret is placed at the leading edge of the cluster that
+        // begins at the position, which is its right edge in a
+        // right-to-left run.
         let cursor = Cursor::from_position(&self.layout, idx, true);
         let mut result = HitTestPosition::default();
         result.point = Point::new(cursor.offset() as f64, cursor.baseline() as f64);
         result.line = cursor.path().line_index;
         result
     }
+
+    fn rects_for_range(&self, range: impl RangeBounds<usize>) -> Vec<Rect> {
+        let len = self.text().len();
+        let start = match range.start_bound() {
+            Bound::Included(start) => *start,
+            Bound::Excluded(start) => *start + 1,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(end) => *end + 1,
+            Bound::Excluded(end) => *end,
+            Bound::Unbounded => len,
+        };
+        // A logical range may cover several visually disjoint spans of a
+        // line in bidirectional text.
+        Selection::new(start.min(len), end.min(len))
+            .geometry(&self.layout)
+            .into_iter()
+            .map(|rect| {
+                Rect::new(
+                    rect.x as f64,
+                    rect.y as f64,
+                    (rect.x + rect.width) as f64,
+                    (rect.y + rect.height) as f64,
+                )
+            })
+            .collect()
+    }
 }
 
 pub struct ParleyTextLayoutBuilder {
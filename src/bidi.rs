@@ -1,5 +1,6 @@
 //! Unicode bidirectional algorithm.
 
+use core::ops::Range;
 use swash::text::{BidiClass, BracketType, Codepoint as _};
 use BidiClass::*;
 
@@ -18,6 +19,9 @@ pub struct BidiResolver {
     runs: Vec<Run>,
     indices: Vec<usize>,
     flags: u16,
+    input: Vec<(char, BidiClass)>,
+    paragraph_levels: Vec<BidiLevel>,
+    paragraphs: Vec<(Range<usize>, BidiLevel)>,
 }
 
 impl BidiResolver {
@@ -33,6 +37,9 @@ impl BidiResolver {
             runs: Vec::new(),
             indices: Vec::new(),
             flags: 0,
+            input: Vec::new(),
+            paragraph_levels: Vec::new(),
+            paragraphs: Vec::new(),
         }
     }
 
@@ -41,6 +48,12 @@ impl BidiResolver {
         self.base_level
     }
 
+    /// Returns the character ranges and base levels of the paragraphs
+    /// resolved by the last call to `resolve_paragraphs`.
+    pub fn paragraphs(&self) -> &[(Range<usize>, BidiLevel)] {
+        &self.paragraphs
+    }
+
     /// Returns the sequence of bidi levels corresponding to all characters in the
     /// paragraph.
     pub fn levels(&self) -> &[BidiLevel] {
@@ -56,6 +69,53 @@ impl BidiResolver {
         self.bracket_pairs.clear();
         self.flags = 0;
         self.base_level = 0;
+        self.paragraphs.clear();
+    }
+
+    /// Resolves text that may contain multiple paragraphs. The text is
+    /// split after each paragraph separator (bidi class B) and every
+    /// paragraph is resolved independently with the specified base
+    /// direction, or a direction determined from its own content if `None`.
+    ///
+    /// The resulting levels cover the full text and the base level is that
+    /// of the first paragraph.
+    pub fn resolve_paragraphs(
+        &mut self,
+        chars: impl Iterator<Item = (char, BidiClass)>,
+        base_level: Option<u8>,
+    ) {
+        let mut input = core::mem::take(&mut self.input);
+        let mut levels = core::mem::take(&mut self.paragraph_levels);
+        let mut paragraphs = core::mem::take(&mut self.paragraphs);
+        input.clear();
+        input.extend(chars);
+        levels.clear();
+        paragraphs.clear();
+        let len = input.len();
+        let mut start = 0;
+        while start < len {
+            let mut end = start;
+            while end < len {
+                let (ch, ty) = input[end];
+                end += 1;
+                if ty == B {
+                    // Keep CRLF together.
+                    if ch == '\r' && input.get(end).map(|x| x.0) == Some('\n') {
+                        end += 1;
+                    }
+                    break;
+                }
+            }
+            self.resolve(input[start..end].iter().copied(), base_level);
+            levels.extend_from_slice(&self.levels);
+            paragraphs.push((start..end, self.base_level));
+            start = end;
+        }
+        core::mem::swap(&mut self.levels, &mut levels);
+        self.base_level = paragraphs.first().map(|p| p.1).unwrap_or(0);
+        self.paragraph_levels = levels;
+        self.paragraphs = paragraphs;
+        self.input = input;
     }
 
     /// Resolves a paragraph with the specified base direction and
@@ -844,3 +904,39 @@ impl BracketStack {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolve_paragraphs(text: &str, base_level: Option<u8>) -> BidiResolver {
+        let mut resolver = BidiResolver::new();
+        resolver.resolve_paragraphs(text.chars().map(|ch| (ch, ch.bidi_class())), base_level);
+        resolver
+    }
+
+    #[test]
+    fn paragraphs_have_their_own_levels() {
+        let resolver = resolve_paragraphs("abc\n\u{5d0}\u{5d1}\n1 \u{5d2}", None);
+        assert_eq!(resolver.paragraphs(), &[(0..4, 0), (4..7, 1), (7..10, 1)]);
+        assert_eq!(resolver.base_level(), 0);
+        assert_eq!(resolver.levels(), &[0, 0, 0, 0, 1, 1, 1, 2, 1, 1]);
+    }
+
+    #[test]
+    fn explicit_base_level_applies_to_every_paragraph() {
+        let resolver = resolve_paragraphs("abc\n\u{5d0}\u{5d1}", Some(1));
+        assert_eq!(resolver.paragraphs(), &[(0..4, 1), (4..6, 1)]);
+        assert_eq!(resolver.base_level(), 1);
+    }
+
+    #[test]
+    fn crlf_is_a_single_separator() {
+        let resolver = resolve_paragraphs("ab\r\n\u{5d0}\r\rc", None);
+        assert_eq!(
+            resolver.paragraphs(),
+            &[(0..4, 0), (4..6, 1), (6..7, 0), (7..8, 0)]
+        );
+        assert_eq!(resolver.levels().len(), 8);
+    }
+}
@@ -2,16 +2,22 @@
 
 use super::bidi;
 use super::font::FontContext;
-use super::layout::Layout;
+use super::hyphen::Hyphenator;
+use super::layout::data::{RubyData, TabStopsData};
+use super::layout::{Alignment, BaseDirection, InlineBox, Layout, WritingMode};
 use super::resolve::range::*;
+use super::resolve::tree::TreeStyleBuilder;
 use super::resolve::*;
 use super::style::*;
+use super::util::floor_char_boundary;
 
 use swash::shape::ShapeContext;
-use swash::text::cluster::CharInfo;
+use swash::text::cluster::{Boundary, CharInfo};
+use swash::text::Language;
 
 use std::cell::{RefCell, RefMut};
-use std::ops::{Deref, DerefMut, RangeBounds};
+use std::collections::HashMap;
+use std::ops::{Bound, Deref, DerefMut, Range, RangeBounds};
 use std::rc::Rc;
 
 /// Context for building a text layout.
@@ -20,8 +26,16 @@ pub struct LayoutContext<B: Brush = [u8; 4]> {
     rcx: ResolveContext,
     styles: Vec<RangedStyle<B>>,
     rsb: RangedStyleBuilder<B>,
+    tsb: TreeStyleBuilder<B>,
     info: Vec<(CharInfo, u16)>,
     scx: ShapeContext,
+    hyphenators: HashMap<String, Rc<Hyphenator>>,
+    hyphen_breaks: Vec<usize>,
+    inline_boxes: Vec<InlineBox>,
+    base_direction: BaseDirection,
+    writing_mode: WritingMode,
+    rubies: Vec<(Range<usize>, String)>,
+    needs_bidi: bool,
 }
 
 impl<B: Brush> LayoutContext<B> {
@@ -31,18 +45,56 @@ impl<B: Brush> LayoutContext<B> {
             rcx: ResolveContext::default(),
             styles: vec![],
             rsb: RangedStyleBuilder::default(),
+            tsb: TreeStyleBuilder::default(),
             info: vec![],
             scx: ShapeContext::default(),
+            hyphenators: HashMap::new(),
+            hyphen_breaks: vec![],
+            inline_boxes: vec![],
+            base_direction: BaseDirection::default(),
+            writing_mode: WritingMode::default(),
+            rubies: vec![],
+            needs_bidi: false,
         }
     }
 
+    /// Registers hyphenation patterns in TeX format for the specified
+    /// locale. Patterns are selected for text by the primary language
+    /// subtag of its locale and replace any that were previously
+    /// registered for the same language. Returns false if the locale or
+    /// the patterns could not be parsed.
+    pub fn register_hyphenation(&mut self, locale: &str, patterns: &[u8]) -> bool {
+        let language = match Language::parse(locale) {
+            Some(language) => language,
+            _ => return false,
+        };
+        match Hyphenator::parse(patterns) {
+            Some(hyphenator) => {
+                self.hyphenators
+                    .insert(language.language().to_string(), Rc::new(hyphenator));
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns true if hyphenation patterns are registered for the
+    /// language of the specified locale.
+    pub fn has_hyphenation(&self, locale: &str) -> bool {
+        Language::parse(locale)
+            .map(|language| self.hyphenators.contains_key(language.language()))
+            .unwrap_or(false)
+    }
+
     pub fn ranged_builder<'a>(
         &'a mut self,
         fcx: &'a mut FontContext,
         text: &'a str,
         scale: f32,
     ) -> RangedBuilder<B, &'a str> {
-        self.begin(text);
+        self.begin();
+        self.rsb.begin(text.len());
+        self.analyze_text(text);
         fcx.cache.reset();
         RangedBuilder {
             text,
@@ -52,24 +104,287 @@ impl<B: Brush> LayoutContext<B> {
         }
     }
 
-    fn begin(&mut self, text: &str) {
+    pub fn tree_builder<'a>(
+        &'a mut self,
+        fcx: &'a mut FontContext,
+        scale: f32,
+    ) -> TreeBuilder<'a, B> {
+        self.begin();
+        self.tsb.begin();
+        fcx.cache.reset();
+        TreeBuilder {
+            scale,
+            lcx: self,
+            fcx,
+        }
+    }
+
+    fn begin(&mut self) {
         self.rcx.clear();
         self.styles.clear();
-        self.rsb.begin(text.len());
         self.info.clear();
         self.bidi.clear();
+        self.inline_boxes.clear();
+        self.base_direction = BaseDirection::default();
+        self.writing_mode = WritingMode::default();
+        self.rubies.clear();
+        self.needs_bidi = false;
+    }
+
+    fn analyze_text(&mut self, text: &str) {
         let text = if text.is_empty() { " " } else { text };
         let mut a = swash::text::analyze(text.chars());
         for x in a.by_ref() {
             self.info.push((CharInfo::new(x.0, x.1), 0));
         }
-        if a.needs_bidi_resolution() {
-            self.bidi.resolve(
+        self.needs_bidi = a.needs_bidi_resolution();
+    }
+
+    /// Resolves bidi levels for the previously analyzed text.
+    fn resolve_bidi(&mut self, text: &str) {
+        let base_level = match self.base_direction {
+            BaseDirection::Auto => None,
+            BaseDirection::LeftToRight => Some(0),
+            BaseDirection::RightToLeft => Some(1),
+        };
+        if self.needs_bidi || base_level == Some(1) {
+            self.bidi.resolve_paragraphs(
                 text.chars()
                     .zip(self.info.iter().map(|info| info.0.bidi_class())),
-                Some(0),
+                base_level,
+            );
+        }
+    }
+
+    /// Shapes the text using the previously computed styles.
+    fn build_into(
+        &mut self,
+        fcx: &mut FontContext,
+        text: &str,
+        scale: f32,
+        layout: &mut Layout<B>,
+    ) {
+        layout.data.clear();
+        layout.data.scale = scale;
+        let mut text = text;
+        let is_empty = text.is_empty();
+        if is_empty {
+            // Force a layout to have at least one line.
+            text = " ";
+        }
+        self.resolve_bidi(text);
+        layout.data.writing_mode = self.writing_mode;
+        layout.data.has_bidi = !self.bidi.levels().is_empty();
+        layout.data.base_level = self.bidi.base_level();
+        // Convert paragraph character ranges to text ranges.
+        let mut offsets = text
+            .char_indices()
+            .map(|(offset, _)| offset)
+            .chain(Some(text.len()));
+        let mut next_char = 0;
+        let mut start = 0;
+        for (range, level) in self.bidi.paragraphs() {
+            let end = offsets.nth(range.end - next_char).unwrap_or(text.len());
+            next_char = range.end + 1;
+            layout.data.paragraphs.push((start..end, *level));
+            start = end;
+        }
+        layout.data.text_len = text.len();
+        let mut char_index = 0;
+        for (i, style) in self.styles.iter().enumerate() {
+            for _ in text[style.range.clone()].chars() {
+                self.info[char_index].1 = i as u16;
+                char_index += 1;
+            }
+        }
+        self.hyphenate(text);
+        if !self.rubies.is_empty() {
+            self.join_ruby_bases(text);
+        }
+        use super::layout::{Decoration, Style};
+        fn conv_deco<B: Brush>(
+            deco: &ResolvedDecoration<B>,
+            default_brush: &B,
+        ) -> Option<Decoration<B>> {
+            if deco.enabled {
+                Some(Decoration {
+                    brush: deco.brush.clone().unwrap_or_else(|| default_brush.clone()),
+                    offset: deco.offset,
+                    size: deco.size,
+                })
+            } else {
+                None
+            }
+        }
+        let rcx = &self.rcx;
+        layout.data.styles.extend(self.styles.iter().map(|s| {
+            let s = &s.style;
+            Style {
+                brush: s.brush.clone(),
+                underline: conv_deco(&s.underline, &s.brush),
+                strikethrough: conv_deco(&s.strikethrough, &s.brush),
+                line_height: s.line_height,
+                tab_stops: s.tab_stops.map(|stops| TabStopsData {
+                    stops: rcx.tab_stops(stops.stops).unwrap_or(&[]).to_vec(),
+                    interval: stops.interval,
+                }),
+            }
+        }));
+        // Stable sort to preserve the order of boxes at the same position.
+        self.inline_boxes.sort_by_key(|b| b.index);
+        super::shape::shape_text(
+            &self.rcx,
+            fcx,
+            &self.styles,
+            &self.info,
+            self.bidi.levels(),
+            &self.inline_boxes,
+            &self.hyphen_breaks,
+            &mut self.scx,
+            text,
+            layout,
+        );
+        layout.data.apply_hyphen_breaks(&self.hyphen_breaks);
+        layout.data.apply_tabs(text);
+        layout.data.finish();
+        if !self.rubies.is_empty() {
+            self.shape_rubies(fcx, text, layout);
+        }
+        if is_empty {
+            layout.data.text_len = 0;
+            // Inline boxes precede the placeholder space, so it is always
+            // the last cluster.
+            let run = layout
+                .data
+                .runs
+                .iter_mut()
+                .rev()
+                .find(|run| run.inline_box.is_none())
+                .unwrap();
+            run.cluster_range.end = run.cluster_range.start;
+            run.text_range.end = run.text_range.start;
+            layout.data.clusters.truncate(run.cluster_range.start);
+        }
+    }
+}
+
+impl<B: Brush> LayoutContext<B> {
+    /// Removes line break opportunities within the base text of ruby
+    /// annotations so that each base is kept on a single line.
+    fn join_ruby_bases(&mut self, text: &str) {
+        let rubies = &self.rubies;
+        let is_inside = |offset: usize| {
+            rubies
+                .iter()
+                .any(|(range, _)| offset > range.start && offset < range.end)
+        };
+        for ((offset, _), info) in text.char_indices().zip(self.info.iter_mut()) {
+            if info.0.boundary() == Boundary::Line && is_inside(offset) {
+                info.0 = CharInfo::new(info.0.properties(), Boundary::Word);
+            }
+        }
+        self.hyphen_breaks.retain(|offset| !is_inside(*offset));
+    }
+
+    /// Shapes the annotation text of each ruby with the style at the start
+    /// of its base, reduced in size, and in the writing mode of the layout.
+    fn shape_rubies(&mut self, fcx: &mut FontContext, text: &str, layout: &mut Layout<B>) {
+        let rubies = core::mem::take(&mut self.rubies);
+        let mut info = vec![];
+        for (range, annotation) in &rubies {
+            if range.end > text.len() || annotation.is_empty() {
+                continue;
+            }
+            let char_index = text[..range.start].chars().count();
+            let style_index = self.info.get(char_index).map(|i| i.1).unwrap_or(0) as usize;
+            let mut style = self.styles[style_index].clone();
+            style.style.font_size *= style.style.ruby_size;
+            style.range = 0..annotation.len();
+            info.clear();
+            info.extend(
+                swash::text::analyze(annotation.chars()).map(|x| (CharInfo::new(x.0, x.1), 0)),
             );
+            let mut ruby = Layout::new();
+            ruby.data.scale = layout.data.scale;
+            ruby.data.text_len = annotation.len();
+            ruby.data.writing_mode = layout.data.writing_mode;
+            ruby.data
+                .styles
+                .push(layout.data.styles[style_index].clone());
+            super::shape::shape_text(
+                &self.rcx,
+                fcx,
+                core::slice::from_ref(&style),
+                &info,
+                &[],
+                &[],
+                &[],
+                &mut self.scx,
+                annotation,
+                &mut ruby,
+            );
+            ruby.data.finish();
+            ruby.break_all_lines(None, Alignment::Start);
+            layout.data.rubies.push(RubyData {
+                text_range: range.clone(),
+                layout: ruby,
+            });
+        }
+        self.rubies = rubies;
+    }
+
+    /// Computes hyphenation opportunities for the text. Soft hyphens are
+    /// converted to regular break opportunities here when hyphenation is
+    /// disabled.
+    fn hyphenate(&mut self, text: &str) {
+        const SOFT_HYPHEN: char = '\u{ad}';
+        self.hyphen_breaks.clear();
+        let mut word_start: Option<(usize, usize)> = None;
+        let mut chars = text.char_indices().enumerate().peekable();
+        while let Some((index, (offset, ch))) = chars.next() {
+            let style = &self.styles[self.info[index].1 as usize].style;
+            if ch == SOFT_HYPHEN {
+                if let Some((next_index, (next_offset, _))) = chars.peek() {
+                    let info = &mut self.info[*next_index].0;
+                    if info.boundary() == Boundary::Line {
+                        if style.hyphens == Hyphens::None {
+                            *info = CharInfo::new(info.properties(), Boundary::Word);
+                        } else {
+                            self.hyphen_breaks.push(*next_offset);
+                        }
+                    }
+                }
+            }
+            if ch.is_alphabetic() {
+                if word_start.is_none() {
+                    word_start = Some((index, offset));
+                }
+                let at_end = chars
+                    .peek()
+                    .map(|(_, (_, next))| !next.is_alphabetic())
+                    .unwrap_or(true);
+                if !at_end {
+                    continue;
+                }
+                let (start_index, start) = word_start.take().unwrap();
+                let style = &self.styles[self.info[start_index].1 as usize].style;
+                if style.hyphens != Hyphens::Auto {
+                    continue;
+                }
+                let hyphenators = &self.hyphenators;
+                let hyphenator = style
+                    .locale
+                    .as_ref()
+                    .and_then(|locale| hyphenators.get(locale.language()));
+                if let Some(hyphenator) = hyphenator {
+                    let end = offset + ch.len_utf8();
+                    let breaks = &mut self.hyphen_breaks;
+                    hyphenator.hyphenate(&text[start..end], |pos| breaks.push(start + pos));
+                }
+            }
         }
+        self.hyphen_breaks.sort_unstable();
+        self.hyphen_breaks.dedup();
     }
 }
 
@@ -81,8 +396,11 @@ impl<B: Brush> Default for LayoutContext<B> {
 
 impl<B: Brush> Clone for LayoutContext<B> {
     fn clone(&self) -> Self {
-        // None of the internal state is visible so just return a new instance.
-        Self::new()
+        // Hyphenation patterns are the only visible state so just carry
+        // those over to a new instance.
+        let mut lcx = Self::new();
+        lcx.hyphenators = self.hyphenators.clone();
+        lcx
     }
 }
 
@@ -105,7 +423,12 @@ impl<B: Brush> RcLayoutContext<B> {
         text: T,
         scale: f32,
     ) -> RangedBuilder<'static, B, T> {
-        self.lcx.borrow_mut().begin(text.as_str());
+        {
+            let mut lcx = self.lcx.borrow_mut();
+            lcx.begin();
+            lcx.rsb.begin(text.as_str().len());
+            lcx.analyze_text(text.as_str());
+        }
         RangedBuilder {
             text,
             scale,
@@ -138,71 +461,75 @@ impl<'a, B: Brush, T: TextSource> RangedBuilder<'a, B, T> {
         lcx.rsb.push(resolved, range);
     }
 
+    /// Sets the base direction of the paragraph.
+    pub fn set_base_direction(&mut self, direction: BaseDirection) {
+        self.lcx.borrow_mut().base_direction = direction;
+    }
+
+    /// Sets the writing mode of the layout.
+    pub fn set_writing_mode(&mut self, writing_mode: WritingMode) {
+        self.lcx.borrow_mut().writing_mode = writing_mode;
+    }
+
+    /// Pushes a ruby annotation for the specified range of base text. The
+    /// annotation is set over the base, which is above it in horizontal
+    /// text and to its right in vertical text, at the font size of the start
+    /// of the base multiplied by [`StyleProperty::RubySize`]. The base is
+    /// never broken across lines.
+    ///
+    /// Offsets inside a character are moved back to the start of that
+    /// character. Empty ranges and ranges past the end of the text are
+    /// ignored.
+    pub fn push_ruby(&mut self, range: impl RangeBounds<usize>, annotation: &str) {
+        let text = self.text.as_str();
+        let len = text.len();
+        let start = match range.start_bound() {
+            Bound::Included(start) => *start,
+            Bound::Excluded(start) => *start + 1,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(end) => *end + 1,
+            Bound::Excluded(end) => *end,
+            Bound::Unbounded => len,
+        };
+        if end > len {
+            return;
+        }
+        let start = floor_char_boundary(text, start);
+        let end = floor_char_boundary(text, end);
+        if start < end {
+            self.lcx
+                .borrow_mut()
+                .rubies
+                .push((start..end, annotation.to_string()));
+        }
+    }
+
+    /// Pushes an inline box. The dimensions of the box are multiplied by
+    /// the scale factor of the builder.
+    ///
+    /// An index past the end of the text is clamped to the length of the
+    /// text and an index inside a character is moved back to the start of
+    /// that character.
+    pub fn push_inline_box(&mut self, inline_box: InlineBox) {
+        let index = floor_char_boundary(self.text.as_str(), inline_box.index);
+        let scale = self.scale;
+        self.lcx.borrow_mut().inline_boxes.push(InlineBox {
+            index,
+            width: inline_box.width * scale,
+            height: inline_box.height * scale,
+            baseline: inline_box.baseline * scale,
+            ..inline_box
+        });
+    }
+
     pub fn build_into(&mut self, layout: &mut Layout<B>) {
-        layout.data.clear();
-        layout.data.scale = self.scale;
         let mut lcx = self.lcx.borrow_mut();
         let lcx = &mut *lcx;
-        let mut text = self.text.as_str();
-        let is_empty = text.is_empty();
-        if is_empty {
-            // Force a layout to have at least one line.
-            text = " ";
-        }
-        layout.data.has_bidi = !lcx.bidi.levels().is_empty();
-        layout.data.base_level = lcx.bidi.base_level();
-        layout.data.text_len = text.len();
         let mut fcx = self.fcx.borrow_mut();
         lcx.rsb.finish(&mut lcx.styles);
-        let mut char_index = 0;
-        for (i, style) in lcx.styles.iter().enumerate() {
-            for _ in text[style.range.clone()].chars() {
-                lcx.info[char_index].1 = i as u16;
-                char_index += 1;
-            }
-        }
-        use super::layout::{Decoration, Style};
-        fn conv_deco<B: Brush>(
-            deco: &ResolvedDecoration<B>,
-            default_brush: &B,
-        ) -> Option<Decoration<B>> {
-            if deco.enabled {
-                Some(Decoration {
-                    brush: deco.brush.clone().unwrap_or_else(|| default_brush.clone()),
-                    offset: deco.offset,
-                    size: deco.size,
-                })
-            } else {
-                None
-            }
-        }
-        layout.data.styles.extend(lcx.styles.iter().map(|s| {
-            let s = &s.style;
-            Style {
-                brush: s.brush.clone(),
-                underline: conv_deco(&s.underline, &s.brush),
-                strikethrough: conv_deco(&s.strikethrough, &s.brush),
-                line_height: s.line_height,
-            }
-        }));
-        super::shape::shape_text(
-            &lcx.rcx,
-            &mut fcx,
-            &lcx.styles,
-            &lcx.info,
-            lcx.bidi.levels(),
-            &mut lcx.scx,
-            text,
-            layout,
-        );
-        layout.data.finish();
-        if is_empty {
-            layout.data.text_len = 0;
-            let run = &mut layout.data.runs[0];
-            run.cluster_range.end = 0;
-            run.text_range.end = 0;
-            layout.data.clusters.clear();
-        }
+        lcx.build_into(&mut fcx, self.text.as_str(), self.scale, layout);
     }
 
     pub fn build(&mut self) -> Layout<B> {
@@ -212,6 +539,87 @@ impl<'a, B: Brush, T: TextSource> RangedBuilder<'a, B, T> {
     }
 }
 
+/// Builder for constructing a text layout with a tree of attributes.
+pub struct TreeBuilder<'a, B: Brush> {
+    scale: f32,
+    lcx: &'a mut LayoutContext<B>,
+    fcx: &'a mut FontContext,
+}
+
+impl<'a, B: Brush> TreeBuilder<'a, B> {
+    /// Pushes a property that applies to the root of the tree. This should
+    /// be called before pushing any spans or text.
+    pub fn push_default(&mut self, property: &StyleProperty<B>) {
+        let resolved = self.lcx.rcx.resolve(self.fcx, property, self.scale);
+        self.lcx.tsb.push_default(resolved);
+    }
+
+    /// Opens a new span that inherits the style of the current span with
+    /// the specified properties applied.
+    pub fn push_style_span(&mut self, properties: &[StyleProperty<B>]) {
+        let mut style = self.lcx.tsb.current_style();
+        for property in properties {
+            style.apply(self.lcx.rcx.resolve(self.fcx, property, self.scale));
+        }
+        self.lcx.tsb.push_style_span(style);
+    }
+
+    /// Closes the current span, restoring the style of its parent.
+    pub fn pop_style_span(&mut self) {
+        self.lcx.tsb.pop_style_span();
+    }
+
+    /// Appends text with the style of the current span.
+    pub fn push_text(&mut self, text: &str) {
+        self.lcx.tsb.push_text(text);
+    }
+
+    /// Sets the base direction of the paragraph.
+    pub fn set_base_direction(&mut self, direction: BaseDirection) {
+        self.lcx.base_direction = direction;
+    }
+
+    /// Sets the writing mode of the layout.
+    pub fn set_writing_mode(&mut self, writing_mode: WritingMode) {
+        self.lcx.writing_mode = writing_mode;
+    }
+
+    /// Pushes an inline box at the end of the current text. The index of
+    /// the box is ignored and the dimensions are multiplied by the scale
+    /// factor of the builder.
+    pub fn push_inline_box(&mut self, inline_box: InlineBox) {
+        let index = self.lcx.tsb.text_len();
+        let scale = self.scale;
+        self.lcx.inline_boxes.push(InlineBox {
+            index,
+            width: inline_box.width * scale,
+            height: inline_box.height * scale,
+            baseline: inline_box.baseline * scale,
+            ..inline_box
+        });
+    }
+
+    /// Builds the layout into the specified target, returning the
+    /// accumulated text. This may be called more than once, which builds
+    /// the same layout each time.
+    pub fn build_into(&mut self, layout: &mut Layout<B>) -> String {
+        let lcx = &mut *self.lcx;
+        lcx.styles.clear();
+        lcx.info.clear();
+        let text = lcx.tsb.finish(&mut lcx.styles);
+        lcx.analyze_text(&text);
+        lcx.build_into(self.fcx, &text, self.scale, layout);
+        text
+    }
+
+    /// Builds the layout, returning it along with the accumulated text.
+    pub fn build(mut self) -> (Layout<B>, String) {
+        let mut layout = Layout::default();
+        let text = self.build_into(&mut layout);
+        (layout, text)
+    }
+}
+
 #[doc(hidden)]
 pub trait TextSource {
     fn as_str(&self) -> &str;
@@ -261,3 +669,135 @@ impl<'a, T> DerefMut for BorrowMut<'a, T> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn tree_builder_builds_repeatedly() {
+        let mut fcx = testing::font_context();
+        let mut lcx = LayoutContext::<[u8; 4]>::new();
+        let mut builder = lcx.tree_builder(&mut fcx, 1.);
+        builder.push_default(&testing::FONT_STACK);
+        builder.push_text("hello ");
+        builder.push_style_span(&[StyleProperty::FontSize(24.)]);
+        builder.push_text("world");
+        builder.pop_style_span();
+        let mut first = Layout::new();
+        let mut second = Layout::new();
+        let text = builder.build_into(&mut first);
+        assert_eq!(builder.build_into(&mut second), text);
+        assert_eq!(text, "hello world");
+        assert_eq!(first.styles().len(), 2);
+        assert_eq!(second.styles().len(), 2);
+        let clusters =
+            |layout: &Layout<[u8; 4]>| layout.runs().map(|run| run.len()).collect::<Vec<_>>();
+        assert_eq!(clusters(&first), clusters(&second));
+        assert_eq!(clusters(&first).iter().sum::<usize>(), text.chars().count());
+    }
+
+    #[test]
+    fn inline_box_index_is_snapped() {
+        let layout = testing::layout_with("a\u{e9}b", |builder| {
+            for index in [2, 10].iter() {
+                builder.push_inline_box(InlineBox {
+                    index: *index,
+                    width: 10.,
+                    height: 10.,
+                    ..Default::default()
+                });
+            }
+        });
+        let indices = layout
+            .runs()
+            .filter_map(|run| run.inline_box())
+            .map(|inline_box| inline_box.index)
+            .collect::<Vec<_>>();
+        assert_eq!(indices, vec![1, 4]);
+    }
+
+    #[test]
+    fn inline_box_after_newline_begins_line() {
+        let mut layout = testing::layout_with("a\nb", |builder| {
+            builder.push_inline_box(InlineBox {
+                index: 2,
+                width: 10.,
+                height: 10.,
+                ..Default::default()
+            });
+        });
+        let run = layout
+            .runs()
+            .find(|run| run.inline_box().is_some())
+            .unwrap();
+        assert!(run.get(0).unwrap().is_hard_line_break());
+        layout.break_all_lines(None, Alignment::Start);
+        assert_eq!(layout.len(), 2);
+        let line = layout.get(1).unwrap();
+        assert!(line.runs().next().unwrap().inline_box().is_some());
+    }
+
+    #[test]
+    fn ruby_offsets_are_snapped() {
+        let layout = testing::layout_with("a\u{e9}b", |builder| {
+            builder.push_ruby(2..4, "x");
+            builder.push_ruby(0..2, "y");
+        });
+        let ranges = layout
+            .data
+            .rubies
+            .iter()
+            .map(|ruby| ruby.text_range.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(ranges, vec![1..4, 0..1]);
+    }
+
+    #[test]
+    fn ruby_size_scales_annotations() {
+        let layout = testing::layout_with("ab", |builder| {
+            builder.push_default(&StyleProperty::RubySize(0.25));
+            builder.push_ruby(0..2, "x");
+        });
+        let ruby = &layout.data.rubies[0].layout;
+        assert_eq!(ruby.data.runs[0].font_size, testing::FONT_SIZE * 0.25);
+    }
+
+    #[test]
+    fn rubies_are_set_over_their_bases() {
+        for &mode in [
+            WritingMode::HorizontalTb,
+            WritingMode::VerticalRl,
+            WritingMode::VerticalLr,
+        ]
+        .iter()
+        {
+            let mut layout = testing::layout_with("ab", |builder| {
+                builder.set_writing_mode(mode);
+                builder.push_ruby(0..2, "xy");
+            });
+            layout.break_all_lines(None, Alignment::Start);
+            let line = layout.get(0).unwrap();
+            let metrics = *line.metrics();
+            let annotation = line.ruby_annotations().next().unwrap();
+            let ruby = annotation.layout();
+            assert_eq!(ruby.writing_mode(), mode);
+            match mode {
+                WritingMode::HorizontalTb => {
+                    assert!(annotation.y() + ruby.height() <= metrics.baseline);
+                    assert!(annotation.y() >= metrics.baseline - metrics.ascent);
+                }
+                _ => {
+                    let center = if mode == WritingMode::VerticalRl {
+                        layout.width() - metrics.baseline
+                    } else {
+                        metrics.baseline
+                    };
+                    assert!(annotation.x() > center);
+                    assert!(annotation.x() + ruby.width() <= layout.width());
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,681 @@
+//! Text editing with incremental relayout.
+
+use super::context::LayoutContext;
+use super::font::FontContext;
+use super::layout::{Affinity, Alignment, BaseDirection, Cursor, Layout, Rect, Selection};
+use super::style::{Brush, StyleProperty};
+
+use core::ops::Range;
+use swash::text::{BidiClass, Codepoint as _};
+
+/// Editable rich text that maintains a layout for each paragraph.
+///
+/// The text is split into paragraphs after each paragraph separator, such
+/// as a newline or U+2029, in the same way that bidi paragraphs of a layout
+/// are determined. Editing operations only
+/// mark the paragraphs they touch as dirty, and [`relayout`](Self::relayout)
+/// re-analyzes, re-shapes and re-breaks just those paragraphs. Ranged
+/// styles are shifted to follow the text as it is edited.
+pub struct RichEditor<B: Brush> {
+    text: String,
+    defaults: Vec<StyleProperty<'static, B>>,
+    styles: Vec<(StyleProperty<'static, B>, Range<usize>)>,
+    paragraphs: Vec<Paragraph<B>>,
+    selection: Selection,
+    scale: f32,
+    max_advance: Option<f32>,
+    alignment: Alignment,
+    base_direction: BaseDirection,
+    width: f32,
+    height: f32,
+}
+
+impl<B: Brush> RichEditor<B> {
+    /// Creates a new editor with the specified initial text.
+    pub fn new(text: &str) -> Self {
+        let mut editor = Self {
+            text: text.to_string(),
+            defaults: vec![],
+            styles: vec![],
+            paragraphs: vec![],
+            selection: Selection::collapsed(text.len(), Affinity::Downstream),
+            scale: 1.,
+            max_advance: None,
+            alignment: Alignment::Start,
+            base_direction: BaseDirection::Auto,
+            width: 0.,
+            height: 0.,
+        };
+        editor.paragraphs = split_paragraphs(&editor.text, 0..text.len());
+        editor
+    }
+
+    /// Returns the text.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Replaces the full text, removing all ranged styles.
+    pub fn set_text(&mut self, text: &str) {
+        self.text.clear();
+        self.text.push_str(text);
+        self.styles.clear();
+        self.paragraphs = split_paragraphs(&self.text, 0..text.len());
+        self.selection = Selection::collapsed(text.len(), Affinity::Downstream);
+    }
+
+    /// Returns the current selection.
+    pub fn selection(&self) -> Selection {
+        self.selection
+    }
+
+    /// Sets the current selection. Positions are clamped to the text.
+    pub fn set_selection(&mut self, selection: Selection) {
+        let len = self.text.len();
+        self.selection = Selection::with_affinity(
+            self.floor_boundary(selection.anchor().min(len)),
+            selection.anchor_affinity(),
+            self.floor_boundary(selection.focus().min(len)),
+            selection.focus_affinity(),
+        );
+    }
+
+    /// Selects the full text.
+    pub fn select_all(&mut self) {
+        self.selection = Selection::new(0, self.text.len());
+    }
+
+    /// Sets the scale factor used when building paragraph layouts.
+    pub fn set_scale(&mut self, scale: f32) {
+        if scale != self.scale {
+            self.scale = scale;
+            self.invalidate();
+        }
+    }
+
+    /// Sets the maximum advance and alignment for line breaking.
+    pub fn set_width(&mut self, max_advance: Option<f32>, alignment: Alignment) {
+        if max_advance != self.max_advance || alignment != self.alignment {
+            self.max_advance = max_advance;
+            self.alignment = alignment;
+            self.invalidate();
+        }
+    }
+
+    /// Sets the base direction for all paragraphs.
+    pub fn set_base_direction(&mut self, direction: BaseDirection) {
+        if direction != self.base_direction {
+            self.base_direction = direction;
+            self.invalidate();
+        }
+    }
+
+    /// Pushes a property that applies to the full text.
+    pub fn push_default(&mut self, property: StyleProperty<'static, B>) {
+        self.defaults.push(property);
+        self.invalidate();
+    }
+
+    /// Pushes a property that applies to the specified range of text.
+    pub fn push(&mut self, property: StyleProperty<'static, B>, range: Range<usize>) {
+        let range = range.start.min(self.text.len())..range.end.min(self.text.len());
+        if range.is_empty() {
+            return;
+        }
+        self.invalidate_range(range.clone());
+        self.styles.push((property, range));
+    }
+
+    /// Removes all ranged properties.
+    pub fn clear_styles(&mut self) {
+        if !self.styles.is_empty() {
+            self.styles.clear();
+            self.invalidate();
+        }
+    }
+
+    /// Replaces the current selection with the specified text and places
+    /// a collapsed selection after it.
+    pub fn insert(&mut self, text: &str) {
+        let range = self.selection.text_range();
+        self.replace(range, text);
+    }
+
+    /// Deletes the current selection or the character before a collapsed
+    /// selection.
+    pub fn delete_backward(&mut self) {
+        let range = self.selection.text_range();
+        if !range.is_empty() {
+            self.replace(range, "");
+        } else if let Some((offset, _)) = self.text[..range.start].char_indices().next_back() {
+            self.replace(offset..range.start, "");
+        }
+    }
+
+    /// Deletes the current selection or the character after a collapsed
+    /// selection.
+    pub fn delete_forward(&mut self) {
+        let range = self.selection.text_range();
+        if !range.is_empty() {
+            self.replace(range, "");
+        } else if let Some(ch) = self.text[range.start..].chars().next() {
+            self.replace(range.start..range.start + ch.len_utf8(), "");
+        }
+    }
+
+    /// Replaces the specified range of text, shifting ranged styles and
+    /// marking the affected paragraphs for relayout. The selection is
+    /// collapsed after the inserted text.
+    pub fn replace(&mut self, range: Range<usize>, text: &str) {
+        let len = self.text.len();
+        let start = self.floor_boundary(range.start.min(len));
+        let end = self.floor_boundary(range.end.min(len)).max(start);
+        let range = start..end;
+        let inserted = text.len();
+        // Map the style ranges to the edited text.
+        let map_start = |pos: usize| {
+            if pos < range.start || (pos == range.start && !range.is_empty()) {
+                pos
+            } else if pos >= range.end {
+                pos - range.len() + inserted
+            } else {
+                range.start + inserted
+            }
+        };
+        let map_end = |pos: usize| {
+            if pos <= range.start && !(pos == range.start && range.is_empty()) {
+                pos
+            } else if pos >= range.end {
+                pos - range.len() + inserted
+            } else {
+                range.start + inserted
+            }
+        };
+        for style in &mut self.styles {
+            style.1 = map_start(style.1.start)..map_end(style.1.end);
+        }
+        self.styles.retain(|style| !style.1.is_empty());
+        self.text.replace_range(range.clone(), text);
+        // Re-split the touched paragraphs.
+        let mut first = self.paragraph_index(range.start);
+        let last = self.paragraph_index(range.end);
+        // A line feed inserted after a carriage return joins the previous
+        // separator.
+        if first > 0 && self.text[..self.paragraphs[first].range.start].ends_with('\r') {
+            first -= 1;
+        }
+        let region_start = self.paragraphs[first].range.start;
+        let region_end = self.paragraphs[last].range.end - range.len() + inserted;
+        let replacement = split_paragraphs(&self.text, region_start..region_end);
+        let count = replacement.len();
+        self.paragraphs.splice(first..=last, replacement);
+        for paragraph in &mut self.paragraphs[first + count..] {
+            paragraph.range.start = paragraph.range.start - range.len() + inserted;
+            paragraph.range.end = paragraph.range.end - range.len() + inserted;
+        }
+        self.selection = Selection::collapsed(range.start + inserted, Affinity::Downstream);
+    }
+
+    /// Returns true if any paragraph needs to be laid out.
+    pub fn needs_relayout(&self) -> bool {
+        self.paragraphs.iter().any(|p| p.dirty)
+    }
+
+    /// Rebuilds the layouts of all paragraphs that were affected by
+    /// changes since the previous call.
+    pub fn relayout(&mut self, lcx: &mut LayoutContext<B>, fcx: &mut FontContext) {
+        let Self {
+            text,
+            defaults,
+            styles,
+            paragraphs,
+            ..
+        } = self;
+        for paragraph in paragraphs.iter_mut().filter(|p| p.dirty) {
+            let range = paragraph.range.clone();
+            let mut builder = lcx.ranged_builder(fcx, &text[range.clone()], self.scale);
+            for property in defaults.iter() {
+                builder.push_default(property);
+            }
+            for (property, style_range) in styles.iter() {
+                let start = style_range.start.max(range.start);
+                let end = style_range.end.min(range.end);
+                if start < end {
+                    builder.push(property, start - range.start..end - range.start);
+                }
+            }
+            builder.set_base_direction(self.base_direction);
+            builder.build_into(&mut paragraph.layout);
+            paragraph
+                .layout
+                .break_all_lines(self.max_advance, self.alignment);
+            paragraph.dirty = false;
+        }
+        let mut y = 0.;
+        let mut width = 0f32;
+        for paragraph in paragraphs.iter_mut() {
+            paragraph.offset = y;
+            y += paragraph.layout.height();
+            width = width.max(paragraph.layout.width());
+        }
+        self.width = width;
+        self.height = y;
+    }
+
+    /// Returns the paragraphs of the text.
+    pub fn paragraphs(&self) -> &[Paragraph<B>] {
+        &self.paragraphs
+    }
+
+    /// Returns the width of the widest paragraph as of the last relayout.
+    pub fn width(&self) -> f32 {
+        self.width
+    }
+
+    /// Returns the total height of all paragraphs as of the last relayout.
+    pub fn height(&self) -> f32 {
+        self.height
+    }
+
+    /// Sets a collapsed selection at the specified point.
+    pub fn select_point(&mut self, x: f32, y: f32) {
+        let (position, affinity) = self.hit_test(x, y);
+        self.selection = Selection::collapsed(position, affinity);
+    }
+
+    /// Moves the focus of the selection to the specified point.
+    pub fn extend_selection_to_point(&mut self, x: f32, y: f32) {
+        let (position, affinity) = self.hit_test(x, y);
+        self.selection = self.selection.extend_to(position, affinity);
+    }
+
+    /// Invokes the specified closure with each visual rectangle covered by
+    /// the selection.
+    pub fn selection_geometry_with(&self, mut f: impl FnMut(Rect)) {
+        let range = self.selection.text_range();
+        if range.is_empty() {
+            return;
+        }
+        for paragraph in &self.paragraphs {
+            let start = range.start.max(paragraph.range.start);
+            let end = range.end.min(paragraph.range.end);
+            if start >= end {
+                continue;
+            }
+            let base = paragraph.range.start;
+            let offset = paragraph.offset;
+            Selection::new(start - base, end - base).geometry_with(&paragraph.layout, |rect| {
+                f(Rect {
+                    y: rect.y + offset,
+                    ..rect
+                })
+            });
+        }
+    }
+
+    /// Returns the visual rectangles covered by the selection.
+    pub fn selection_geometry(&self) -> Vec<Rect> {
+        let mut rects = vec![];
+        self.selection_geometry_with(|rect| rects.push(rect));
+        rects
+    }
+
+    /// Returns the rectangle of the caret at the focus of the selection
+    /// with the specified width.
+    pub fn caret_geometry(&self, width: f32) -> Option<Rect> {
+        let focus = self.selection.focus();
+        let mut index = self.paragraph_index(focus);
+        let mut local = focus - self.paragraphs[index].range.start;
+        let is_leading = self.selection.focus_affinity() == Affinity::Downstream;
+        // An upstream caret at the start of a paragraph belongs to the end
+        // of the previous paragraph.
+        if !is_leading && local == 0 && index > 0 {
+            index -= 1;
+            local = self.paragraphs[index].range.len();
+        }
+        let paragraph = &self.paragraphs[index];
+        let cursor = if is_leading || local == 0 {
+            Cursor::from_position(&paragraph.layout, local, true)
+        } else {
+            Cursor::from_position(&paragraph.layout, local - 1, false)
+        };
+        let line = cursor.path().line(&paragraph.layout)?;
+        let metrics = line.metrics();
+        Some(Rect {
+            x: cursor.offset(),
+            y: paragraph.offset + metrics.baseline - metrics.ascent - metrics.leading * 0.5,
+            width,
+            height: metrics.size(),
+        })
+    }
+
+    fn hit_test(&self, x: f32, y: f32) -> (usize, Affinity) {
+        let paragraph = self
+            .paragraphs
+            .iter()
+            .find(|p| y < p.offset + p.layout.height())
+            .unwrap_or_else(|| self.paragraphs.last().unwrap());
+        let cursor = Cursor::from_point(&paragraph.layout, x, y - paragraph.offset);
+        let affinity = if cursor.is_leading() {
+            Affinity::Downstream
+        } else {
+            Affinity::Upstream
+        };
+        let position = (paragraph.range.start + cursor.text_position()).min(paragraph.range.end);
+        (position, affinity)
+    }
+
+    /// Returns the index of the paragraph containing the specified
+    /// position.
+    fn paragraph_index(&self, position: usize) -> usize {
+        self.paragraphs
+            .iter()
+            .position(|p| position < p.range.end)
+            .unwrap_or(self.paragraphs.len() - 1)
+    }
+
+    fn floor_boundary(&self, mut position: usize) -> usize {
+        while !self.text.is_char_boundary(position) {
+            position -= 1;
+        }
+        position
+    }
+
+    fn invalidate(&mut self) {
+        for paragraph in &mut self.paragraphs {
+            paragraph.dirty = true;
+        }
+    }
+
+    fn invalidate_range(&mut self, range: Range<usize>) {
+        for paragraph in &mut self.paragraphs {
+            if paragraph.range.start < range.end && paragraph.range.end > range.start {
+                paragraph.dirty = true;
+            }
+        }
+    }
+}
+
+/// Editable plain text that maintains a layout for each paragraph.
+///
+/// This is a [`RichEditor`] without ranged styles: properties pushed with
+/// [`push_default`](Self::push_default) apply to the full text.
+pub struct PlainEditor<B: Brush> {
+    editor: RichEditor<B>,
+}
+
+impl<B: Brush> PlainEditor<B> {
+    /// Creates a new editor with the specified initial text.
+    pub fn new(text: &str) -> Self {
+        Self {
+            editor: RichEditor::new(text),
+        }
+    }
+
+    /// Returns the text.
+    pub fn text(&self) -> &str {
+        self.editor.text()
+    }
+
+    /// Replaces the full text.
+    pub fn set_text(&mut self, text: &str) {
+        self.editor.set_text(text);
+    }
+
+    /// Returns the current selection.
+    pub fn selection(&self) -> Selection {
+        self.editor.selection()
+    }
+
+    /// Sets the current selection. Positions are clamped to the text.
+    pub fn set_selection(&mut self, selection: Selection) {
+        self.editor.set_selection(selection);
+    }
+
+    /// Selects the full text.
+    pub fn select_all(&mut self) {
+        self.editor.select_all();
+    }
+
+    /// Sets the scale factor used when building paragraph layouts.
+    pub fn set_scale(&mut self, scale: f32) {
+        self.editor.set_scale(scale);
+    }
+
+    /// Sets the maximum advance and alignment for line breaking.
+    pub fn set_width(&mut self, max_advance: Option<f32>, alignment: Alignment) {
+        self.editor.set_width(max_advance, alignment);
+    }
+
+    /// Sets the base direction for all paragraphs.
+    pub fn set_base_direction(&mut self, direction: BaseDirection) {
+        self.editor.set_base_direction(direction);
+    }
+
+    /// Pushes a property that applies to the full text.
+    pub fn push_default(&mut self, property: StyleProperty<'static, B>) {
+        self.editor.push_default(property);
+    }
+
+    /// Replaces the current selection with the specified text and places
+    /// a collapsed selection after it.
+    pub fn insert(&mut self, text: &str) {
+        self.editor.insert(text);
+    }
+
+    /// Deletes the current selection or the character before a collapsed
+    /// selection.
+    pub fn delete_backward(&mut self) {
+        self.editor.delete_backward();
+    }
+
+    /// Deletes the current selection or the character after a collapsed
+    /// selection.
+    pub fn delete_forward(&mut self) {
+        self.editor.delete_forward();
+    }
+
+    /// Replaces the specified range of text and marks the affected
+    /// paragraphs for relayout. The selection is collapsed after the
+    /// inserted text.
+    pub fn replace(&mut self, range: Range<usize>, text: &str) {
+        self.editor.replace(range, text);
+    }
+
+    /// Returns true if any paragraph needs to be laid out.
+    pub fn needs_relayout(&self) -> bool {
+        self.editor.needs_relayout()
+    }
+
+    /// Rebuilds the layouts of all paragraphs that were affected by
+    /// changes since the previous call.
+    pub fn relayout(&mut self, lcx: &mut LayoutContext<B>, fcx: &mut FontContext) {
+        self.editor.relayout(lcx, fcx);
+    }
+
+    /// Returns the paragraphs of the text.
+    pub fn paragraphs(&self) -> &[Paragraph<B>] {
+        self.editor.paragraphs()
+    }
+
+    /// Returns the width of the widest paragraph as of the last relayout.
+    pub fn width(&self) -> f32 {
+        self.editor.width()
+    }
+
+    /// Returns the total height of all paragraphs as of the last relayout.
+    pub fn height(&self) -> f32 {
+        self.editor.height()
+    }
+
+    /// Sets a collapsed selection at the specified point.
+    pub fn select_point(&mut self, x: f32, y: f32) {
+        self.editor.select_point(x, y);
+    }
+
+    /// Moves the focus of the selection to the specified point.
+    pub fn extend_selection_to_point(&mut self, x: f32, y: f32) {
+        self.editor.extend_selection_to_point(x, y);
+    }
+
+    /// Invokes the specified closure with each visual rectangle covered by
+    /// the selection.
+    pub fn selection_geometry_with(&self, f: impl FnMut(Rect)) {
+        self.editor.selection_geometry_with(f);
+    }
+
+    /// Returns the visual rectangles covered by the selection.
+    pub fn selection_geometry(&self) -> Vec<Rect> {
+        self.editor.selection_geometry()
+    }
+
+    /// Returns the rectangle of the caret at the focus of the selection
+    /// with the specified width.
+    pub fn caret_geometry(&self, width: f32) -> Option<Rect> {
+        self.editor.caret_geometry(width)
+    }
+}
+
+/// Paragraph of an editor with its layout.
+pub struct Paragraph<B: Brush> {
+    range: Range<usize>,
+    layout: Layout<B>,
+    offset: f32,
+    dirty: bool,
+}
+
+impl<B: Brush> Paragraph<B> {
+    /// Returns the range of text for the paragraph, including the trailing
+    /// paragraph separator.
+    pub fn text_range(&self) -> Range<usize> {
+        self.range.clone()
+    }
+
+    /// Returns the layout of the paragraph.
+    pub fn layout(&self) -> &Layout<B> {
+        &self.layout
+    }
+
+    /// Returns the vertical offset of the paragraph.
+    pub fn offset(&self) -> f32 {
+        self.offset
+    }
+}
+
+/// Splits the specified range of text into dirty paragraphs after each
+/// paragraph separator (bidi class B), keeping CRLF together, as done when
+/// resolving bidi paragraphs of a layout. A trailing empty paragraph is
+/// produced when the range ends the text after a separator.
+fn split_paragraphs<B: Brush>(text: &str, range: Range<usize>) -> Vec<Paragraph<B>> {
+    let mut paragraphs = vec![];
+    let mut start = range.start;
+    let paragraph = |range: Range<usize>| Paragraph {
+        range,
+        layout: Layout::new(),
+        offset: 0.,
+        dirty: true,
+    };
+    let mut chars = text[range.clone()].char_indices().peekable();
+    while let Some((offset, ch)) = chars.next() {
+        if ch.bidi_class() != BidiClass::B {
+            continue;
+        }
+        let mut end = range.start + offset + ch.len_utf8();
+        if ch == '\r' && chars.peek().map(|x| x.1) == Some('\n') {
+            chars.next();
+            end += 1;
+        }
+        paragraphs.push(paragraph(start..end));
+        start = end;
+    }
+    if start < range.end || range.end == text.len() {
+        paragraphs.push(paragraph(start..range.end));
+    }
+    paragraphs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    fn editor(text: &str) -> PlainEditor<[u8; 4]> {
+        let mut editor = PlainEditor::new(text);
+        editor.push_default(testing::FONT_STACK);
+        editor.push_default(StyleProperty::FontSize(testing::FONT_SIZE));
+        editor
+    }
+
+    fn ranges(editor: &PlainEditor<[u8; 4]>) -> Vec<Range<usize>> {
+        editor.paragraphs().iter().map(|p| p.text_range()).collect()
+    }
+
+    #[test]
+    fn paragraphs_split_at_separators() {
+        let editor = editor("a\nb\u{2029}c\r\nd");
+        assert_eq!(ranges(&editor), [0..2, 2..6, 6..9, 9..10]);
+        let editor = self::editor("a\n");
+        assert_eq!(ranges(&editor), [0..2, 2..2]);
+    }
+
+    #[test]
+    fn line_feed_after_carriage_return_joins_separator() {
+        let mut editor = editor("a\rb");
+        assert_eq!(ranges(&editor), [0..2, 2..3]);
+        editor.replace(2..2, "\n");
+        assert_eq!(ranges(&editor), [0..3, 3..4]);
+    }
+
+    #[test]
+    fn relayout_only_touches_edited_paragraphs() {
+        let mut fcx = testing::font_context();
+        let mut lcx = LayoutContext::new();
+        let mut editor = editor("one\ntwo\nthree");
+        editor.relayout(&mut lcx, &mut fcx);
+        assert!(!editor.needs_relayout());
+        editor.set_selection(Selection::collapsed(7, Affinity::Downstream));
+        editor.insert(" more\u{2029}and");
+        assert_eq!(editor.text(), "one\ntwo more\u{2029}and\nthree");
+        let dirty: Vec<bool> = editor.paragraphs().iter().map(|p| p.dirty).collect();
+        assert_eq!(dirty, [false, true, true, false]);
+        editor.relayout(&mut lcx, &mut fcx);
+        let mut y = 0.;
+        for paragraph in editor.paragraphs() {
+            let mut expected = testing::layout(&editor.text()[paragraph.text_range()]);
+            expected.break_all_lines(None, Alignment::Start);
+            assert_eq!(paragraph.layout().width(), expected.width());
+            assert_eq!(paragraph.offset(), y);
+            y += paragraph.layout().height();
+        }
+        assert_eq!(editor.height(), y);
+    }
+
+    #[test]
+    fn styles_follow_edits() {
+        let mut editor = RichEditor::<[u8; 4]>::new("abc def");
+        editor.push(StyleProperty::FontSize(20.), 4..7);
+        editor.replace(0..1, "xyz");
+        assert_eq!(editor.styles[0].1, 6..9);
+        editor.replace(7..8, "");
+        assert_eq!(editor.styles[0].1, 6..8);
+        editor.replace(0..9, "");
+        assert!(editor.styles.is_empty());
+    }
+
+    #[test]
+    fn upstream_caret_at_paragraph_start_ends_previous_paragraph() {
+        let mut fcx = testing::font_context();
+        let mut lcx = LayoutContext::new();
+        let mut editor = editor("ab\ncd");
+        editor.relayout(&mut lcx, &mut fcx);
+        let second = editor.paragraphs()[1].offset();
+        assert!(second > 0.);
+        editor.set_selection(Selection::collapsed(3, Affinity::Downstream));
+        let caret = editor.caret_geometry(1.).unwrap();
+        assert_eq!((caret.x, caret.y), (0., second));
+        editor.set_selection(Selection::collapsed(3, Affinity::Upstream));
+        let caret = editor.caret_geometry(1.).unwrap();
+        assert_eq!(caret.y, 0.);
+        assert!(caret.x > 0.);
+    }
+}
@@ -1,15 +1,30 @@
 //! Font management.
 
+mod config;
+
+pub use config::{ConfigError, FontConfig};
+
 use fount::{FamilyId, FontData, FontId, GenericFamily, Library, Locale, SourceId};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::Arc;
+use swash::iter::{Features, Variations};
 use swash::proxy::CharmapProxy;
 use swash::text::cluster::*;
-use swash::text::Script;
-use swash::{Attributes, CacheKey, FontRef, Synthesis};
+use swash::text::{Language, Script};
+use swash::{Attributes, CacheKey, FontRef, StringId, Synthesis};
 
 // Make this configurable?
 const RETAINED_SOURCE_COUNT: usize = 12;
 
+/// Open licensed fonts that are compiled into the library.
+#[cfg(feature = "bundled-fonts")]
+const BUNDLED_FONTS: &[&[u8]] = &[
+    include_bytes!("../fonts/DejaVuSans.ttf"),
+    include_bytes!("../fonts/DejaVuSerif.ttf"),
+    include_bytes!("../fonts/DejaVuSansMono.ttf"),
+];
+
 /// Shared handle to a font.
 #[derive(Clone)]
 pub struct Font {
@@ -27,6 +42,38 @@ impl Font {
             key: self.key,
         }
     }
+
+    /// Returns the data containing the font. This may be a font collection.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Returns the offset to the font within its data.
+    pub fn offset(&self) -> u32 {
+        self.offset
+    }
+
+    /// Returns the primary attributes of the font.
+    pub fn attributes(&self) -> Attributes {
+        self.as_ref().attributes()
+    }
+
+    /// Returns the localized string with the specified identifier,
+    /// preferring the specified language.
+    pub fn name(&self, id: StringId, language: Option<&str>) -> Option<String> {
+        let name = self.as_ref().localized_strings().find_by_id(id, language)?;
+        Some(name.chars().collect())
+    }
+
+    /// Returns an iterator over the variation axes of the font.
+    pub fn variations(&self) -> Variations<'_> {
+        self.as_ref().variations()
+    }
+
+    /// Returns an iterator over the features supported by the font.
+    pub fn features(&self) -> Features<'_> {
+        self.as_ref().features()
+    }
 }
 
 impl PartialEq for Font {
@@ -43,14 +90,150 @@ pub struct FontContext {
 
 impl FontContext {
     pub fn new() -> Self {
+        let library = Library::builder().add_system_fonts().build();
         Self {
-            cache: FontCache::new(),
+            cache: FontCache::new(&library),
+        }
+    }
+
+    /// Creates a new context that ignores the fonts installed on the system.
+    /// Only fonts that are explicitly registered are available, which makes
+    /// layouts independent of the host. The system fonts are not scanned.
+    pub fn empty() -> Self {
+        let library = Library::builder().build();
+        let mut cache = FontCache::new(&library);
+        cache.filter.system_fonts = false;
+        Self { cache }
+    }
+
+    /// Creates a new context that ignores the fonts installed on the system
+    /// and contains the bundled DejaVu fonts. The generic serif, sans-serif,
+    /// monospace and system-ui families resolve to the bundled fonts.
+    #[cfg(feature = "bundled-fonts")]
+    pub fn bundled() -> Self {
+        let mut fcx = Self::empty();
+        for data in BUNDLED_FONTS {
+            fcx.register(data.to_vec());
+        }
+        let mut config = FontConfig::new();
+        for (generic, family) in &[
+            (GenericFamily::Serif, "DejaVu Serif"),
+            (GenericFamily::SansSerif, "DejaVu Sans"),
+            (GenericFamily::Monospace, "DejaVu Sans Mono"),
+            (GenericFamily::SystemUi, "DejaVu Sans"),
+        ] {
+            config
+                .generic_families
+                .push((*generic, vec![family.to_string()]));
+        }
+        fcx.apply_config(&config);
+        fcx
+    }
+
+    /// Creates a new context with the specified configuration.
+    ///
+    /// The configured directories are scanned for font files immediately,
+    /// but the files are only read and indexed the first time the context
+    /// is used to build a layout or when [`load_pending`](Self::load_pending)
+    /// is called. Files that cannot be loaded are reported by
+    /// [`skipped_files`](Self::skipped_files).
+    ///
+    /// WOFF and WOFF2 files are not decoded. They are never registered and
+    /// are only listed by [`skipped_files`](Self::skipped_files).
+    pub fn with_config(config: &FontConfig) -> Self {
+        let mut fcx = Self::new();
+        fcx.apply_config(config);
+        fcx
+    }
+
+    /// Applies the aliases and generic family mappings of the specified
+    /// configuration. The directories of the configuration are scanned
+    /// immediately and the font files that were found are queued for
+    /// loading.
+    pub fn apply_config(&mut self, config: &FontConfig) {
+        let cache = &mut self.cache;
+        for (alias, families) in &config.aliases {
+            cache.aliases.insert(alias.to_lowercase(), families.clone());
+        }
+        for (generic, families) in &config.generic_families {
+            cache.generics.retain(|(family, _)| family != generic);
+            cache.generics.push((*generic, families.clone()));
+        }
+        for dir in &config.directories {
+            config::scan_directory(dir, &mut cache.pending, &mut cache.skipped);
         }
+        cache.invalidate();
+    }
+
+    /// Loads and registers any font files that are queued from configured
+    /// directories. Returns the number of files that were registered.
+    pub fn load_pending(&mut self) -> usize {
+        self.cache.load_pending()
+    }
+
+    /// Returns the font files from configured directories that were not
+    /// registered. This includes files in unsupported formats, such as WOFF
+    /// and WOFF2, and queued files that could not be read or that contain
+    /// no valid fonts. Queued files are only checked once they are loaded.
+    pub fn skipped_files(&self) -> &[PathBuf] {
+        &self.cache.skipped
     }
 
     /// Returns true if a family of the specified name exists in the context.
     pub fn has_family(&self, name: &str) -> bool {
-        self.cache.context.family_by_name(name).is_some()
+        self.family(name).is_some()
+    }
+
+    /// Returns an iterator over all font families in the context.
+    pub fn families(&self) -> impl Iterator<Item = FamilyInfo> + '_ {
+        let cache = &self.cache;
+        cache
+            .context
+            .families()
+            .filter(move |entry| cache.has_fonts(entry))
+            .map(move |entry| cache.family_info(entry))
+    }
+
+    /// Returns the font family with the specified name.
+    pub fn family(&self, name: &str) -> Option<FamilyInfo> {
+        let entry = self.cache.family_by_name(name)?;
+        Some(self.cache.family_info(entry))
+    }
+
+    /// Returns an iterator over the fonts in the specified family.
+    pub fn family_fonts<'a>(
+        &'a self,
+        family: &'a FamilyInfo,
+    ) -> impl Iterator<Item = FontInfo> + 'a {
+        family
+            .entry
+            .fonts()
+            .iter()
+            .filter(move |id| self.cache.filter.is_available(**id))
+            .filter_map(move |id| self.cache.context.font(*id))
+            .map(|entry| FontInfo { entry })
+    }
+
+    /// Returns the font in the specified family that best matches the
+    /// attributes.
+    pub fn query(&self, family: &FamilyInfo, attributes: Attributes) -> Option<FontInfo> {
+        let cache = &self.cache;
+        let id = query_family(&cache.context, &cache.filter, &family.entry, attributes)?;
+        let entry = self.cache.context.font(id)?;
+        Some(FontInfo { entry })
+    }
+
+    /// Returns the family of the specified font.
+    pub fn font_family(&self, font: &FontInfo) -> Option<FamilyInfo> {
+        let entry = self.cache.context.family(font.entry.family())?;
+        Some(self.cache.family_info(entry))
+    }
+
+    /// Loads the specified font.
+    pub fn load(&mut self, font: &FontInfo) -> Option<Font> {
+        let cache = &mut self.cache;
+        let (font, _) = cache.sources.get(&cache.context, font.entry.id())?;
+        Some(font)
     }
 
     /// Registers the fonts in the specified font data. Returns the family name
@@ -59,10 +242,240 @@ impl FontContext {
     /// This API is temporary to support piet until the more of the underlying
     /// font collection code is exposed.
     pub fn register_fonts(&mut self, data: Vec<u8>) -> Option<String> {
-        let reg = self.cache.context.register_fonts(data)?;
-        let first_family = reg.families.get(0)?;
-        let family = self.cache.context.family(*first_family)?;
-        Some(family.name().to_owned())
+        let reg = self.register(data)?;
+        reg.families.into_iter().next()
+    }
+
+    /// Registers the fonts in the specified font data. The returned
+    /// registration can be used to remove the fonts from the context.
+    pub fn register(&mut self, data: Vec<u8>) -> Option<Registration> {
+        self.cache.register(data)
+    }
+
+    /// Removes the fonts added by the specified registration. Returns false
+    /// if the registration does not exist.
+    ///
+    /// The fonts are only hidden from queries and fallback: the underlying
+    /// font data stays in the context and its memory is not freed. Existing
+    /// layouts and fonts remain valid, but layouts that were built before
+    /// the change may reference removed fonts. Use
+    /// [`generation`](Self::generation) to detect them.
+    pub fn unregister(&mut self, id: RegistrationId) -> bool {
+        let cache = &mut self.cache;
+        let fonts = match cache.registrations.remove(&id.0) {
+            Some(fonts) => fonts,
+            None => return false,
+        };
+        for font in fonts {
+            if let Some(entry) = cache.context.font(font) {
+                cache.sources.sources.remove(&entry.source());
+            }
+            cache.filter.registered.remove(&font);
+            cache.filter.removed.insert(font);
+        }
+        cache.invalidate();
+        true
+    }
+
+    /// Replaces the fonts added by the specified registration with the fonts
+    /// in the specified font data.
+    ///
+    /// As with [`unregister`](Self::unregister), the data of the replaced
+    /// fonts is never freed, so replacing fonts repeatedly, such as on each
+    /// reload of a font file under development, uses memory without bound.
+    pub fn replace(&mut self, id: RegistrationId, data: Vec<u8>) -> Option<Registration> {
+        self.unregister(id);
+        self.register(data)
+    }
+
+    /// Returns a counter that is incremented each time fonts are registered
+    /// or unregistered. Layouts built with an earlier generation may be
+    /// stale.
+    pub fn generation(&self) -> u64 {
+        self.cache.generation
+    }
+
+    /// Registers a chain of font families that are tried, in order, before
+    /// the system fallback for text in the specified script. If a locale is
+    /// provided, the chain only applies to text with the same language and
+    /// region. Chains with a locale take precedence over those without.
+    ///
+    /// Families that do not exist in the context are ignored. Returns false
+    /// if the locale cannot be parsed or if none of the families exist.
+    pub fn set_fallback_families(
+        &mut self,
+        script: Script,
+        locale: Option<&str>,
+        families: &[&str],
+    ) -> bool {
+        let locale = match locale.map(Language::parse) {
+            Some(None) => return false,
+            Some(locale) => locale,
+            None => None,
+        };
+        let families = self.cache.family_ids(families);
+        let found = !families.is_empty();
+        let chains = &mut self.cache.fallback.chains;
+        chains.retain(|chain| chain.script != script || chain.locale != locale);
+        if found {
+            chains.push(FallbackChain {
+                script,
+                locale,
+                families,
+            });
+        }
+        self.cache.invalidate_fallbacks();
+        found
+    }
+
+    /// Removes all registered fallback chains.
+    pub fn clear_fallback_families(&mut self) {
+        self.cache.fallback.chains.clear();
+        self.cache.invalidate_fallbacks();
+    }
+
+    /// Overrides the font families used for emoji. An empty list restores
+    /// the default emoji family. Returns false if none of the families
+    /// exist.
+    pub fn set_emoji_families(&mut self, families: &[&str]) -> bool {
+        self.cache.fallback.emoji = self.cache.family_ids(families);
+        self.cache.invalidate_fallbacks();
+        families.is_empty() || !self.cache.fallback.emoji.is_empty()
+    }
+
+    /// Installs a custom source of fallback families that is consulted
+    /// after the registered fallback chains and before the system fallback.
+    /// The source is shared by clones of the context, which may be sent to
+    /// other threads.
+    pub fn set_fallback(&mut self, fallback: Option<Arc<dyn FontFallback + Send + Sync>>) {
+        self.cache.fallback.callback = fallback;
+        self.cache.invalidate_fallbacks();
+    }
+}
+
+/// Handle to the fonts added by a registration.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct RegistrationId(u64);
+
+/// Result of registering font data with a font context.
+#[derive(Clone, Debug)]
+pub struct Registration {
+    id: RegistrationId,
+    families: Vec<String>,
+}
+
+impl Registration {
+    /// Returns the handle for the registration.
+    pub fn id(&self) -> RegistrationId {
+        self.id
+    }
+
+    /// Returns the names of the families that received fonts.
+    pub fn families(&self) -> &[String] {
+        &self.families
+    }
+}
+
+/// Font family in a font context.
+#[derive(Clone)]
+pub struct FamilyInfo {
+    entry: fount::FamilyEntry,
+    len: usize,
+}
+
+impl FamilyInfo {
+    /// Returns the name of the family.
+    pub fn name(&self) -> &str {
+        self.entry.name()
+    }
+
+    /// Returns the number of available fonts in the family. Fonts that
+    /// were unregistered or that are hidden by the context are not counted.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if the family contains no available fonts.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+/// Font in a font context. Use [`FontContext::load`] to access the font
+/// data and metadata.
+#[derive(Clone)]
+pub struct FontInfo {
+    entry: fount::FontEntry,
+}
+
+impl FontInfo {
+    /// Returns the attributes of the font.
+    pub fn attributes(&self) -> Attributes {
+        self.entry.attributes()
+    }
+
+    /// Returns the index of the font within its source.
+    pub fn index(&self) -> u32 {
+        self.entry.index()
+    }
+}
+
+/// Custom source of fallback font families.
+///
+/// Results are cached for each combination of script, locale and font
+/// attributes until the fallback configuration of the context changes.
+pub trait FontFallback {
+    /// Returns the names of the font families to try, in order, for text in
+    /// the specified script and locale.
+    fn fallback_families(&self, script: Script, locale: Option<Language>) -> Vec<String>;
+}
+
+/// Families tried before the system fallback for a script and locale.
+#[derive(Clone)]
+struct FallbackChain {
+    script: Script,
+    locale: Option<Language>,
+    families: Vec<FamilyId>,
+}
+
+/// User configuration for font fallback.
+#[derive(Clone, Default)]
+struct FallbackConfig {
+    chains: Vec<FallbackChain>,
+    emoji: Vec<FamilyId>,
+    callback: Option<Arc<dyn FontFallback + Send + Sync>>,
+}
+
+impl FallbackConfig {
+    /// Appends the configured families for the specified script and locale.
+    fn families(
+        &self,
+        context: &fount::FontContext,
+        script: Script,
+        locale: Option<Locale>,
+        families: &mut Vec<FamilyId>,
+    ) {
+        let matches_locale = |chain: &FallbackChain| match (chain.locale, locale) {
+            (Some(expected), Some(locale)) => {
+                expected.language() == locale.language()
+                    && (expected.region().is_none() || expected.region() == locale.region())
+            }
+            _ => false,
+        };
+        let chains = self.chains.iter().filter(|chain| chain.script == script);
+        for chain in chains.clone().filter(|chain| matches_locale(chain)) {
+            families.extend_from_slice(&chain.families);
+        }
+        for chain in chains.filter(|chain| chain.locale.is_none()) {
+            families.extend_from_slice(&chain.families);
+        }
+        if let Some(callback) = &self.callback {
+            for name in callback.fallback_families(script, locale) {
+                if let Some(family) = context.family_by_name(&name) {
+                    families.push(family.id());
+                }
+            }
+        }
     }
 }
 
@@ -72,46 +485,185 @@ pub(crate) struct FontCache {
     sources: SourceCache,
     selected_params: Option<(usize, Attributes)>,
     selected_fonts: Vec<CachedFont>,
-    fallback_params: (Script, Option<Locale>, Attributes),
+    fallback_params: Option<(Script, Option<Locale>, Attributes)>,
     fallback_fonts: Vec<CachedFont>,
-    emoji_font: Option<CachedFont>,
+    fallback: FallbackConfig,
+    emoji_fonts: Option<Vec<CachedFont>>,
     attrs: Attributes,
+    /// Fonts added by each registration.
+    registrations: HashMap<u64, Vec<FontId>>,
+    next_registration: u64,
+    filter: FontFilter,
+    generation: u64,
+    /// Font files queued for loading.
+    pending: Vec<PathBuf>,
+    /// Font files that were found in directories but not registered.
+    skipped: Vec<PathBuf>,
+    /// Family aliases, keyed by lowercase name.
+    aliases: HashMap<String, Vec<String>>,
+    /// Configured families for generic families.
+    generics: Vec<(GenericFamily, Vec<String>)>,
 }
 
 impl FontCache {
-    pub fn new() -> Self {
+    pub fn new(library: &Library) -> Self {
         Self {
-            context: fount::FontContext::new(&Library::default()),
+            context: fount::FontContext::new(library),
             sources: SourceCache::default(),
             selected_params: None,
             selected_fonts: vec![],
-            fallback_params: (Script::Unknown, None, Attributes::default()),
+            fallback_params: None,
             fallback_fonts: vec![],
-            emoji_font: None,
+            fallback: FallbackConfig::default(),
+            emoji_fonts: None,
             attrs: Attributes::default(),
+            registrations: HashMap::new(),
+            next_registration: 0,
+            filter: FontFilter::default(),
+            generation: 0,
+            pending: vec![],
+            skipped: vec![],
+            aliases: HashMap::new(),
+            generics: vec![],
         }
     }
 
     pub fn reset(&mut self) {
+        self.load_pending();
         self.selected_params = None;
         self.selected_fonts.clear();
-        self.fallback_params = (Script::Unknown, None, Attributes::default());
+        self.fallback_params = None;
         self.fallback_fonts.clear();
         self.sources.serial += 1;
         self.sources.prune();
         self.attrs = Attributes::default();
     }
 
+    pub fn register(&mut self, data: Vec<u8>) -> Option<Registration> {
+        let reg = self.context.register_fonts(data)?;
+        let context = &self.context;
+        let families = reg
+            .families
+            .iter()
+            .filter_map(|id| context.family(*id))
+            .map(|family| family.name().to_owned())
+            .collect();
+        let id = self.next_registration;
+        self.next_registration += 1;
+        for font in &reg.fonts {
+            self.filter.registered.insert(*font);
+            self.filter.removed.remove(font);
+        }
+        self.registrations.insert(id, reg.fonts);
+        self.invalidate();
+        Some(Registration {
+            id: RegistrationId(id),
+            families,
+        })
+    }
+
+    /// Loads and registers the queued font files.
+    fn load_pending(&mut self) -> usize {
+        let mut count = 0;
+        for path in core::mem::take(&mut self.pending) {
+            let registered = match std::fs::read(&path) {
+                Ok(data) => self.register(data).is_some(),
+                Err(_) => false,
+            };
+            if registered {
+                count += 1;
+            } else {
+                self.skipped.push(path);
+            }
+        }
+        count
+    }
+
+    /// Returns the family with the specified name, resolving aliases if no
+    /// such family exists.
+    pub fn family_by_name(&self, name: &str) -> Option<fount::FamilyEntry> {
+        let find = |name: &str| {
+            self.context
+                .family_by_name(name)
+                .filter(|family| self.has_fonts(family))
+        };
+        find(name).or_else(|| {
+            self.aliases
+                .get(&name.to_lowercase())?
+                .iter()
+                .find_map(|name| find(name))
+        })
+    }
+
+    /// Appends the families for the specified generic family, preferring
+    /// the configured families over those provided by the system.
+    pub fn generic_families(&self, generic: GenericFamily, families: &mut Vec<FamilyId>) {
+        match self.generics.iter().find(|(family, _)| *family == generic) {
+            Some((_, names)) => families.extend(
+                names
+                    .iter()
+                    .filter_map(|name| self.family_by_name(name))
+                    .map(|family| family.id()),
+            ),
+            None => families.extend_from_slice(self.context.generic_families(generic)),
+        }
+    }
+
+    /// Returns the identifiers of the families with the specified names,
+    /// ignoring those that do not exist.
+    fn family_ids(&self, names: &[&str]) -> Vec<FamilyId> {
+        names
+            .iter()
+            .filter_map(|name| self.family_by_name(name))
+            .map(|family| family.id())
+            .collect()
+    }
+
+    /// Returns the family info for the entry with the number of available
+    /// fonts.
+    fn family_info(&self, entry: fount::FamilyEntry) -> FamilyInfo {
+        let len = entry
+            .fonts()
+            .iter()
+            .filter(|id| self.filter.is_available(**id))
+            .count();
+        FamilyInfo { entry, len }
+    }
+
+    /// Returns true if the family has fonts that were not unregistered.
+    fn has_fonts(&self, family: &fount::FamilyEntry) -> bool {
+        family
+            .fonts()
+            .iter()
+            .any(|id| self.filter.is_available(*id))
+    }
+
+    /// Discards all cached selections after the set of fonts changed.
+    fn invalidate(&mut self) {
+        self.selected_params = None;
+        self.selected_fonts.clear();
+        self.invalidate_fallbacks();
+        self.generation += 1;
+    }
+
+    /// Discards the cached fallback fonts after a configuration change.
+    fn invalidate_fallbacks(&mut self) {
+        self.fallback_params = None;
+        self.fallback_fonts.clear();
+        self.emoji_fonts = None;
+    }
+
     pub fn select_families(&mut self, id: usize, families: &[FamilyId], attrs: Attributes) {
         if self.selected_params != Some((id, attrs)) {
             self.selected_params = Some((id, attrs));
             self.selected_fonts.clear();
             let context = &self.context;
+            let filter = &self.filter;
             self.selected_fonts.extend(
                 families
                     .iter()
                     .filter_map(|id| context.family(*id))
-                    .filter_map(|family| family.query(attrs))
+                    .filter_map(|family| query_family(context, filter, &family, attrs))
                     .map(CachedFont::new),
             );
             self.attrs = attrs;
@@ -119,16 +671,20 @@ impl FontCache {
     }
 
     pub fn select_fallbacks(&mut self, script: Script, locale: Option<Locale>, attrs: Attributes) {
-        if self.fallback_params != (script, locale, attrs) {
-            self.fallback_params = (script, locale, attrs);
+        if self.fallback_params != Some((script, locale, attrs)) {
+            self.fallback_params = Some((script, locale, attrs));
             self.fallback_fonts.clear();
             let context = &self.context;
-            let fallback_families = context.fallback_families(script, locale);
+            let mut families = vec![];
+            self.fallback
+                .families(context, script, locale, &mut families);
+            families.extend_from_slice(context.fallback_families(script, locale));
+            let filter = &self.filter;
             self.fallback_fonts.extend(
-                fallback_families
+                families
                     .iter()
                     .filter_map(|id| context.family(*id))
-                    .filter_map(|family| family.query(attrs))
+                    .filter_map(|family| query_family(context, filter, &family, attrs))
                     .map(CachedFont::new),
             );
             self.attrs = attrs;
@@ -147,21 +703,27 @@ impl FontCache {
             return best.map(|(font, attrs)| (font, attrs.synthesize(self.attrs)));
         }
         if cluster.info().is_emoji() {
-            if self.emoji_font.is_none() {
-                self.emoji_font = self
-                    .context
-                    .generic_families(GenericFamily::Emoji)
-                    .iter()
-                    .filter_map(|id| self.context.family(*id))
-                    .filter_map(|family| family.query(Attributes::default()))
-                    .map(CachedFont::new)
-                    .next()
+            if self.emoji_fonts.is_none() {
+                let context = &self.context;
+                let filter = &self.filter;
+                let query = |id: &FamilyId| {
+                    let family = context.family(*id)?;
+                    query_family(context, filter, &family, Attributes::default())
+                        .map(CachedFont::new)
+                };
+                self.emoji_fonts = Some(if self.fallback.emoji.is_empty() {
+                    let mut families = vec![];
+                    self.generic_families(GenericFamily::Emoji, &mut families);
+                    families.iter().filter_map(query).take(1).collect()
+                } else {
+                    self.fallback.emoji.iter().filter_map(query).collect()
+                });
             }
-            if let Some(emoji_font) = &mut self.emoji_font {
+            if let Some(emoji_fonts) = &mut self.emoji_fonts {
                 if map_cluster(
                     &self.context,
                     &mut self.sources,
-                    core::slice::from_mut(emoji_font),
+                    emoji_fonts,
                     cluster,
                     &mut best,
                 ) {
@@ -180,6 +742,59 @@ impl FontCache {
     }
 }
 
+/// Set of fonts in the underlying collection that may be selected.
+#[derive(Clone)]
+struct FontFilter {
+    /// If false, only registered fonts are available.
+    system_fonts: bool,
+    /// Fonts added by registrations.
+    registered: HashSet<FontId>,
+    /// Fonts that were unregistered.
+    removed: HashSet<FontId>,
+}
+
+impl FontFilter {
+    fn is_available(&self, id: FontId) -> bool {
+        if self.system_fonts {
+            !self.removed.contains(&id)
+        } else {
+            self.registered.contains(&id)
+        }
+    }
+}
+
+impl Default for FontFilter {
+    fn default() -> Self {
+        Self {
+            system_fonts: true,
+            registered: HashSet::new(),
+            removed: HashSet::new(),
+        }
+    }
+}
+
+/// Returns the font in the family that best matches the attributes,
+/// ignoring fonts that are not available.
+fn query_family(
+    context: &fount::FontContext,
+    filter: &FontFilter,
+    family: &fount::FamilyEntry,
+    attrs: Attributes,
+) -> Option<FontId> {
+    let id = family.query(attrs)?;
+    if filter.is_available(id) {
+        return Some(id);
+    }
+    // Prefer a remaining font with the same attributes, otherwise take the
+    // first one.
+    let mut fonts = family.fonts().iter().filter(|id| filter.is_available(**id));
+    fonts
+        .clone()
+        .find(|id| context.font(**id).map(|font| font.attributes()) == Some(attrs))
+        .or_else(|| fonts.next())
+        .copied()
+}
+
 fn map_cluster(
     context: &fount::FontContext,
     sources: &mut SourceCache,
@@ -295,3 +910,196 @@ impl CachedFont {
         false
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::layout::{Alignment, Layout};
+    use crate::style::{FontFamily, FontStack, StyleProperty};
+    use crate::testing;
+    use crate::LayoutContext;
+
+    const DEJAVU_SANS: &[u8] = include_bytes!("../fonts/DejaVuSans.ttf");
+    const DEJAVU_SERIF: &[u8] = include_bytes!("../fonts/DejaVuSerif.ttf");
+
+    /// Returns the size of the font data used by each run of the text set in
+    /// the specified family.
+    fn run_fonts(
+        fcx: &mut FontContext,
+        text: &str,
+        family: &str,
+        locale: Option<&str>,
+    ) -> Vec<usize> {
+        let mut lcx = LayoutContext::new();
+        let mut builder = lcx.ranged_builder(fcx, text, 1.);
+        builder.push_default(&StyleProperty::FontStack(FontStack::Single(
+            FontFamily::Named(family),
+        )));
+        builder.push_default(&StyleProperty::Locale(locale));
+        let mut layout: Layout<[u8; 4]> = builder.build();
+        layout.break_all_lines(None, Alignment::Start);
+        let mut fonts = vec![];
+        for line in layout.lines() {
+            for run in line.runs() {
+                fonts.push(run.font().data().len());
+            }
+        }
+        fonts
+    }
+
+    struct SerifFallback(std::sync::Mutex<Vec<Option<Language>>>);
+
+    impl FontFallback for SerifFallback {
+        fn fallback_families(&self, _script: Script, locale: Option<Language>) -> Vec<String> {
+            self.0.lock().unwrap().push(locale);
+            vec!["DejaVu Serif".to_string()]
+        }
+    }
+
+    #[test]
+    fn fallback_chains_are_tried_in_order() {
+        let mut fcx = testing::font_context();
+        assert!(fcx.set_fallback_families(
+            Script::Latin,
+            None,
+            &["Missing", "DejaVu Serif", "DejaVu Sans"]
+        ));
+        assert_eq!(
+            run_fonts(&mut fcx, "abc", "Missing", None),
+            [DEJAVU_SERIF.len()]
+        );
+        assert!(fcx.set_fallback_families(Script::Latin, None, &["DejaVu Sans", "DejaVu Serif"]));
+        assert_eq!(
+            run_fonts(&mut fcx, "abc", "Missing", None),
+            [DEJAVU_SANS.len()]
+        );
+        assert!(!fcx.set_fallback_families(Script::Latin, None, &["Missing"]));
+        assert!(fcx.cache.fallback.chains.is_empty());
+    }
+
+    #[test]
+    fn fallback_chains_with_a_locale_take_precedence() {
+        let mut fcx = testing::font_context();
+        assert!(fcx.set_fallback_families(Script::Latin, None, &["DejaVu Sans"]));
+        assert!(fcx.set_fallback_families(Script::Latin, Some("fr"), &["DejaVu Serif"]));
+        assert!(!fcx.set_fallback_families(Script::Latin, Some("not a locale"), &["DejaVu Sans"]));
+        assert_eq!(fcx.cache.fallback.chains.len(), 2);
+        assert_eq!(
+            run_fonts(&mut fcx, "abc", "Missing", Some("fr-CA")),
+            [DEJAVU_SERIF.len()]
+        );
+        assert_eq!(
+            run_fonts(&mut fcx, "abc", "Missing", Some("de")),
+            [DEJAVU_SANS.len()]
+        );
+        assert_eq!(
+            run_fonts(&mut fcx, "abc", "Missing", None),
+            [DEJAVU_SANS.len()]
+        );
+    }
+
+    #[test]
+    fn emoji_families_override_the_default() {
+        let mut fcx = testing::font_context();
+        fcx.set_fallback(Some(Arc::new(SerifFallback(Default::default()))));
+        assert!(fcx.set_emoji_families(&["DejaVu Sans"]));
+        assert_eq!(
+            run_fonts(&mut fcx, "\u{263a}", "Missing", None),
+            [DEJAVU_SANS.len()]
+        );
+        assert!(!fcx.set_emoji_families(&["Missing"]));
+        assert_eq!(
+            run_fonts(&mut fcx, "\u{263a}", "Missing", None),
+            [DEJAVU_SERIF.len()]
+        );
+    }
+
+    #[test]
+    fn fallback_callback_follows_the_chains() {
+        let mut fcx = testing::font_context();
+        let callback = Arc::new(SerifFallback(Default::default()));
+        fcx.set_fallback(Some(callback.clone()));
+        assert_eq!(
+            run_fonts(&mut fcx, "abc", "Missing", Some("fr")),
+            [DEJAVU_SERIF.len()]
+        );
+        assert_eq!(*callback.0.lock().unwrap(), [Language::parse("fr")]);
+        assert!(fcx.set_fallback_families(Script::Latin, None, &["DejaVu Sans"]));
+        assert_eq!(
+            run_fonts(&mut fcx, "abc", "Missing", None),
+            [DEJAVU_SANS.len()]
+        );
+        fcx.set_fallback(None);
+        assert_eq!(
+            run_fonts(&mut fcx, "abc", "Missing", Some("fr")),
+            [DEJAVU_SANS.len()]
+        );
+        assert_eq!(callback.0.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn family_len_counts_available_fonts() {
+        let mut fcx = FontContext::empty();
+        let first = fcx.register(DEJAVU_SANS.to_vec()).unwrap();
+        fcx.register(DEJAVU_SANS.to_vec()).unwrap();
+        assert_eq!(fcx.family("DejaVu Sans").unwrap().len(), 2);
+        fcx.unregister(first.id());
+        let family = fcx.family("DejaVu Sans").unwrap();
+        assert_eq!(family.len(), fcx.family_fonts(&family).count());
+        assert_eq!(family.len(), 1);
+    }
+
+    #[test]
+    fn unregistered_fonts_are_not_selected() {
+        let mut fcx = FontContext::empty();
+        let sans = fcx.register(DEJAVU_SANS.to_vec()).unwrap();
+        fcx.register(DEJAVU_SERIF.to_vec()).unwrap();
+        assert!(fcx.set_fallback_families(Script::Latin, None, &["DejaVu Serif"]));
+        let fonts = run_fonts(&mut fcx, "abc", "DejaVu Sans", None);
+        assert_eq!(fonts, [DEJAVU_SANS.len()]);
+        let generation = fcx.generation();
+        assert!(fcx.unregister(sans.id()));
+        assert!(fcx.generation() > generation);
+        assert!(fcx.family("DejaVu Sans").is_none());
+        let fonts = run_fonts(&mut fcx, "abc", "DejaVu Sans", None);
+        assert_eq!(fonts, [DEJAVU_SERIF.len()]);
+        assert!(!fcx.unregister(sans.id()));
+    }
+
+    #[test]
+    fn aliases_keep_existing_families() {
+        let mut fcx = FontContext::empty();
+        fcx.register(DEJAVU_SANS.to_vec()).unwrap();
+        let config = FontConfig::parse(
+            "alias DejaVu Sans = Missing\nalias Helvetica = Missing, DejaVu Sans",
+        )
+        .unwrap();
+        fcx.apply_config(&config);
+        assert_eq!(fcx.family("DejaVu Sans").unwrap().name(), "DejaVu Sans");
+        assert_eq!(fcx.family("helvetica").unwrap().name(), "DejaVu Sans");
+        assert!(fcx.family("Missing").is_none());
+    }
+
+    #[test]
+    fn unsupported_files_are_reported() {
+        let dir = std::env::temp_dir().join(format!("parley-fonts-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a.ttf"), DEJAVU_SANS).unwrap();
+        std::fs::write(dir.join("b.woff2"), b"wOF2").unwrap();
+        std::fs::write(dir.join("c.otf"), b"invalid").unwrap();
+        let mut fcx = FontContext::empty();
+        fcx.apply_config(&FontConfig {
+            directories: vec![dir.clone()],
+            ..FontConfig::default()
+        });
+        assert_eq!(fcx.skipped_files(), &[dir.join("b.woff2")][..]);
+        assert_eq!(fcx.load_pending(), 1);
+        assert_eq!(
+            fcx.skipped_files(),
+            &[dir.join("b.woff2"), dir.join("c.otf")][..]
+        );
+        assert!(fcx.has_family("DejaVu Sans"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
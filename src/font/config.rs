@@ -0,0 +1,154 @@
+//! Font configuration.
+
+use fount::GenericFamily;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Configuration for the fonts of a font context.
+///
+/// The configuration can be parsed from a simple line based format:
+///
+/// ```text
+/// # Comments start with a hash.
+/// dir /opt/app/fonts
+/// alias Helvetica = Liberation Sans, Arimo
+/// generic sans-serif = Noto Sans, DejaVu Sans
+/// generic emoji = Noto Color Emoji
+/// ```
+///
+/// Aliases add a list of families that are tried in order when a family
+/// name is requested that does not exist in the context. A family with the
+/// aliased name remains resolvable and takes precedence. Generic mappings replace the families that
+/// the system provides for the generic family, which makes them resolve the
+/// same way on every host.
+#[derive(Clone, Default, Debug)]
+pub struct FontConfig {
+    /// Directories that are scanned recursively for font files when the
+    /// configuration is applied.
+    pub directories: Vec<PathBuf>,
+    /// Family aliases and the families they resolve to.
+    pub aliases: Vec<(String, Vec<String>)>,
+    /// Generic families and the families they resolve to.
+    pub generic_families: Vec<(GenericFamily, Vec<String>)>,
+}
+
+impl FontConfig {
+    /// Creates an empty configuration.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parses a configuration from the specified source.
+    pub fn parse(source: &str) -> Result<Self, ConfigError> {
+        let mut config = Self::new();
+        for (index, line) in source.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = ConfigError { line: index + 1 };
+            let (keyword, rest) = split_keyword(line).ok_or(error.clone())?;
+            match keyword {
+                "dir" => config.directories.push(PathBuf::from(rest)),
+                "alias" => {
+                    let (name, families) = parse_mapping(rest).ok_or(error)?;
+                    config.aliases.push((name.to_owned(), families));
+                }
+                "generic" => {
+                    let (name, families) = parse_mapping(rest).ok_or(error.clone())?;
+                    let generic = GenericFamily::parse(name).ok_or(error)?;
+                    config.generic_families.push((generic, families));
+                }
+                _ => return Err(error),
+            }
+        }
+        Ok(config)
+    }
+
+    /// Loads a configuration from the file at the specified path. Relative
+    /// directories are resolved against the directory of the file.
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let path = path.as_ref();
+        let source = std::fs::read_to_string(path)?;
+        let mut config = Self::parse(&source)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        if let Some(base) = path.parent() {
+            for dir in &mut config.directories {
+                if dir.is_relative() {
+                    *dir = base.join(&dir);
+                }
+            }
+        }
+        Ok(config)
+    }
+}
+
+/// Error that occurs when parsing a font configuration.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ConfigError {
+    /// Line number of the invalid entry, starting at 1.
+    pub line: usize,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid font configuration entry on line {}", self.line)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+fn split_keyword(line: &str) -> Option<(&str, &str)> {
+    let mut parts = line.splitn(2, char::is_whitespace);
+    let keyword = parts.next()?;
+    let rest = parts.next()?.trim();
+    if rest.is_empty() {
+        None
+    } else {
+        Some((keyword, rest))
+    }
+}
+
+fn parse_mapping(source: &str) -> Option<(&str, Vec<String>)> {
+    let mut parts = source.splitn(2, '=');
+    let name = parts.next()?.trim();
+    let families: Vec<String> = parts
+        .next()?
+        .split(',')
+        .map(|family| family.trim().trim_matches('"').to_owned())
+        .filter(|family| !family.is_empty())
+        .collect();
+    if name.is_empty() || families.is_empty() {
+        None
+    } else {
+        Some((name, families))
+    }
+}
+
+/// Collects the paths of the font files in the specified directory and its
+/// subdirectories, sorted for deterministic registration. Font files in
+/// formats that cannot be loaded, such as WOFF and WOFF2, are collected
+/// into `unsupported`.
+pub(crate) fn scan_directory(dir: &Path, paths: &mut Vec<PathBuf>, unsupported: &mut Vec<PathBuf>) {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    let mut entries: Vec<PathBuf> = entries.filter_map(|e| Some(e.ok()?.path())).collect();
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            scan_directory(&path, paths, unsupported);
+            continue;
+        }
+        let ext = match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) => ext.to_ascii_lowercase(),
+            None => continue,
+        };
+        match ext.as_str() {
+            "ttf" | "otf" | "ttc" | "otc" => paths.push(path),
+            "woff" | "woff2" => unsupported.push(path),
+            _ => {}
+        }
+    }
+}
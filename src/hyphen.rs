@@ -0,0 +1,181 @@
+//! Hyphenation based on Liang's pattern algorithm.
+
+use std::collections::HashMap;
+
+/// Minimum number of characters before the first hyphenation point.
+const LEFT_MIN: usize = 2;
+
+/// Minimum number of characters after the last hyphenation point.
+const RIGHT_MIN: usize = 3;
+
+/// Dictionary of hyphenation patterns and exceptions for a single language.
+#[derive(Clone, Default)]
+pub struct Hyphenator {
+    /// Maps pattern letters to the inter-letter levels.
+    patterns: HashMap<Vec<char>, Vec<u8>>,
+    /// Maps words to explicit hyphenation points in character offsets.
+    exceptions: HashMap<String, Vec<usize>>,
+    /// Length of the longest pattern in characters.
+    max_len: usize,
+}
+
+impl Hyphenator {
+    /// Parses a set of patterns in TeX format.
+    ///
+    /// The data may contain `\patterns{...}` and `\hyphenation{...}` groups
+    /// or simply a whitespace separated list of patterns (such as `hy3ph`)
+    /// and exceptions (such as `ta-ble`). Comments begin with `%`.
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let source = core::str::from_utf8(data).ok()?;
+        let mut hyphenator = Self::default();
+        let mut in_exceptions = false;
+        for line in source.lines() {
+            let line = match line.find('%') {
+                Some(pos) => &line[..pos],
+                _ => line,
+            };
+            for token in line.split_whitespace() {
+                let mut token = token;
+                if token.starts_with('\\') {
+                    in_exceptions = token.starts_with("\\hyphenation");
+                    token = match token.find('{') {
+                        Some(pos) => &token[pos + 1..],
+                        _ => "",
+                    };
+                }
+                let token = token.trim_matches(|ch| ch == '{' || ch == '}');
+                if token.is_empty() {
+                    continue;
+                }
+                if in_exceptions || (token.contains('-') && !token.contains(char::is_numeric)) {
+                    hyphenator.add_exception(token);
+                } else {
+                    hyphenator.add_pattern(token);
+                }
+            }
+        }
+        if hyphenator.patterns.is_empty() && hyphenator.exceptions.is_empty() {
+            return None;
+        }
+        Some(hyphenator)
+    }
+
+    fn add_pattern(&mut self, pattern: &str) {
+        let mut letters = vec![];
+        let mut levels = vec![0u8];
+        for ch in pattern.chars() {
+            if let Some(level) = ch.to_digit(10) {
+                *levels.last_mut().unwrap() = level as u8;
+            } else {
+                letters.extend(ch.to_lowercase());
+                levels.push(0);
+            }
+        }
+        if letters.is_empty() {
+            return;
+        }
+        self.max_len = self.max_len.max(letters.len());
+        self.patterns.insert(letters, levels);
+    }
+
+    fn add_exception(&mut self, word: &str) {
+        let mut text = String::new();
+        let mut points = vec![];
+        let mut len = 0;
+        for ch in word.chars() {
+            if ch == '-' {
+                points.push(len);
+            } else {
+                for ch in ch.to_lowercase() {
+                    text.push(ch);
+                    len += 1;
+                }
+            }
+        }
+        if !text.is_empty() {
+            self.exceptions.insert(text, points);
+        }
+    }
+
+    /// Invokes the specified closure with the byte offset of each
+    /// hyphenation point in the word.
+    pub fn hyphenate(&self, word: &str, mut f: impl FnMut(usize)) {
+        // Characters are lowercased the same way as the patterns, which
+        // may produce more than one character. Only the first of these
+        // maps to an offset in the word.
+        let mut chars = Vec::with_capacity(word.len());
+        let mut offsets = Vec::with_capacity(word.len());
+        for (offset, ch) in word.char_indices() {
+            for (i, ch) in ch.to_lowercase().enumerate() {
+                chars.push(ch);
+                offsets.push(if i == 0 { Some(offset) } else { None });
+            }
+        }
+        let offsets = offsets
+            .iter()
+            .enumerate()
+            .filter_map(|(index, offset)| Some((index, (*offset)?)));
+        let len = chars.len();
+        if len < LEFT_MIN + RIGHT_MIN {
+            return;
+        }
+        let key = chars.iter().collect::<String>();
+        if let Some(points) = self.exceptions.get(&key) {
+            for (index, offset) in offsets {
+                if points.contains(&index) {
+                    f(offset);
+                }
+            }
+            return;
+        }
+        let mut padded = Vec::with_capacity(len + 2);
+        padded.push('.');
+        padded.extend_from_slice(&chars);
+        padded.push('.');
+        let mut levels = vec![0u8; padded.len() + 1];
+        for start in 0..padded.len() {
+            let max_end = padded.len().min(start + self.max_len);
+            for end in start + 1..=max_end {
+                if let Some(pattern) = self.patterns.get(&padded[start..end]) {
+                    for (level, value) in levels[start..].iter_mut().zip(pattern) {
+                        *level = (*level).max(*value);
+                    }
+                }
+            }
+        }
+        // The level between characters index - 1 and index is stored at
+        // index + 1 due to the leading padding character.
+        for (index, offset) in offsets {
+            if index >= LEFT_MIN && len - index >= RIGHT_MIN && levels[index + 1] & 1 != 0 {
+                f(offset);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Hyphenator;
+
+    fn points(hyphenator: &Hyphenator, word: &str) -> Vec<usize> {
+        let mut points = vec![];
+        hyphenator.hyphenate(word, |offset| points.push(offset));
+        points
+    }
+
+    #[test]
+    fn words_are_lowercased_like_patterns() {
+        let hyphenator = Hyphenator::parse(b"C1D").unwrap();
+        assert_eq!(points(&hyphenator, "abcdefg"), vec![3]);
+        assert_eq!(points(&hyphenator, "ABCDEFG"), vec![3]);
+        // U+0130 lowercases to two characters.
+        assert_eq!(points(&hyphenator, "\u{130}bcdefg"), vec![4]);
+    }
+
+    #[test]
+    fn exceptions_override_patterns() {
+        let hyphenator = Hyphenator::parse(b"c1d \\hyphenation{AB-CDEFG}").unwrap();
+        assert_eq!(points(&hyphenator, "AbCdEfG"), vec![2]);
+        assert_eq!(points(&hyphenator, "abcdefgh"), vec![3]);
+    }
+}
@@ -23,17 +23,23 @@ impl<'a, B: Brush> Cluster<'a, B> {
 
     /// Returns true if the cluster is a word boundary.
     pub fn is_word_boundary(&self) -> bool {
-        self.data.info.is_boundary()
+        self.data.boundary != Boundary::None
     }
 
     /// Returns true if the cluster is a soft line break.
     pub fn is_soft_line_break(&self) -> bool {
-        self.data.info.boundary() == Boundary::Line
+        self.data.boundary == Boundary::Line
+    }
+
+    /// Returns true if a line may be broken before the cluster by inserting
+    /// a hyphen.
+    pub fn is_hyphen_break(&self) -> bool {
+        self.data.is_hyphen_break()
     }
 
     /// Returns true if the cluster is a hard line break.
     pub fn is_hard_line_break(&self) -> bool {
-        self.data.info.boundary() == Boundary::Mandatory
+        self.data.boundary == Boundary::Mandatory
     }
 
     /// Returns true if the cluster is a space or no-break space.
@@ -62,6 +68,10 @@ impl<'a, B: Brush> Cluster<'a, B> {
     pub(crate) fn info(&self) -> ClusterInfo {
         self.data.info
     }
+
+    pub(crate) fn boundary(&self) -> Boundary {
+        self.data.boundary
+    }
 }
 
 #[derive(Clone)]
@@ -1,6 +1,7 @@
-//! Hit testing.
+//! Hit testing and selection.
 
 use super::*;
+use swash::text::cluster::Whitespace;
 
 /// Represents a position within a layout.
 #[derive(Copy, Clone, Default, Debug)]
@@ -14,6 +15,8 @@ pub struct Cursor {
     is_rtl: bool,
     is_leading: bool,
     is_inside: bool,
+    /// Horizontal goal position preserved across vertical movement.
+    h_pos: Option<f32>,
 }
 
 impl Cursor {
@@ -40,43 +43,61 @@ impl Cursor {
             let mut last_edge = line_metrics.offset;
             for (run_index, run) in line.runs().enumerate() {
                 result.path.run_index = run_index;
-                let cluster_range = run.data().cluster_range.clone();
+                let run_len = run.len();
+                // Account for an inserted hyphen at the logical end of
+                // the run.
+                let inserted = run.inserted_glyph().map(|g| g.advance).unwrap_or(0.);
+                if run.is_rtl() {
+                    last_edge += inserted;
+                }
                 for (cluster_index, cluster) in run.visual_clusters().enumerate() {
                     let range = cluster.text_range();
                     result.text_start = range.start;
                     result.text_end = range.end;
-                    if run.is_rtl() {
-                        result.path.cluster_index = cluster_range.end - cluster_index - 1;
+                    result.is_rtl = run.is_rtl();
+                    if result.is_rtl {
+                        result.path.cluster_index = run_len - cluster_index - 1;
                     } else {
                         result.path.cluster_index = cluster_index;
                     }
                     let advance = cluster.advance();
+                    // A caret is never placed after a newline since that
+                    // position belongs to the following line.
+                    let is_newline = cluster.info().whitespace() == Whitespace::Newline;
+                    // The leading edge of a right-to-left cluster is on
+                    // the right.
                     if x >= last_edge {
                         let next_edge = last_edge + advance;
+                        result.advance = advance;
                         if x < next_edge {
-                            result.is_leading = false;
                             let middle = (last_edge + next_edge) * 0.5;
-                            result.advance = advance;
-                            if x <= middle {
-                                result.is_leading = true;
-                                result.offset = last_edge;
+                            let is_left = if is_newline {
+                                !result.is_rtl
                             } else {
-                                result.is_leading = false;
-                                result.offset = next_edge;
-                            }
+                                x <= middle
+                            };
+                            result.is_leading = is_left != result.is_rtl;
+                            result.offset = if is_left { last_edge } else { next_edge };
                             return result;
                         }
-                        result.advance = advance;
-                        result.is_leading = false;
-                        result.offset = next_edge;
+                        if is_newline {
+                            result.is_leading = true;
+                            result.offset = if result.is_rtl { next_edge } else { last_edge };
+                        } else {
+                            result.is_leading = result.is_rtl;
+                            result.offset = next_edge;
+                        }
                         last_edge = next_edge;
                     } else {
                         result.is_inside = false;
-                        result.is_leading = true;
+                        result.is_leading = !result.is_rtl;
                         result.offset = line_metrics.offset;
                         return result;
                     }
                 }
+                if !run.is_rtl() {
+                    last_edge += inserted;
+                }
             }
             break;
         }
@@ -117,7 +138,10 @@ impl Cursor {
                     result.offset = last_edge;
                     continue;
                 }
-                let cluster_range = run.data().cluster_range.clone();
+                let run_len = run.len();
+                if run.is_rtl() {
+                    last_edge += run.inserted_glyph().map(|g| g.advance).unwrap_or(0.);
+                }
                 for (cluster_index, cluster) in run.visual_clusters().enumerate() {
                     let range = cluster.text_range();
                     result.text_start = range.start;
@@ -125,16 +149,18 @@ impl Cursor {
                     result.offset = last_edge;
                     result.is_rtl = run.is_rtl();
                     if result.is_rtl {
-                        result.path.cluster_index = cluster_range.end - cluster_index - 1;
+                        result.path.cluster_index = run_len - cluster_index - 1;
                     } else {
                         result.path.cluster_index = cluster_index;
                     }
                     let advance = cluster.advance();
                     if range.contains(&position) {
-                        if !is_leading || !result.is_inside {
+                        result.is_leading = is_leading && result.is_inside;
+                        // The leading edge of a right-to-left cluster is on
+                        // the right.
+                        if result.is_leading == result.is_rtl {
                             result.offset += advance;
                         }
-                        result.is_leading = is_leading;
                         result.advance = advance;
                         return result;
                     }
@@ -149,6 +175,242 @@ impl Cursor {
         result
     }
 
+    /// Returns a cursor moved to the next cluster boundary in visual
+    /// order, which is to the right.
+    pub fn next_visual<B: Brush>(&self, layout: &Layout<B>) -> Self {
+        self.move_visual(layout, true)
+    }
+
+    /// Returns a cursor moved to the previous cluster boundary in visual
+    /// order, which is to the left.
+    pub fn prev_visual<B: Brush>(&self, layout: &Layout<B>) -> Self {
+        self.move_visual(layout, false)
+    }
+
+    fn move_visual<B: Brush>(&self, layout: &Layout<B>, right: bool) -> Self {
+        let line_count = layout.len();
+        let mut line_index = self.path.line_index;
+        let line = match layout.get(line_index) {
+            Some(line) => line,
+            _ => return *self,
+        };
+        let clusters = visual_clusters(&line);
+        let current = clusters.iter().position(|c| {
+            c.run_index == self.path.run_index && c.cluster_index == self.path.cluster_index
+        });
+        let (index, is_right) = match current {
+            Some(index) => (index, self.is_leading == self.is_rtl),
+            _ => return *self,
+        };
+        // Normalize to the index of the cluster whose left edge is at the
+        // caret, which may be one past the end.
+        let edge = if is_right { index + 1 } else { index };
+        let target = if right {
+            // Skip over a trailing newline since the position after it
+            // belongs to the following line.
+            if edge < clusters.len() && !clusters[edge].is_newline {
+                Some(edge + 1)
+            } else {
+                None
+            }
+        } else if edge > 0 {
+            Some(edge - 1)
+        } else {
+            None
+        };
+        if let Some(edge) = target {
+            return Self::from_visual_edge(layout, line_index, &clusters, edge);
+        }
+        // Move to the adjacent line.
+        if right {
+            if line_index + 1 >= line_count {
+                return *self;
+            }
+            line_index += 1;
+            let line = layout.get(line_index).unwrap();
+            let clusters = visual_clusters(&line);
+            Self::from_visual_edge(layout, line_index, &clusters, 0)
+        } else {
+            if line_index == 0 {
+                return *self;
+            }
+            line_index -= 1;
+            let line = layout.get(line_index).unwrap();
+            let clusters = visual_clusters(&line);
+            let mut edge = clusters.len();
+            if edge > 0 && clusters[edge - 1].is_newline {
+                edge -= 1;
+            }
+            Self::from_visual_edge(layout, line_index, &clusters, edge)
+        }
+    }
+
+    /// Creates a cursor at the left edge of the cluster at the specified
+    /// visual index, or the right edge of the last cluster if the index is
+    /// past the end.
+    fn from_visual_edge<B: Brush>(
+        layout: &Layout<B>,
+        line_index: usize,
+        clusters: &[VisualCluster],
+        edge: usize,
+    ) -> Self {
+        let line = layout.get(line_index).unwrap();
+        let metrics = line.metrics();
+        let mut result = Self {
+            baseline: metrics.baseline,
+            offset: metrics.offset,
+            is_leading: true,
+            is_inside: true,
+            ..Default::default()
+        };
+        result.path.line_index = line_index;
+        let (cluster, is_right) = if edge < clusters.len() {
+            (&clusters[edge], false)
+        } else if let Some(cluster) = clusters.last() {
+            (cluster, true)
+        } else {
+            // Empty line.
+            let range = line.text_range();
+            result.text_start = range.start;
+            result.text_end = range.start;
+            return result;
+        };
+        result.path.run_index = cluster.run_index;
+        result.path.cluster_index = cluster.cluster_index;
+        result.text_start = cluster.text_range.start;
+        result.text_end = cluster.text_range.end;
+        result.advance = cluster.advance;
+        result.is_rtl = cluster.is_rtl;
+        result.is_leading = is_right == cluster.is_rtl;
+        result.offset = if is_right {
+            cluster.x + cluster.advance
+        } else {
+            cluster.x
+        };
+        result
+    }
+
+    /// Returns a cursor moved to the next cluster boundary in logical
+    /// order.
+    pub fn next_logical<B: Brush>(&self, layout: &Layout<B>) -> Self {
+        let position = self.text_position();
+        let next = logical_clusters(layout)
+            .find(|(range, _)| !range.is_empty() && range.end > position)
+            .map(|(range, _)| range.end);
+        match next {
+            Some(next) => Self::from_position(layout, next, true),
+            _ => *self,
+        }
+    }
+
+    /// Returns a cursor moved to the previous cluster boundary in logical
+    /// order.
+    pub fn prev_logical<B: Brush>(&self, layout: &Layout<B>) -> Self {
+        let position = self.text_position();
+        let prev = logical_clusters(layout)
+            .take_while(|(range, _)| range.start < position)
+            .filter(|(range, _)| !range.is_empty())
+            .last()
+            .map(|(range, _)| range.start);
+        match prev {
+            Some(prev) => Self::from_position(layout, prev, true),
+            _ => *self,
+        }
+    }
+
+    /// Returns a cursor moved to the start of the next word.
+    pub fn next_word<B: Brush>(&self, layout: &Layout<B>) -> Self {
+        let position = self.text_position();
+        let next = logical_clusters(layout)
+            .find(|(range, cluster)| range.start > position && is_word_start(cluster))
+            .map(|(range, _)| range.start)
+            .unwrap_or(layout.data.text_len);
+        Self::from_position(layout, next, true)
+    }
+
+    /// Returns a cursor moved to the start of the current word, or the
+    /// previous word if the cursor is already at the start of a word.
+    pub fn prev_word<B: Brush>(&self, layout: &Layout<B>) -> Self {
+        let position = self.text_position();
+        let prev = logical_clusters(layout)
+            .take_while(|(range, _)| range.start < position)
+            .filter(|(_, cluster)| is_word_start(cluster))
+            .last()
+            .map(|(range, _)| range.start)
+            .unwrap_or(0);
+        Self::from_position(layout, prev, true)
+    }
+
+    /// Returns a cursor moved to the logical start of the current line.
+    pub fn line_start<B: Brush>(&self, layout: &Layout<B>) -> Self {
+        match layout.get(self.path.line_index) {
+            Some(line) => Self::from_position(layout, line.text_range().start, true),
+            _ => *self,
+        }
+    }
+
+    /// Returns a cursor moved to the logical end of the current line,
+    /// before any newline.
+    pub fn line_end<B: Brush>(&self, layout: &Layout<B>) -> Self {
+        let line = match layout.get(self.path.line_index) {
+            Some(line) => line,
+            _ => return *self,
+        };
+        let line_range = line.text_range();
+        let last = logical_clusters(layout)
+            .skip_while(|(range, _)| range.start < line_range.start)
+            .take_while(|(range, _)| range.end <= line_range.end)
+            .filter(|(range, cluster)| {
+                !range.is_empty() && cluster.info.whitespace() != Whitespace::Newline
+            })
+            .last();
+        match last {
+            // Target the trailing edge of the last cluster so that the
+            // cursor remains on this line when it wraps.
+            Some((range, _)) => Self::from_position(layout, range.start, false),
+            _ => Self::from_position(layout, line_range.start, true),
+        }
+    }
+
+    /// Returns a cursor moved to the previous line, preserving the
+    /// horizontal position across consecutive vertical movements.
+    pub fn move_up<B: Brush>(&self, layout: &Layout<B>) -> Self {
+        if self.path.line_index == 0 {
+            // Moving past the first line keeps the horizontal position so
+            // that moving back down returns to the same column.
+            return self.with_h_pos(Self::from_position(layout, 0, true));
+        }
+        self.move_to_line(layout, self.path.line_index - 1)
+    }
+
+    /// Returns a cursor moved to the next line, preserving the horizontal
+    /// position across consecutive vertical movements.
+    pub fn move_down<B: Brush>(&self, layout: &Layout<B>) -> Self {
+        if self.path.line_index + 1 >= layout.len() {
+            return self.with_h_pos(self.line_end(layout));
+        }
+        self.move_to_line(layout, self.path.line_index + 1)
+    }
+
+    fn move_to_line<B: Brush>(&self, layout: &Layout<B>, line_index: usize) -> Self {
+        let line = layout.get(line_index).unwrap();
+        let result = Self::from_point(layout, self.h_pos(), line.metrics().baseline);
+        self.with_h_pos(result)
+    }
+
+    /// Returns the horizontal position that is preserved across vertical
+    /// movements.
+    fn h_pos(&self) -> f32 {
+        self.h_pos.unwrap_or(self.offset)
+    }
+
+    /// Returns the specified cursor with the horizontal position of this
+    /// cursor.
+    fn with_h_pos(&self, mut cursor: Self) -> Self {
+        cursor.h_pos = Some(self.h_pos());
+        cursor
+    }
+
     /// Returns the path to the target cluster.
     pub fn path(&self) -> &CursorPath {
         &self.path
@@ -174,6 +436,16 @@ impl Cursor {
         self.text_start..self.text_end
     }
 
+    /// Returns the text position of the cursor. This is the start of the
+    /// target cluster for a leading edge and the end otherwise.
+    pub fn text_position(&self) -> usize {
+        if self.is_leading {
+            self.text_start
+        } else {
+            self.text_end
+        }
+    }
+
     /// Returns true if the cursor is on the leading edge of the target
     /// cluster.
     pub fn is_leading(&self) -> bool {
@@ -198,6 +470,70 @@ impl Cursor {
     }
 }
 
+/// Cluster of a line in visual order with its position.
+struct VisualCluster {
+    run_index: usize,
+    cluster_index: usize,
+    x: f32,
+    advance: f32,
+    text_range: Range<usize>,
+    is_rtl: bool,
+    is_newline: bool,
+}
+
+/// Collects the clusters of a line in visual order.
+fn visual_clusters<B: Brush>(line: &Line<B>) -> Vec<VisualCluster> {
+    let mut clusters = vec![];
+    let mut x = line.metrics().offset;
+    for (run_index, run) in line.runs().enumerate() {
+        let run_len = run.len();
+        let inserted = run.inserted_glyph().map(|g| g.advance).unwrap_or(0.);
+        if run.is_rtl() {
+            x += inserted;
+        }
+        for (index, cluster) in run.visual_clusters().enumerate() {
+            let advance = cluster.advance();
+            clusters.push(VisualCluster {
+                run_index,
+                cluster_index: if run.is_rtl() {
+                    run_len - index - 1
+                } else {
+                    index
+                },
+                x,
+                advance,
+                text_range: cluster.text_range(),
+                is_rtl: run.is_rtl(),
+                is_newline: cluster.info().whitespace() == Whitespace::Newline,
+            });
+            x += advance;
+        }
+        if !run.is_rtl() {
+            x += inserted;
+        }
+    }
+    clusters
+}
+
+/// Returns an iterator over all clusters of the layout in logical order
+/// with their text ranges.
+fn logical_clusters<B: Brush>(
+    layout: &Layout<B>,
+) -> impl Iterator<Item = (Range<usize>, &ClusterData)> + '_ {
+    let data = &layout.data;
+    data.runs.iter().flat_map(move |run| {
+        data.clusters[run.cluster_range.clone()]
+            .iter()
+            .map(move |cluster| (cluster.text_range(run), cluster))
+    })
+}
+
+fn is_word_start(cluster: &ClusterData) -> bool {
+    cluster.boundary != Boundary::None
+        && !cluster.info.is_whitespace()
+        && !cluster.is_ligature_component()
+}
+
 /// Index based path to a cluster.
 #[derive(Copy, Clone, Default, Debug)]
 pub struct CursorPath {
@@ -225,3 +561,290 @@ impl CursorPath {
         self.run(layout)?.get(self.cluster_index)
     }
 }
+
+/// Side of a text position that is associated with a caret.
+///
+/// This resolves ambiguity for positions that map to more than one visual
+/// location, such as the end of a wrapped line which is also the start of
+/// the following line.
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+pub enum Affinity {
+    /// The position is associated with the preceding character.
+    Upstream,
+    /// The position is associated with the following character.
+    #[default]
+    Downstream,
+}
+
+/// Range of text defined by an anchor and a focus position.
+///
+/// The anchor is the fixed end of the selection and the focus is the end
+/// that moves when the selection is extended. Positions are byte offsets
+/// into the source text.
+#[derive(Copy, Clone, PartialEq, Default, Debug)]
+pub struct Selection {
+    anchor: usize,
+    anchor_affinity: Affinity,
+    focus: usize,
+    focus_affinity: Affinity,
+}
+
+impl Selection {
+    /// Creates a new selection from the specified anchor and focus
+    /// positions.
+    pub fn new(anchor: usize, focus: usize) -> Self {
+        Self {
+            anchor,
+            focus,
+            ..Default::default()
+        }
+    }
+
+    /// Creates a new selection from the specified anchor and focus
+    /// positions with explicit affinities.
+    pub fn with_affinity(
+        anchor: usize,
+        anchor_affinity: Affinity,
+        focus: usize,
+        focus_affinity: Affinity,
+    ) -> Self {
+        Self {
+            anchor,
+            anchor_affinity,
+            focus,
+            focus_affinity,
+        }
+    }
+
+    /// Creates a new collapsed selection at the specified position.
+    pub fn collapsed(position: usize, affinity: Affinity) -> Self {
+        Self::with_affinity(position, affinity, position, affinity)
+    }
+
+    /// Creates a new collapsed selection from the specified layout and
+    /// point.
+    pub fn from_point<B: Brush>(layout: &Layout<B>, x: f32, y: f32) -> Self {
+        let (position, affinity) = Self::hit_test(layout, x, y);
+        Self::collapsed(position, affinity)
+    }
+
+    /// Returns a new selection with the same anchor and the focus moved to
+    /// the specified point.
+    pub fn extend_to_point<B: Brush>(&self, layout: &Layout<B>, x: f32, y: f32) -> Self {
+        let (focus, focus_affinity) = Self::hit_test(layout, x, y);
+        Self {
+            focus,
+            focus_affinity,
+            ..*self
+        }
+    }
+
+    /// Returns a new selection with the same anchor and the focus moved to
+    /// the specified position.
+    pub fn extend_to(&self, focus: usize, focus_affinity: Affinity) -> Self {
+        Self {
+            focus,
+            focus_affinity,
+            ..*self
+        }
+    }
+
+    fn hit_test<B: Brush>(layout: &Layout<B>, x: f32, y: f32) -> (usize, Affinity) {
+        let cursor = Cursor::from_point(layout, x, y);
+        let affinity = if cursor.is_leading() {
+            Affinity::Downstream
+        } else {
+            Affinity::Upstream
+        };
+        (cursor.text_position(), affinity)
+    }
+
+    /// Returns the anchor position.
+    pub fn anchor(&self) -> usize {
+        self.anchor
+    }
+
+    /// Returns the affinity of the anchor position.
+    pub fn anchor_affinity(&self) -> Affinity {
+        self.anchor_affinity
+    }
+
+    /// Returns the focus position.
+    pub fn focus(&self) -> usize {
+        self.focus
+    }
+
+    /// Returns the affinity of the focus position.
+    pub fn focus_affinity(&self) -> Affinity {
+        self.focus_affinity
+    }
+
+    /// Returns true if the anchor and focus are at the same position.
+    pub fn is_collapsed(&self) -> bool {
+        self.anchor == self.focus
+    }
+
+    /// Returns the range of text covered by the selection.
+    pub fn text_range(&self) -> Range<usize> {
+        if self.anchor <= self.focus {
+            self.anchor..self.focus
+        } else {
+            self.focus..self.anchor
+        }
+    }
+
+    /// Returns the affinity of the end of the selected range.
+    fn end_affinity(&self) -> Affinity {
+        if self.anchor <= self.focus {
+            self.focus_affinity
+        } else {
+            self.anchor_affinity
+        }
+    }
+
+    /// Returns the visual rectangles covered by the selection.
+    pub fn geometry<B: Brush>(&self, layout: &Layout<B>) -> Vec<Rect> {
+        let mut rects = vec![];
+        self.geometry_with(layout, |rect| rects.push(rect));
+        rects
+    }
+
+    /// Invokes the specified closure with each visual rectangle covered by
+    /// the selection, in line order and from left to right within a line.
+    ///
+    /// Each line produces one rectangle for every visually contiguous
+    /// sequence of selected clusters. When the selection continues past the
+    /// end of a line, the highlight is extended to the edge of the line
+    /// box in the direction of the paragraph.
+    pub fn geometry_with<B: Brush>(&self, layout: &Layout<B>, mut f: impl FnMut(Rect)) {
+        if self.is_collapsed() {
+            return;
+        }
+        let range = self.text_range();
+        let last_line = layout.len().saturating_sub(1);
+        for (line_index, line) in layout.lines().enumerate() {
+            let line_range = line.text_range();
+            if line_range.start >= range.end && !line_range.is_empty() {
+                break;
+            }
+            if line_range.end < range.start {
+                continue;
+            }
+            let is_rtl = line.is_rtl();
+            let metrics = line.metrics();
+            let y = metrics.baseline - metrics.ascent - metrics.leading * 0.5;
+            let height = metrics.size();
+            let mut spans: Vec<(f32, f32)> = vec![];
+            let mut x = metrics.offset;
+            for run in line.runs() {
+                let inserted = run.inserted_glyph().map(|g| g.advance).unwrap_or(0.);
+                if run.is_rtl() {
+                    x += inserted;
+                }
+                for cluster in run.visual_clusters() {
+                    let cluster_range = cluster.text_range();
+                    let advance = cluster.advance();
+                    let is_selected = if cluster_range.is_empty() {
+                        range.contains(&cluster_range.start)
+                    } else {
+                        cluster_range.start < range.end && cluster_range.end > range.start
+                    };
+                    if is_selected {
+                        match spans.last_mut() {
+                            Some(span) if span.1 == x => span.1 = x + advance,
+                            _ => spans.push((x, x + advance)),
+                        }
+                    }
+                    x += advance;
+                }
+                if !run.is_rtl() {
+                    x += inserted;
+                }
+            }
+            // Extend the highlight to the edge of the line box if the
+            // selection continues on a following line.
+            let continues = line_index != last_line
+                && (range.end > line_range.end
+                    || (range.end == line_range.end
+                        && self.end_affinity() == Affinity::Downstream));
+            if continues && range.start < line_range.end {
+                if is_rtl {
+                    let end = metrics.offset;
+                    if end > 0. {
+                        match spans.first_mut() {
+                            Some(span) if span.0 == end => span.0 = 0.,
+                            _ => spans.insert(0, (0., end)),
+                        }
+                    }
+                } else {
+                    let start = metrics.offset + metrics.advance;
+                    let max_advance = line.data.max_advance;
+                    let extent = if max_advance.is_finite() && max_advance < f32::MAX {
+                        max_advance
+                    } else {
+                        layout.data.full_width
+                    };
+                    if extent > start {
+                        match spans.last_mut() {
+                            Some(span) if span.1 >= start => span.1 = span.1.max(extent),
+                            _ => spans.push((start, extent)),
+                        }
+                    }
+                }
+            }
+            for (x0, x1) in spans {
+                f(Rect {
+                    x: x0,
+                    y,
+                    width: x1 - x0,
+                    height,
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn vertical_movement_keeps_horizontal_position() {
+        let mut layout = testing::layout("hello world");
+        layout.break_all_lines(Some(50.), Alignment::Start);
+        assert_eq!(layout.len(), 2);
+        let cursor = Cursor::from_position(&layout, 2, true);
+        let up = cursor.move_up(&layout);
+        assert_eq!(up.text_position(), 0);
+        let down = up.move_down(&layout);
+        assert_eq!(down.path().line_index, 1);
+        assert!((down.offset() - cursor.offset()).abs() <= down.advance());
+        let end = down.move_down(&layout);
+        assert_eq!(end.text_position(), layout.get(1).unwrap().text_range().end);
+        let up = end.move_up(&layout);
+        assert_eq!(up.path().line_index, 0);
+        assert!((up.offset() - cursor.offset()).abs() <= up.advance());
+    }
+
+    #[test]
+    fn selection_geometry_is_split_per_visual_run() {
+        let width = |text: &str| {
+            let mut layout = testing::layout(text);
+            layout.break_all_lines(None, Alignment::Start);
+            layout.get(0).unwrap().metrics().advance
+        };
+        let mut layout = testing::layout("abc \u{5d0}\u{5d1}\u{5d2} def");
+        layout.break_all_lines(None, Alignment::Start);
+        // Selects "c " and the first Hebrew letter, which is displayed at
+        // the right edge of the Hebrew run.
+        let rects = Selection::new(2, 6).geometry(&layout);
+        assert_eq!(rects.len(), 2);
+        assert!((rects[0].x - width("ab")).abs() < 0.01);
+        assert!((rects[0].x + rects[0].width - width("abc ")).abs() < 0.01);
+        assert!(rects[0].x + rects[0].width < rects[1].x);
+        let hebrew_end = width("abc \u{5d0}\u{5d1}\u{5d2}");
+        assert!((rects[1].x + rects[1].width - hebrew_end).abs() < 0.01);
+        assert!((rects[1].width - width("\u{5d0}")).abs() < 0.01);
+    }
+}
@@ -1,15 +1,21 @@
 use crate::font::Font;
-use crate::layout::{Alignment, Decoration, Glyph, LineMetrics, RunMetrics, Style};
-use crate::style::Brush;
+use crate::layout::{
+    Alignment, Decoration, Glyph, InlineBox, Layout, LineMetrics, RunMetrics, Style, WritingMode,
+};
+use crate::style::{Brush, TabAlignment, TabStop};
 use crate::util::*;
 use core::ops::Range;
 use swash::shape::Shaper;
 use swash::text::cluster::{Boundary, ClusterInfo};
-use swash::Synthesis;
+use swash::{GlyphId, Synthesis};
 
 #[derive(Copy, Clone)]
 pub struct ClusterData {
     pub info: ClusterInfo,
+    /// Boundary before the cluster. This starts out as the boundary from
+    /// cluster analysis and is adjusted for inline boxes and text that is
+    /// combined upright.
+    pub boundary: Boundary,
     pub flags: u16,
     pub style_index: u16,
     pub glyph_len: u8,
@@ -26,6 +32,14 @@ impl ClusterData {
     pub const LIGATURE_START: u16 = 1;
     pub const LIGATURE_COMPONENT: u16 = 2;
     pub const DIVERGENT_STYLES: u16 = 4;
+    /// A line may be broken before this cluster by inserting a hyphen.
+    pub const HYPHEN_BREAK: u16 = 8;
+    /// The cluster is the decimal separator of a decimal tab stop in its
+    /// style.
+    pub const DECIMAL_SEPARATOR: u16 = 16;
+    /// The cluster is a tab. Tabs are control characters, so cluster
+    /// analysis does not classify them as whitespace.
+    pub const TAB: u16 = 32;
 
     pub fn is_ligature_start(&self) -> bool {
         self.flags & Self::LIGATURE_START != 0
@@ -39,12 +53,33 @@ impl ClusterData {
         self.flags & Self::DIVERGENT_STYLES != 0
     }
 
+    pub fn is_hyphen_break(&self) -> bool {
+        self.flags & Self::HYPHEN_BREAK != 0
+    }
+
+    pub fn is_decimal_separator(&self) -> bool {
+        self.flags & Self::DECIMAL_SEPARATOR != 0
+    }
+
+    pub fn is_tab(&self) -> bool {
+        self.flags & Self::TAB != 0
+    }
+
     pub fn text_range(&self, run: &RunData) -> Range<usize> {
         let start = run.text_range.start + self.text_offset as usize;
         start..start + self.text_len as usize
     }
 }
 
+/// Tab stops for a style.
+#[derive(Clone, Debug)]
+pub struct TabStopsData {
+    /// Explicit tab stops, sorted by position.
+    pub stops: Vec<TabStop>,
+    /// Interval between the uniform stops following the explicit stops.
+    pub interval: f32,
+}
+
 #[derive(Clone)]
 pub struct RunData {
     /// Index of the font for the run.
@@ -73,6 +108,68 @@ pub struct RunData {
     pub letter_spacing: f32,
     /// Total advance of the run.
     pub advance: f32,
+    /// Hyphen glyph from the font of the run.
+    pub hyphen: Option<InsertedGlyph>,
+    /// Ellipsis glyphs from the font of the run.
+    pub ellipsis: Option<InsertedGlyph>,
+    /// Index of the inline box if the run represents one.
+    pub inline_box: Option<usize>,
+    /// True if the glyphs are set upright in vertical text.
+    pub upright: bool,
+    /// Placement of the glyphs if the run is combined upright in vertical
+    /// text.
+    pub combine: Option<CombineData>,
+}
+
+impl RunData {
+    /// Returns the extents of the run above and below the baseline of a
+    /// line. Vertical lines use a central baseline.
+    pub fn line_extents(&self, is_vertical: bool) -> (f32, f32) {
+        if !is_vertical || self.inline_box.is_some() {
+            (self.metrics.ascent, self.metrics.descent)
+        } else if self.upright {
+            let half = self.font_size * 0.5;
+            (half, half)
+        } else {
+            let half = (self.metrics.ascent + self.metrics.descent) * 0.5;
+            (half, half)
+        }
+    }
+}
+
+/// Placement of a run that is part of text combined upright in vertical
+/// text. Fallback fonts may split the combined text into several runs that
+/// share a single em box.
+#[derive(Copy, Clone, Default, Debug)]
+pub struct CombineData {
+    /// Horizontal scale factor applied to the glyphs.
+    pub scale: f32,
+    /// Offset of the glyphs of the run from the start of the combined text,
+    /// before scaling.
+    pub offset: f32,
+    /// Total width of the combined text, before scaling.
+    pub width: f32,
+}
+
+/// Candidate characters for a hyphen with their repeat counts.
+pub const HYPHENS: &[(char, u8)] = &[('\u{2010}', 1), ('-', 1)];
+
+/// Candidate characters for an ellipsis with their repeat counts.
+pub const ELLIPSES: &[(char, u8)] = &[('\u{2026}', 1), ('.', 3)];
+
+/// Glyph that is not present in the source text but is inserted at the end
+/// of a line run, such as a hyphen or an ellipsis.
+#[derive(Copy, Clone, Default)]
+pub struct InsertedGlyph {
+    /// Glyph identifier.
+    pub id: GlyphId,
+    /// Number of times the glyph is repeated.
+    pub count: u8,
+    /// Total advance of the repeated glyphs.
+    pub advance: f32,
+    /// Index of the entry in the fallback runs that supplies the font for
+    /// the glyph when the font of the run does not support it.
+    pub font_run: Option<usize>,
 }
 
 #[derive(Copy, Clone, PartialEq)]
@@ -95,6 +192,8 @@ pub struct LineData {
     pub text_range: Range<usize>,
     /// Range of line runs.
     pub run_range: Range<usize>,
+    /// Range of clusters in logical order.
+    pub cluster_range: Range<usize>,
     /// Metrics for the line.
     pub metrics: LineMetrics,
     /// The cause of the line break.
@@ -105,6 +204,8 @@ pub struct LineData {
     pub max_advance: f32,
     /// Number of justified clusters on the line.
     pub num_spaces: usize,
+    /// Base level of the paragraph containing the line.
+    pub base_level: u8,
 }
 
 impl LineData {
@@ -127,12 +228,22 @@ pub struct LineRunData {
     pub text_range: Range<usize>,
     /// Range of clusters.
     pub cluster_range: Range<usize>,
+    /// Glyph inserted after the last cluster of the run.
+    pub inserted: Option<InsertedGlyph>,
     /// Advance for the run.
     pub advance: f32,
 }
 
+/// Line height of runs that represent inline boxes. Line heights scale the
+/// extents of a run, and inline boxes define their own extents, so these
+/// are used as is.
+const INLINE_BOX_LINE_HEIGHT: f32 = 1.;
+
 impl LineRunData {
     pub fn compute_line_height<B: Brush>(&self, layout: &LayoutData<B>) -> f32 {
+        if layout.runs[self.run_index].inline_box.is_some() {
+            return INLINE_BOX_LINE_HEIGHT;
+        }
         let mut line_height = 0f32;
         let glyph_start = layout.runs[self.run_index].glyph_start;
         for cluster in &layout.clusters[self.cluster_range.clone()] {
@@ -161,6 +272,7 @@ pub struct StyleData<B: Brush> {
 #[derive(Clone)]
 pub struct LayoutData<B: Brush> {
     pub scale: f32,
+    pub writing_mode: WritingMode,
     pub has_bidi: bool,
     pub base_level: u8,
     pub text_len: usize,
@@ -175,12 +287,39 @@ pub struct LayoutData<B: Brush> {
     pub glyphs: Vec<Glyph>,
     pub lines: Vec<LineData>,
     pub line_runs: Vec<LineRunData>,
+    pub inline_boxes: Vec<InlineBox>,
+    /// Text ranges and base levels of paragraphs. Empty if the text was
+    /// entirely left-to-right.
+    pub paragraphs: Vec<(Range<usize>, u8)>,
+    /// Logical cluster index, relative to the start of the line, for each
+    /// visual position. Indexed by the cluster range of the line. Positions
+    /// past the visible clusters of a truncated line hold `u32::MAX`.
+    pub visual_map: Vec<u32>,
+    /// Visual position within the line for each cluster, or `u32::MAX` for
+    /// clusters removed by truncation.
+    pub logical_map: Vec<u32>,
+    pub rubies: Vec<RubyData<B>>,
+    /// Runs that supply fallback fonts for inserted glyphs. These are
+    /// copies of the runs that own the glyphs with the font replaced.
+    pub fallback_runs: Vec<RunData>,
+    /// Range of text removed by truncation.
+    pub truncated: Option<Range<usize>>,
+}
+
+/// Ruby annotation with its layout.
+#[derive(Clone)]
+pub struct RubyData<B: Brush> {
+    /// Range of the base text.
+    pub text_range: Range<usize>,
+    /// Layout of the annotation text.
+    pub layout: Layout<B>,
 }
 
 impl<B: Brush> Default for LayoutData<B> {
     fn default() -> Self {
         Self {
             scale: 1.,
+            writing_mode: WritingMode::HorizontalTb,
             has_bidi: false,
             base_level: 0,
             text_len: 0,
@@ -195,6 +334,13 @@ impl<B: Brush> Default for LayoutData<B> {
             glyphs: Vec::new(),
             lines: Vec::new(),
             line_runs: Vec::new(),
+            inline_boxes: Vec::new(),
+            paragraphs: Vec::new(),
+            visual_map: Vec::new(),
+            logical_map: Vec::new(),
+            rubies: Vec::new(),
+            fallback_runs: Vec::new(),
+            truncated: None,
         }
     }
 }
@@ -216,6 +362,26 @@ impl<B: Brush> LayoutData<B> {
         self.glyphs.clear();
         self.lines.clear();
         self.line_runs.clear();
+        self.inline_boxes.clear();
+        self.paragraphs.clear();
+        self.visual_map.clear();
+        self.logical_map.clear();
+        self.rubies.clear();
+        self.fallback_runs.clear();
+        self.truncated = None;
+    }
+
+    /// Returns the base level of the paragraph containing the specified
+    /// text offset.
+    pub fn paragraph_level(&self, offset: usize) -> u8 {
+        let index = self
+            .paragraphs
+            .partition_point(|(range, _)| range.end <= offset);
+        self.paragraphs
+            .get(index)
+            .or_else(|| self.paragraphs.last())
+            .map(|p| p.1)
+            .unwrap_or(self.base_level)
     }
 
     #[allow(unused_assignments)]
@@ -265,6 +431,11 @@ impl<B: Brush> LayoutData<B> {
             word_spacing,
             letter_spacing,
             advance: 0.,
+            hyphen: None,
+            ellipsis: None,
+            inline_box: None,
+            upright: false,
+            combine: None,
         };
         // Track these so that we can flush if they overflow a u16.
         let mut glyph_count = 0usize;
@@ -310,6 +481,7 @@ impl<B: Brush> LayoutData<B> {
             run.advance += advance;
             let mut cluster_data = ClusterData {
                 info: cluster.info,
+                boundary: cluster.info.boundary(),
                 flags: 0,
                 style_index: cluster.data as _,
                 glyph_len: glyph_len as u8,
@@ -380,6 +552,359 @@ impl<B: Brush> LayoutData<B> {
         flush_run!();
     }
 
+    /// Returns a glyph for the first candidate character supported by the
+    /// font of the run at the specified index, repeated the paired number of
+    /// times.
+    pub fn inserted_glyph(
+        &self,
+        run_index: usize,
+        candidates: &[(char, u8)],
+    ) -> Option<InsertedGlyph> {
+        let run = self.runs.get(run_index)?;
+        if run.inline_box.is_some() {
+            return None;
+        }
+        let font = self.fonts[run.font_index].as_ref();
+        let charmap = font.charmap();
+        let (id, count) = candidates
+            .iter()
+            .find_map(|&(ch, count)| match charmap.map(ch) {
+                0 => None,
+                id => Some((id, count)),
+            })?;
+        let metrics = font
+            .glyph_metrics(&self.coords[run.coords_range.clone()])
+            .scale(run.font_size);
+        let advance = if run.upright {
+            metrics.advance_height(id)
+        } else {
+            metrics.advance_width(id)
+        };
+        Some(InsertedGlyph {
+            id,
+            count,
+            advance: advance * count as f32,
+            font_run: None,
+        })
+    }
+
+    /// Resolves the ellipses of the runs at the specified indices. Runs
+    /// whose font does not support an ellipsis borrow one from the other
+    /// fonts of the layout.
+    pub fn resolve_ellipses(&mut self, run_indices: impl IntoIterator<Item = usize>) {
+        for index in run_indices {
+            let run = &self.runs[index];
+            if run.ellipsis.is_some() || run.inline_box.is_some() {
+                continue;
+            }
+            let mut ellipsis = self.inserted_glyph(index, ELLIPSES);
+            let mut font_index = 0;
+            while ellipsis.is_none() && font_index < self.fonts.len() {
+                let font = self.fonts[font_index].clone();
+                ellipsis = ELLIPSES.iter().find_map(|&(ch, count)| {
+                    self.push_fallback_glyph(index, font.clone(), Synthesis::default(), ch, count)
+                });
+                font_index += 1;
+            }
+            self.runs[index].ellipsis = ellipsis;
+        }
+    }
+
+    /// Returns a glyph for the character, repeated the specified number of
+    /// times, from a fallback font for the run at the specified index.
+    /// Returns `None` if the font does not support the character.
+    pub fn push_fallback_glyph(
+        &mut self,
+        run_index: usize,
+        font: Font,
+        synthesis: Synthesis,
+        ch: char,
+        count: u8,
+    ) -> Option<InsertedGlyph> {
+        let mut run = self.runs.get(run_index)?.clone();
+        let (id, advance, metrics) = {
+            let font = font.as_ref();
+            let id = font.charmap().map(ch);
+            if id == 0 {
+                return None;
+            }
+            let glyph_metrics = font.glyph_metrics(&[]).scale(run.font_size);
+            let advance = if run.upright {
+                glyph_metrics.advance_height(id)
+            } else {
+                glyph_metrics.advance_width(id)
+            };
+            (id, advance, font.metrics(&[]).scale(run.font_size))
+        };
+        run.font_index = self
+            .fonts
+            .iter()
+            .position(|f| *f == font)
+            .unwrap_or_else(|| {
+                self.fonts.push(font);
+                self.fonts.len() - 1
+            });
+        run.synthesis = synthesis;
+        run.coords_range = 0..0;
+        run.metrics = RunMetrics {
+            ascent: metrics.ascent,
+            descent: metrics.descent,
+            leading: metrics.leading,
+            underline_offset: metrics.underline_offset,
+            underline_size: metrics.stroke_size,
+            strikethrough_offset: metrics.strikeout_offset,
+            strikethrough_size: metrics.stroke_size,
+        };
+        run.hyphen = None;
+        run.ellipsis = None;
+        self.fallback_runs.push(run);
+        Some(InsertedGlyph {
+            id,
+            count,
+            advance: advance * count as f32,
+            font_run: Some(self.fallback_runs.len() - 1),
+        })
+    }
+
+    /// Replaces the advances of the runs starting at the specified index
+    /// with vertical advances for upright glyphs in vertical text.
+    pub fn apply_vertical_advances(&mut self, first_run: usize) {
+        for run in &mut self.runs[first_run..] {
+            let font = self.fonts[run.font_index].as_ref();
+            let metrics = font
+                .glyph_metrics(&self.coords[run.coords_range.clone()])
+                .scale(run.font_size);
+            run.upright = true;
+            run.advance = 0.;
+            let clusters = &mut self.clusters[run.cluster_range.clone()];
+            let mut i = 0;
+            while i < clusters.len() {
+                let cluster = &clusters[i];
+                let advance = if cluster.glyph_len == 0xFF {
+                    metrics.advance_height(cluster.glyph_offset)
+                } else {
+                    let start = run.glyph_start + cluster.glyph_offset as usize;
+                    let end = start + cluster.glyph_len as usize;
+                    let mut advance = 0.;
+                    for glyph in &mut self.glyphs[start..end] {
+                        glyph.advance = metrics.advance_height(glyph.id);
+                        advance += glyph.advance;
+                    }
+                    advance
+                };
+                // Distribute the advance of a ligature over its components.
+                let mut end = i + 1;
+                if cluster.is_ligature_start() {
+                    while end < clusters.len() && clusters[end].is_ligature_component() {
+                        end += 1;
+                    }
+                }
+                let count = (end - i) as f32;
+                for cluster in &mut clusters[i..end] {
+                    cluster.advance = advance / count;
+                }
+                run.advance += advance;
+                i = end;
+            }
+        }
+    }
+
+    /// Collapses each of the runs starting at the specified index into a
+    /// single upright cluster that occupies one em in vertical text. The
+    /// glyphs keep their horizontal positions and are compressed to fit
+    /// the em box if necessary.
+    pub fn combine_upright(&mut self, first_run: usize) {
+        let mut combined = vec![];
+        let mut width = 0.;
+        for run in &self.runs[first_run..] {
+            let clusters = &self.clusters[run.cluster_range.clone()];
+            let first = match clusters.first() {
+                Some(first) => *first,
+                _ => continue,
+            };
+            let mut glyphs = vec![];
+            let mut x = 0.;
+            for cluster in clusters {
+                if cluster.glyph_len == 0xFF {
+                    glyphs.push(Glyph {
+                        id: cluster.glyph_offset,
+                        style_index: cluster.style_index,
+                        x,
+                        y: 0.,
+                        advance: cluster.advance,
+                    });
+                    x += cluster.advance;
+                } else {
+                    let start = run.glyph_start + cluster.glyph_offset as usize;
+                    let end = start + cluster.glyph_len as usize;
+                    for glyph in &self.glyphs[start..end] {
+                        glyphs.push(Glyph {
+                            x: x + glyph.x,
+                            ..*glyph
+                        });
+                        x += glyph.advance;
+                    }
+                }
+            }
+            if glyphs.len() >= 0xFF || run.text_range.len() > 0xFF {
+                // Too long to be represented as a single cluster so just
+                // set the text of the item upright.
+                self.apply_vertical_advances(first_run);
+                return;
+            }
+            let mut cluster = ClusterData {
+                flags: 0,
+                glyph_len: glyphs.len() as u8,
+                text_len: run.text_range.len() as u8,
+                advance: 0.,
+                text_offset: 0,
+                glyph_offset: 0,
+                ..first
+            };
+            if glyphs.iter().any(|g| g.style_index != first.style_index) {
+                cluster.flags |= ClusterData::DIVERGENT_STYLES;
+            }
+            if !combined.is_empty() && cluster.boundary != Boundary::Mandatory {
+                // The combined text is never broken between runs.
+                cluster.boundary = Boundary::None;
+            }
+            combined.push((run.clone(), cluster, glyphs, width));
+            width += x;
+        }
+        let (cluster_start, glyph_start, font_size) = match self.runs.get(first_run) {
+            Some(run) => (run.cluster_range.start, run.glyph_start, run.font_size),
+            _ => return,
+        };
+        // All runs of the item share a single em box. The visually last run
+        // takes the full advance so that every run begins at the start of
+        // the box.
+        let last = if self.runs[first_run].bidi_level & 1 != 0 {
+            0
+        } else {
+            combined.len().saturating_sub(1)
+        };
+        let scale = if width > font_size {
+            font_size / width
+        } else {
+            1.
+        };
+        self.runs.truncate(first_run);
+        self.clusters.truncate(cluster_start);
+        self.glyphs.truncate(glyph_start);
+        for (i, (mut run, mut cluster, glyphs, offset)) in combined.into_iter().enumerate() {
+            let advance = if i == last { font_size } else { 0. };
+            cluster.advance = advance;
+            run.cluster_range = self.clusters.len()..self.clusters.len() + 1;
+            run.glyph_start = self.glyphs.len();
+            run.advance = advance;
+            run.upright = true;
+            run.combine = Some(CombineData {
+                scale,
+                offset,
+                width,
+            });
+            self.clusters.push(cluster);
+            self.glyphs.extend_from_slice(&glyphs);
+            self.runs.push(run);
+        }
+    }
+
+    /// Pushes a run containing a single cluster that represents an inline
+    /// box.
+    pub fn push_inline_box(
+        &mut self,
+        inline_box: InlineBox,
+        style_index: u16,
+        bidi_level: u8,
+        boundary: Boundary,
+    ) {
+        let index = self.inline_boxes.len();
+        self.inline_boxes.push(inline_box);
+        let cluster_index = self.clusters.len();
+        self.clusters.push(ClusterData {
+            info: ClusterInfo::default(),
+            boundary,
+            flags: 0,
+            style_index,
+            glyph_len: 0,
+            text_len: 0,
+            glyph_offset: 0,
+            text_offset: 0,
+            advance: inline_box.width,
+        });
+        self.runs.push(RunData {
+            font_index: 0,
+            font_size: 0.,
+            synthesis: Synthesis::default(),
+            coords_range: 0..0,
+            text_range: inline_box.index..inline_box.index,
+            bidi_level,
+            ends_with_newline: false,
+            cluster_range: cluster_index..cluster_index + 1,
+            glyph_start: self.glyphs.len(),
+            metrics: RunMetrics {
+                ascent: inline_box.baseline,
+                descent: inline_box.height - inline_box.baseline,
+                ..Default::default()
+            },
+            word_spacing: 0.,
+            letter_spacing: 0.,
+            advance: inline_box.width,
+            hyphen: None,
+            ellipsis: None,
+            inline_box: Some(index),
+            upright: false,
+            combine: None,
+        });
+    }
+
+    /// Marks the clusters that begin at the specified sorted text offsets
+    /// as hyphenation opportunities.
+    pub fn apply_hyphen_breaks(&mut self, offsets: &[usize]) {
+        if offsets.is_empty() {
+            return;
+        }
+        for run in &self.runs {
+            let text_start = run.text_range.start;
+            for cluster in &mut self.clusters[run.cluster_range.clone()] {
+                let offset = text_start + cluster.text_offset as usize;
+                if offsets.binary_search(&offset).is_ok() {
+                    cluster.flags |= ClusterData::HYPHEN_BREAK;
+                }
+            }
+        }
+    }
+
+    /// Marks tab clusters and the clusters that hold the separator of a
+    /// decimal tab stop in the style of the cluster.
+    pub fn apply_tabs(&mut self, text: &str) {
+        for run in &self.runs {
+            for cluster in &mut self.clusters[run.cluster_range.clone()] {
+                let ch = match text
+                    .get(cluster.text_range(run))
+                    .and_then(|s| s.chars().next())
+                {
+                    Some(ch) => ch,
+                    None => continue,
+                };
+                if ch == '\t' {
+                    cluster.flags |= ClusterData::TAB;
+                    continue;
+                }
+                let stops = match &self.styles[cluster.style_index as usize].tab_stops {
+                    Some(tabs) => &tabs.stops,
+                    None => continue,
+                };
+                if stops
+                    .iter()
+                    .any(|stop| stop.alignment == TabAlignment::Decimal(ch))
+                {
+                    cluster.flags |= ClusterData::DECIMAL_SEPARATOR;
+                }
+            }
+        }
+    }
+
     pub fn finish(&mut self) {
         for run in &self.runs {
             let word = run.word_spacing;
@@ -408,3 +933,64 @@ impl<B: Brush> LayoutData<B> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::layout::{Alignment, WritingMode};
+    use crate::style::{FontFamily, FontStack, StyleProperty};
+    use crate::testing;
+    use swash::text::cluster::Boundary;
+
+    #[test]
+    fn combined_runs_share_em_box() {
+        let serif = StyleProperty::FontStack(FontStack::Single(FontFamily::Named("DejaVu Serif")));
+        let layout = testing::layout_with("12", |builder| {
+            builder.set_writing_mode(WritingMode::VerticalRl);
+            builder.push_default(&StyleProperty::TextCombineUpright(true));
+            builder.push(&serif, 1..2);
+        });
+        let data = &layout.data;
+        assert_eq!(data.runs.len(), 2);
+        let advance: f32 = data.runs.iter().map(|run| run.advance).sum();
+        assert_eq!(advance, testing::FONT_SIZE);
+        let first = data.runs[0].combine.unwrap();
+        let second = data.runs[1].combine.unwrap();
+        assert_eq!(first.offset, 0.);
+        assert!(second.offset > 0.);
+        assert_eq!(first.width, second.width);
+        assert_eq!(first.scale, second.scale);
+        assert!(first.scale < 1.);
+    }
+
+    #[test]
+    fn combined_runs_are_not_broken() {
+        let serif = StyleProperty::FontStack(FontStack::Single(FontFamily::Named("DejaVu Serif")));
+        let mut layout = testing::layout_with("1 2", |builder| {
+            builder.set_writing_mode(WritingMode::VerticalRl);
+            builder.push_default(&StyleProperty::TextCombineUpright(true));
+            builder.push(&serif, 2..3);
+        });
+        let data = &layout.data;
+        assert_eq!(data.runs.len(), 2);
+        assert!(data.clusters[1..]
+            .iter()
+            .all(|cluster| cluster.boundary == Boundary::None));
+        layout.break_all_lines(Some(1.), Alignment::Start);
+        assert_eq!(layout.len(), 1);
+    }
+
+    #[test]
+    fn long_combined_text_is_set_upright() {
+        let text = "0".repeat(0x100);
+        let layout = testing::layout_with(&text, |builder| {
+            builder.set_writing_mode(WritingMode::VerticalRl);
+            builder.push_default(&StyleProperty::TextCombineUpright(true));
+        });
+        let data = &layout.data;
+        assert!(data
+            .runs
+            .iter()
+            .all(|run| run.upright && run.combine.is_none()));
+        assert_eq!(data.clusters.len(), 0x100);
+    }
+}
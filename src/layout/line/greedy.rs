@@ -1,18 +1,19 @@
 //! Greedy line breaking.
 
 use crate::layout::*;
-use crate::style::Brush;
+use crate::style::{Brush, TabAlignment, TabStop};
+use swash::text::cluster::Whitespace;
 
 use core::ops::Range;
 
 #[derive(Default)]
-struct LineLayout {
-    lines: Vec<LineData>,
-    runs: Vec<LineRunData>,
+pub(super) struct LineLayout {
+    pub lines: Vec<LineData>,
+    pub runs: Vec<LineRunData>,
 }
 
 impl LineLayout {
-    fn swap<B: Brush>(&mut self, layout: &mut LayoutData<B>) {
+    pub fn swap<B: Brush>(&mut self, layout: &mut LayoutData<B>) {
         core::mem::swap(&mut self.lines, &mut layout.lines);
         core::mem::swap(&mut self.runs, &mut layout.line_runs);
     }
@@ -32,6 +33,7 @@ impl<'a, B: Brush> BreakLines<'a, B> {
         unjustify(layout);
         layout.width = 0.;
         layout.height = 0.;
+        layout.truncated = None;
         let mut lines = LineLayout::default();
         lines.swap(layout);
         lines.lines.clear();
@@ -54,15 +56,20 @@ impl<'a, B: Brush> BreakLines<'a, B> {
         self.prev_state = Some(self.state.clone());
         let run_count = self.layout.runs.len();
         while self.state.i < run_count {
-            let run_data = &self.layout.runs[self.state.i];
-            let run = Run::new(self.layout, run_data, None);
-            let cluster_start = run_data.cluster_range.start;
-            let cluster_end = run_data.cluster_range.end;
+            let cluster_start = self.layout.runs[self.state.i].cluster_range.start;
+            let cluster_end = self.layout.runs[self.state.i].cluster_range.end;
             while self.state.j < cluster_end {
+                // Tabs advance to a stop measured from the start of the
+                // line, so they are resolved each time a line is broken.
+                if let Some(advance) = tab_advance(self.layout, self.state.j, self.state.line.x) {
+                    self.layout.clusters[self.state.j].advance = advance;
+                }
+                let run_data = &self.layout.runs[self.state.i];
+                let run = Run::new(self.layout, run_data, None);
                 let cluster = run.get(self.state.j - cluster_start).unwrap();
                 let is_ligature_continuation = cluster.is_ligature_continuation();
                 let is_space = cluster.info().whitespace().is_space_or_nbsp();
-                let boundary = cluster.info().boundary();
+                let boundary = cluster.boundary();
                 match boundary {
                     Boundary::Mandatory => {
                         if !self.state.line.skip_mandatory_break {
@@ -87,6 +94,31 @@ impl<'a, B: Brush> BreakLines<'a, B> {
                             }
                         }
                     }
+                    _ if cluster.is_hyphen_break() => {
+                        // The hyphen is taken from the run of the preceding
+                        // cluster since that run ends the line.
+                        let hyphen = if self.state.j > cluster_start {
+                            run_data.hyphen
+                        } else {
+                            self.state
+                                .i
+                                .checked_sub(1)
+                                .and_then(|i| self.layout.runs[i].hyphen)
+                        };
+                        if let Some(hyphen) = hyphen {
+                            if !is_ligature_continuation
+                                && self.state.line.x + hyphen.advance <= max_advance
+                            {
+                                let mut state = self.state.line.clone();
+                                state.hyphen = Some(hyphen);
+                                self.state.prev_boundary = Some(PrevBoundaryState {
+                                    i: self.state.i,
+                                    j: self.state.j,
+                                    state,
+                                });
+                            }
+                        }
+                    }
                     Boundary::Line => {
                         if !is_ligature_continuation {
                             self.state.prev_boundary = Some(PrevBoundaryState {
@@ -241,16 +273,21 @@ impl<'a, B: Brush> BreakLines<'a, B> {
     /// Reverts the last computed line, returning to the previous state.
     pub fn revert(&mut self) -> bool {
         if let Some(state) = self.prev_state.take() {
-            self.state = state;
-            self.lines.lines.truncate(self.state.lines);
-            self.lines.runs.truncate(self.state.runs);
-            self.done = false;
+            self.restore(state);
             true
         } else {
             false
         }
     }
 
+    /// Returns to the specified state, removing all lines computed after it.
+    fn restore(&mut self, state: BreakerState) {
+        self.state = state;
+        self.lines.lines.truncate(self.state.lines);
+        self.lines.runs.truncate(self.state.runs);
+        self.done = false;
+    }
+
     /// Breaks all remaining lines with the specified maximum advance. This
     /// consumes the line breaker.
     pub fn break_remaining(mut self, max_advance: f32, alignment: Alignment) {
@@ -258,173 +295,339 @@ impl<'a, B: Brush> BreakLines<'a, B> {
         self.finish();
     }
 
-    /// Consumes the line breaker and finalizes all line computations.
-    pub fn finish(mut self) {
-        for run in &mut self.lines.runs {
-            run.is_whitespace = true;
-            if run.bidi_level & 1 != 0 {
-                // RTL runs check for "trailing" whitespace at the front.
-                for cluster in self.layout.clusters[run.cluster_range.clone()].iter() {
-                    if cluster.info.is_whitespace() {
-                        run.has_trailing_whitespace = true;
-                    } else {
-                        run.is_whitespace = false;
-                        break;
-                    }
-                }
-            } else {
-                for cluster in self.layout.clusters[run.cluster_range.clone()].iter().rev() {
-                    if cluster.info.is_whitespace() {
-                        run.has_trailing_whitespace = true;
-                    } else {
-                        run.is_whitespace = false;
-                        break;
-                    }
+    /// Breaks all remaining lines until the specified limits are exceeded,
+    /// then replaces the final line that fits with a truncated line. At
+    /// least one line is always kept. Returns the range of text that was
+    /// removed, if any. This consumes the line breaker.
+    pub(crate) fn break_remaining_truncated(
+        mut self,
+        max_advance: f32,
+        alignment: Alignment,
+        truncation: Truncation,
+    ) -> Option<Range<usize>> {
+        let max_lines = truncation.max_lines.unwrap_or(usize::MAX);
+        let max_height = truncation.max_height.unwrap_or(f32::MAX);
+        let mut count = 0;
+        let mut height = 0.;
+        // State before the last line that fits.
+        let mut last_state = self.state.clone();
+        loop {
+            let state = self.state.clone();
+            let size = match self.break_next(max_advance, alignment) {
+                Some((_, size)) => size,
+                None => {
+                    self.finish();
+                    return None;
                 }
+            };
+            count += 1;
+            height += size;
+            if count > max_lines || height > max_height {
+                // Break the last line that fits, or this line if it is the
+                // first, again as the truncated line.
+                self.restore(if count > 1 { last_state } else { state });
+                break;
             }
+            last_state = state;
         }
-        let mut y = 0.;
-        for line in &mut self.lines.lines {
-            let run_base = line.run_range.start;
-            let run_count = line.run_range.end - run_base;
-            line.metrics.ascent = 0.;
-            line.metrics.descent = 0.;
-            line.metrics.leading = 0.;
-            line.metrics.offset = 0.;
-            let mut have_metrics = false;
-            let mut needs_reorder = false;
-            line.text_range.start = usize::MAX;
-            // Compute metrics for the line, but ignore trailing whitespace.
-            for line_run in self.lines.runs[line.run_range.clone()].iter_mut().rev() {
-                line.text_range.end = line.text_range.end.max(line_run.text_range.end);
-                line.text_range.start = line.text_range.start.min(line_run.text_range.start);
-                if line_run.bidi_level != 0 {
-                    needs_reorder = true;
-                }
-                if !have_metrics && line_run.is_whitespace {
-                    continue;
-                }
-                line_run.advance = self.layout.clusters[line_run.cluster_range.clone()]
+        let removed = self.break_truncated(max_advance, alignment, truncation.ellipsis);
+        if removed.is_empty() {
+            None
+        } else {
+            self.layout.truncated = Some(removed.clone());
+            Some(removed)
+        }
+    }
+
+    /// Commits a final line, removing clusters at the specified position
+    /// until the remaining content and an ellipsis fit within the maximum
+    /// advance. Returns the range of text that was removed.
+    fn break_truncated(
+        &mut self,
+        max_advance: f32,
+        alignment: Alignment,
+        position: EllipsisPosition,
+    ) -> Range<usize> {
+        if position == EllipsisPosition::End {
+            self.break_next(max_advance, alignment);
+        } else {
+            // The final line holds the remaining text of its paragraph so
+            // that the end of the paragraph stays visible.
+            let state = &mut self.state.line;
+            let clusters = &self.layout.clusters;
+            let end = (state.clusters.start + 1..clusters.len())
+                .find(|&index| clusters[index].boundary == Boundary::Mandatory)
+                .unwrap_or(clusters.len());
+            state.clusters.end = end;
+            state.runs.end = self
+                .layout
+                .runs
+                .partition_point(|run| run.cluster_range.end < end)
+                + 1;
+            state.x = resolve_tabs(self.layout, state.clusters.clone());
+            state.hyphen = None;
+            commit_line(
+                self.layout,
+                &mut self.lines,
+                state,
+                max_advance,
+                alignment,
+                BreakReason::None,
+                true,
+            );
+        }
+        let removed = truncate_last_line(self.layout, &mut self.lines, max_advance, position);
+        self.done = true;
+        finish_lines(self.layout, &mut self.lines);
+        removed
+    }
+
+    /// Consumes the line breaker and finalizes all line computations.
+    pub fn finish(mut self) {
+        finish_lines(self.layout, &mut self.lines);
+    }
+}
+
+impl<'a, B: Brush> Drop for BreakLines<'a, B> {
+    fn drop(&mut self) {
+        finish_layout(self.layout, &mut self.lines);
+    }
+}
+
+/// Computes final metrics, alignment and ordering for all committed lines.
+pub(super) fn finish_lines<B: Brush>(layout: &mut LayoutData<B>, lines: &mut LineLayout) {
+    layout.visual_map.clear();
+    layout.visual_map.resize(layout.clusters.len(), u32::MAX);
+    layout.logical_map.clear();
+    layout.logical_map.resize(layout.clusters.len(), u32::MAX);
+    let mut y = 0.;
+    for line in &mut lines.lines {
+        let is_rtl = line.base_level & 1 != 0;
+        let run_base = line.run_range.start;
+        let run_count = line.run_range.end - run_base;
+        line.metrics.offset = 0.;
+        compute_line_metrics(layout, line, &mut lines.runs[line.run_range.clone()]);
+        let needs_reorder = lines.runs[line.run_range.clone()]
+            .iter()
+            .any(|run| run.bidi_level != 0);
+        if needs_reorder && run_count > 1 {
+            reorder_runs(&mut lines.runs[line.run_range.clone()]);
+        }
+        build_cluster_maps(layout, line, &lines.runs[line.run_range.clone()]);
+        // Trailing whitespace is at the logical end of the line, which is
+        // not necessarily in the visually last run.
+        let trailing_whitespace = lines.runs[line.run_range.clone()]
+            .iter()
+            .find(|run| {
+                !run.cluster_range.is_empty() && run.cluster_range.end == line.cluster_range.end
+            })
+            .map(|run| {
+                layout.clusters[run.cluster_range.clone()]
                     .iter()
-                    .map(|c| c.advance)
-                    .sum();
-                let line_height = line_run.compute_line_height(&self.layout);
-                let run = &self.layout.runs[line_run.run_index];
-                line.metrics.ascent = line.metrics.ascent.max(run.metrics.ascent * line_height);
-                line.metrics.descent = line.metrics.descent.max(run.metrics.descent * line_height);
-                line.metrics.leading = line.metrics.leading.max(run.metrics.leading * line_height);
-                have_metrics = true;
-            }
-            if needs_reorder && run_count > 1 {
-                reorder_runs(&mut self.lines.runs[line.run_range.clone()]);
-            }
-            let trailing_whitespace = if !line.run_range.is_empty() {
-                let last_run = &self.lines.runs[line.run_range.end - 1];
-                if !last_run.cluster_range.is_empty() {
-                    let cluster = &self.layout.clusters[last_run.cluster_range.end - 1];
-                    if cluster.info.whitespace().is_space_or_nbsp() {
-                        cluster.advance
-                    } else {
-                        0.
-                    }
+                    .rev()
+                    .take_while(|cluster| cluster.info.whitespace().is_space_or_nbsp())
+                    .map(|cluster| cluster.advance)
+                    .sum()
+            })
+            .unwrap_or(0.);
+        line.metrics.trailing_whitespace = trailing_whitespace;
+        // Start and end alignment are relative to the base direction.
+        let alignment = match (line.alignment, is_rtl) {
+            (Alignment::Start, true) => Alignment::End,
+            (Alignment::End, true) => Alignment::Start,
+            (alignment, _) => alignment,
+        };
+        if alignment != Alignment::Start
+            && line.max_advance.is_finite()
+            && line.max_advance < f32::MAX
+        {
+            let extra = line.max_advance - line.metrics.advance + trailing_whitespace;
+            if extra > 0. {
+                // The trailing whitespace of a right-to-left line is on the
+                // left, where it hangs past the aligned content.
+                let hang = if is_rtl { trailing_whitespace } else { 0. };
+                let offset = if alignment == Alignment::Middle {
+                    extra * 0.5
                 } else {
-                    0.
-                }
-            } else {
-                0.
-            };
-            line.metrics.trailing_whitespace = trailing_whitespace;
-            if line.alignment != Alignment::Start
-                && line.max_advance.is_finite()
-                && line.max_advance < f32::MAX
-            {
-                let extra = line.max_advance - line.metrics.advance + trailing_whitespace;
-                if extra > 0. {
-                    let offset = if line.alignment == Alignment::Middle {
-                        extra * 0.5
+                    extra
+                } - hang;
+                if alignment == Alignment::Justified {
+                    if line.break_reason == BreakReason::None || line.num_spaces == 0 {
+                        // Lines that are not justified are aligned to the
+                        // start.
+                        if is_rtl {
+                            line.metrics.offset = offset;
+                        }
                     } else {
-                        extra
-                    };
-                    if line.alignment == Alignment::Justified {
-                        if line.break_reason != BreakReason::None && line.num_spaces != 0 {
-                            let adjustment = extra / line.num_spaces as f32;
-                            let mut applied = 0;
-                            for line_run in &self.lines.runs[line.run_range.clone()] {
-                                if line_run.bidi_level & 1 != 0 {
-                                    for cluster in self.layout.clusters
-                                        [line_run.cluster_range.clone()]
+                        line.metrics.offset = -hang;
+                        let adjustment = extra / line.num_spaces as f32;
+                        let mut applied = 0;
+                        for line_run in &lines.runs[line.run_range.clone()] {
+                            if line_run.bidi_level & 1 != 0 {
+                                for cluster in layout.clusters[line_run.cluster_range.clone()]
                                     .iter_mut()
                                     .rev()
-                                    {
-                                        if applied == line.num_spaces {
-                                            break;
-                                        }
-                                        if cluster.info.whitespace().is_space_or_nbsp() {
-                                            cluster.advance += adjustment;
-                                            applied += 1;
-                                        }
+                                {
+                                    if applied == line.num_spaces {
+                                        break;
                                     }
-                                } else {
-                                    for cluster in self.layout.clusters
-                                        [line_run.cluster_range.clone()]
-                                    .iter_mut()
-                                    {
-                                        if applied == line.num_spaces {
-                                            break;
-                                        }
-                                        if cluster.info.whitespace().is_space_or_nbsp() {
-                                            cluster.advance += adjustment;
-                                            applied += 1;
-                                        }
+                                    if cluster.info.whitespace().is_space_or_nbsp() {
+                                        cluster.advance += adjustment;
+                                        applied += 1;
+                                    }
+                                }
+                            } else {
+                                for cluster in
+                                    layout.clusters[line_run.cluster_range.clone()].iter_mut()
+                                {
+                                    if applied == line.num_spaces {
+                                        break;
+                                    }
+                                    if cluster.info.whitespace().is_space_or_nbsp() {
+                                        cluster.advance += adjustment;
+                                        applied += 1;
                                     }
                                 }
                             }
                         }
-                    } else {
-                        line.metrics.offset = offset;
                     }
+                } else {
+                    line.metrics.offset = offset;
                 }
             }
-            if !have_metrics {
-                // Line consisting entirely of whitespace?
-                if !line.run_range.is_empty() {
-                    let line_run = &self.lines.runs[line.run_range.start];
-                    let run = &self.layout.runs[line_run.run_index];
-                    line.metrics.ascent = run.metrics.ascent;
-                    line.metrics.descent = run.metrics.descent;
-                    line.metrics.leading = run.metrics.leading;
+        }
+        let above = (line.metrics.ascent + line.metrics.leading * 0.5).round();
+        let below = (line.metrics.descent + line.metrics.leading * 0.5).round();
+        line.metrics.baseline = y + above;
+        y = line.metrics.baseline + below;
+    }
+}
+
+/// Computes the text range and the ascent, descent and leading of a line
+/// from its runs, and the advance of each run. Trailing whitespace does not
+/// contribute to the metrics.
+fn compute_line_metrics<B: Brush>(
+    layout: &LayoutData<B>,
+    line: &mut LineData,
+    runs: &mut [LineRunData],
+) {
+    for run in runs.iter_mut() {
+        run.is_whitespace = true;
+        if run.bidi_level & 1 != 0 {
+            // RTL runs check for "trailing" whitespace at the front.
+            for cluster in layout.clusters[run.cluster_range.clone()].iter() {
+                if cluster.info.is_whitespace() {
+                    run.has_trailing_whitespace = true;
+                } else {
+                    run.is_whitespace = false;
+                    break;
                 }
             }
-            line.metrics.ascent = line.metrics.ascent.round();
-            line.metrics.descent = line.metrics.descent.round();
-            line.metrics.leading = (line.metrics.leading * 0.5).round() * 2.;
-            let above = (line.metrics.ascent + line.metrics.leading * 0.5).round();
-            let below = (line.metrics.descent + line.metrics.leading * 0.5).round();
-            line.metrics.baseline = y + above;
-            y = line.metrics.baseline + below;
+        } else {
+            for cluster in layout.clusters[run.cluster_range.clone()].iter().rev() {
+                if cluster.info.is_whitespace() {
+                    run.has_trailing_whitespace = true;
+                } else {
+                    run.is_whitespace = false;
+                    break;
+                }
+            }
+        }
+    }
+    let is_vertical = layout.writing_mode.is_vertical();
+    line.metrics.ascent = 0.;
+    line.metrics.descent = 0.;
+    line.metrics.leading = 0.;
+    let mut have_metrics = false;
+    line.text_range.start = usize::MAX;
+    line.text_range.end = 0;
+    for line_run in runs.iter_mut().rev() {
+        line.text_range.end = line.text_range.end.max(line_run.text_range.end);
+        line.text_range.start = line.text_range.start.min(line_run.text_range.start);
+        if !have_metrics && line_run.is_whitespace {
+            continue;
         }
+        line_run.advance = layout.clusters[line_run.cluster_range.clone()]
+            .iter()
+            .map(|c| c.advance)
+            .sum::<f32>()
+            + line_run.inserted.map(|g| g.advance).unwrap_or(0.);
+        let line_height = line_run.compute_line_height(layout);
+        let run = &layout.runs[line_run.run_index];
+        let (ascent, descent) = run.line_extents(is_vertical);
+        line.metrics.ascent = line.metrics.ascent.max(ascent * line_height);
+        line.metrics.descent = line.metrics.descent.max(descent * line_height);
+        line.metrics.leading = line.metrics.leading.max(run.metrics.leading * line_height);
+        have_metrics = true;
     }
+    // Make room for ruby annotations over their bases. This is above
+    // the baseline, except in vertical-lr where the over side (the
+    // right) follows the baseline.
+    for ruby in &layout.rubies {
+        if ruby.text_range.start < line.text_range.start
+            || ruby.text_range.start >= line.text_range.end
+        {
+            continue;
+        }
+        // Round up so that the line box still contains the annotation
+        // after its metrics are rounded.
+        let extent = (base_extent(layout, runs, ruby) + ruby.layout.data.height).ceil();
+        if layout.writing_mode == WritingMode::VerticalLr {
+            line.metrics.descent = line.metrics.descent.max(extent);
+        } else {
+            line.metrics.ascent = line.metrics.ascent.max(extent);
+        }
+    }
+    if !have_metrics {
+        // Line consisting entirely of whitespace?
+        if let Some(line_run) = runs.first() {
+            let run = &layout.runs[line_run.run_index];
+            let (ascent, descent) = run.line_extents(is_vertical);
+            line.metrics.ascent = ascent;
+            line.metrics.descent = descent;
+            line.metrics.leading = run.metrics.leading;
+        }
+    }
+    line.metrics.ascent = line.metrics.ascent.round();
+    line.metrics.descent = line.metrics.descent.round();
+    line.metrics.leading = (line.metrics.leading * 0.5).round() * 2.;
 }
 
-impl<'a, B: Brush> Drop for BreakLines<'a, B> {
-    fn drop(&mut self) {
-        let mut width = 0f32;
-        let mut full_width = 0f32;
-        let mut height = 0f32;
-        for line in &self.lines.lines {
-            width = width.max(line.metrics.advance - line.metrics.trailing_whitespace);
-            full_width = full_width.max(line.metrics.advance);
-            height += line.metrics.size();
-        }
-        self.layout.width = width;
-        self.layout.full_width = full_width;
-        self.layout.height = height;
-        self.lines.swap(self.layout);
+/// Returns the maximum extent from the baseline to the over side of the
+/// line runs that contain the base text of the specified ruby annotation.
+/// Vertical lines are symmetric around their central baseline.
+pub(crate) fn base_extent<B: Brush>(
+    layout: &LayoutData<B>,
+    runs: &[LineRunData],
+    ruby: &RubyData<B>,
+) -> f32 {
+    let is_vertical = layout.writing_mode.is_vertical();
+    runs.iter()
+        .filter(|run| {
+            run.text_range.start < ruby.text_range.end && run.text_range.end > ruby.text_range.start
+        })
+        .map(|run| layout.runs[run.run_index].line_extents(is_vertical).0)
+        .fold(0., f32::max)
+}
+
+/// Computes the size of the layout and moves the committed lines into it.
+pub(super) fn finish_layout<B: Brush>(layout: &mut LayoutData<B>, lines: &mut LineLayout) {
+    let mut width = 0f32;
+    let mut full_width = 0f32;
+    let mut height = 0f32;
+    for line in &lines.lines {
+        width = width.max(line.metrics.advance - line.metrics.trailing_whitespace);
+        full_width = full_width.max(line.metrics.advance);
+        height += line.metrics.size();
     }
+    layout.width = width;
+    layout.full_width = full_width;
+    layout.height = height;
+    lines.swap(layout);
 }
 
 /// Removes previous justification applied to clusters.
-fn unjustify<B: Brush>(layout: &mut LayoutData<B>) {
+pub(super) fn unjustify<B: Brush>(layout: &mut LayoutData<B>) {
     for line in &layout.lines {
         if line.alignment == Alignment::Justified
             && line.max_advance.is_finite()
@@ -466,12 +669,14 @@ fn unjustify<B: Brush>(layout: &mut LayoutData<B>) {
 }
 
 #[derive(Clone, Default)]
-struct LineState {
-    x: f32,
-    runs: Range<usize>,
-    clusters: Range<usize>,
-    skip_mandatory_break: bool,
-    num_spaces: usize,
+pub(super) struct LineState {
+    pub x: f32,
+    pub runs: Range<usize>,
+    pub clusters: Range<usize>,
+    pub skip_mandatory_break: bool,
+    pub num_spaces: usize,
+    /// Hyphen inserted at the end of the line.
+    pub hyphen: Option<InsertedGlyph>,
 }
 
 #[derive(Clone, Default)]
@@ -491,7 +696,7 @@ struct BreakerState {
     prev_boundary: Option<PrevBoundaryState>,
 }
 
-fn commit_line<B: Brush>(
+pub(super) fn commit_line<B: Brush>(
     layout: &LayoutData<B>,
     lines: &mut LineLayout,
     state: &mut LineState,
@@ -540,6 +745,7 @@ fn commit_line<B: Brush>(
             has_trailing_whitespace: false,
             cluster_range,
             text_range,
+            inserted: None,
             advance: 0.,
         };
         lines.runs.push(line_run);
@@ -549,18 +755,31 @@ fn commit_line<B: Brush>(
         return false;
     }
     let mut num_spaces = state.num_spaces;
-    if break_reason == BreakReason::Regular {
+    if break_reason == BreakReason::Regular && state.hyphen.is_none() {
         num_spaces = num_spaces.saturating_sub(1);
     }
+    let mut advance = state.x;
+    if let Some(hyphen) = state.hyphen.take() {
+        lines.runs[runs_end - 1].inserted = Some(hyphen);
+        advance += hyphen.advance;
+    }
+    let base_level = layout.paragraph_level(lines.runs[runs_start].text_range.start);
+    reset_whitespace_levels(layout, &mut lines.runs, runs_start, base_level);
+    let runs_end = lines.runs.len();
+    let cluster_range =
+        lines.runs[runs_start].cluster_range.start..lines.runs[runs_end - 1].cluster_range.end;
     let mut line = LineData {
         run_range: runs_start..runs_end,
+        cluster_range,
+        base_level,
         max_advance,
         alignment,
         break_reason,
         num_spaces,
         ..Default::default()
     };
-    line.metrics.advance = state.x;
+    line.metrics.advance = advance;
+    compute_line_metrics(layout, &mut line, &mut lines.runs[runs_start..runs_end]);
     lines.lines.push(line);
     state.clusters.start = state.clusters.end;
     state.clusters.end += 1;
@@ -569,6 +788,299 @@ fn commit_line<B: Brush>(
     true
 }
 
+/// Applies rule L1 of the bidi algorithm to the line runs starting at the
+/// specified index. Whitespace at the end of the line and before segment or
+/// paragraph separators is reset to the paragraph level, splitting runs
+/// where the resulting levels differ.
+fn reset_whitespace_levels<B: Brush>(
+    layout: &LayoutData<B>,
+    runs: &mut Vec<LineRunData>,
+    start: usize,
+    base_level: u8,
+) {
+    let line_runs: Vec<LineRunData> = runs.drain(start..).collect();
+    let mut resets = vec![];
+    let mut reset = true;
+    for line_run in line_runs.iter().rev() {
+        for cluster in layout.clusters[line_run.cluster_range.clone()].iter().rev() {
+            reset = match cluster.info.whitespace() {
+                _ if cluster.is_tab() => true,
+                Whitespace::Newline => true,
+                Whitespace::None | Whitespace::NoBreakSpace => false,
+                _ => reset,
+            };
+            resets.push(reset);
+        }
+    }
+    resets.reverse();
+    let mut resets = resets.into_iter();
+    for line_run in line_runs {
+        let run_data = &layout.runs[line_run.run_index];
+        let text_range = |range: &Range<usize>| {
+            if range.is_empty() {
+                return line_run.text_range.clone();
+            }
+            let first = &layout.clusters[range.start];
+            let last = &layout.clusters[range.end - 1];
+            first.text_range(run_data).start..last.text_range(run_data).end
+        };
+        let start = line_run.cluster_range.start;
+        let mut piece = LineRunData {
+            cluster_range: start..start,
+            inserted: None,
+            ..line_run.clone()
+        };
+        for index in line_run.cluster_range.clone() {
+            let level = if resets.next().unwrap_or(false) {
+                base_level
+            } else {
+                line_run.bidi_level
+            };
+            if level != piece.bidi_level && !piece.cluster_range.is_empty() {
+                piece.text_range = text_range(&piece.cluster_range);
+                let next = LineRunData {
+                    cluster_range: index..index,
+                    ..piece.clone()
+                };
+                runs.push(core::mem::replace(&mut piece, next));
+            }
+            piece.bidi_level = level;
+            piece.cluster_range.end = index + 1;
+        }
+        piece.text_range = text_range(&piece.cluster_range);
+        piece.inserted = line_run.inserted;
+        runs.push(piece);
+    }
+}
+
+/// Resolves the advances of the tabs in the specified range of clusters,
+/// measured from the start of the range or from the last mandatory break
+/// before them. Returns the advance of the clusters that follow the last
+/// start.
+pub(super) fn resolve_tabs<B: Brush>(layout: &mut LayoutData<B>, clusters: Range<usize>) -> f32 {
+    let mut x = 0.;
+    for index in clusters {
+        if layout.clusters[index].boundary == Boundary::Mandatory {
+            x = 0.;
+        }
+        if let Some(advance) = tab_advance(layout, index, x) {
+            layout.clusters[index].advance = advance;
+        }
+        x += layout.clusters[index].advance;
+    }
+    x
+}
+
+/// Returns the advance of the cluster at the specified index if it is a tab
+/// with tab stops in its style, given the position of the tab on the line.
+fn tab_advance<B: Brush>(layout: &LayoutData<B>, index: usize, x: f32) -> Option<f32> {
+    let cluster = &layout.clusters[index];
+    if !cluster.is_tab() {
+        return None;
+    }
+    let tabs = layout
+        .styles
+        .get(cluster.style_index as usize)?
+        .tab_stops
+        .as_ref()?;
+    let stop = match tabs.stops.iter().find(|stop| stop.position > x) {
+        Some(stop) => *stop,
+        None if tabs.interval > 0. => TabStop {
+            position: ((x / tabs.interval).floor() + 1.) * tabs.interval,
+            alignment: TabAlignment::Left,
+        },
+        None => return Some(0.),
+    };
+    // Width of the text following the tab that precedes the stop.
+    let width = |decimal: bool| {
+        let mut width = 0.;
+        for cluster in &layout.clusters[index + 1..] {
+            if cluster.is_tab()
+                || cluster.info.whitespace() == Whitespace::Newline
+                || (decimal && cluster.is_decimal_separator())
+            {
+                break;
+            }
+            width += cluster.advance;
+        }
+        width
+    };
+    let width = match stop.alignment {
+        TabAlignment::Left => 0.,
+        TabAlignment::Center => width(false) * 0.5,
+        TabAlignment::Right => width(false),
+        TabAlignment::Decimal(_) => width(true),
+    };
+    Some((stop.position - x - width).max(0.))
+}
+
+/// Removes clusters from the last line at the specified position until the
+/// line, including an ellipsis, fits within the maximum advance. Returns the
+/// range of text that was removed.
+fn truncate_last_line<B: Brush>(
+    layout: &mut LayoutData<B>,
+    lines: &mut LineLayout,
+    max_advance: f32,
+    position: EllipsisPosition,
+) -> Range<usize> {
+    // Ellipses are only needed here, so they are resolved on demand for the
+    // runs of the last line.
+    if let Some(line) = lines.lines.last() {
+        layout.resolve_ellipses(
+            lines.runs[line.run_range.clone()]
+                .iter()
+                .map(|run| run.run_index),
+        );
+    }
+    let layout = &*layout;
+    let clusters = &layout.clusters;
+    let run_of = |index: usize| {
+        let i = layout
+            .runs
+            .partition_point(|run| run.cluster_range.end <= index);
+        &layout.runs[i.min(layout.runs.len() - 1)]
+    };
+    let offset = |index: usize| {
+        if index < clusters.len() {
+            clusters[index].text_range(run_of(index)).start
+        } else {
+            layout.text_len
+        }
+    };
+    let (start, end, run_start) = match lines.lines.last() {
+        Some(line) if !line.cluster_range.is_empty() => (
+            line.cluster_range.start,
+            line.cluster_range.end,
+            line.run_range.start,
+        ),
+        _ => return layout.text_len..layout.text_len,
+    };
+    // Text that follows the paragraph of the final line is always removed.
+    let tail = offset(end)..layout.text_len;
+    let advance = |range: Range<usize>| clusters[range].iter().map(|c| c.advance).sum::<f32>();
+    if position != EllipsisPosition::End && advance(start..end) <= max_advance {
+        return tail;
+    }
+    let is_space = |index: usize| clusters[index].info.is_whitespace();
+    let is_component = |index: usize| index < end && clusters[index].is_ligature_component();
+    // Clusters in the range a..b are removed and replaced by the ellipsis.
+    let (mut a, mut b) = match position {
+        EllipsisPosition::Start => (start, start),
+        EllipsisPosition::Middle => ((start + end) / 2, (start + end) / 2),
+        EllipsisPosition::End => (end, end),
+    };
+    loop {
+        // Never split a ligature and drop whitespace next to the ellipsis.
+        while a > start && (is_component(a) || is_space(a - 1)) {
+            a -= 1;
+        }
+        while b < end && b > a && (is_component(b) || is_space(b)) {
+            b += 1;
+        }
+        if a == start && b == end {
+            break;
+        }
+        let ellipsis_run = run_of(if a > start { a - 1 } else { b });
+        let left = advance(start..a);
+        let right = advance(b..end);
+        let ellipsis = ellipsis_run.ellipsis.map(|g| g.advance).unwrap_or(0.);
+        if left + right + ellipsis <= max_advance {
+            break;
+        }
+        match position {
+            EllipsisPosition::Start => b += 1,
+            EllipsisPosition::Middle if b == end || (a > start && left >= right) => a -= 1,
+            EllipsisPosition::Middle => b += 1,
+            EllipsisPosition::End => a -= 1,
+        }
+    }
+    let line_runs: Vec<LineRunData> = lines.runs.drain(run_start..).collect();
+    let mut line_advance = 0.;
+    for line_run in line_runs {
+        let run_data = &layout.runs[line_run.run_index];
+        let text_range = |range: &Range<usize>| {
+            if range.is_empty() {
+                let offset = offset(range.start).min(line_run.text_range.end);
+                return offset..offset;
+            }
+            let first = &clusters[range.start];
+            let last = &clusters[range.end - 1];
+            first.text_range(run_data).start..last.text_range(run_data).end
+        };
+        let range = line_run.cluster_range.clone();
+        let left = range.start..range.end.min(a);
+        let right = range.start.max(b)..range.end;
+        let mut pieces = vec![];
+        if !left.is_empty() {
+            let inserted = if a > start && left.end == a {
+                run_data.ellipsis
+            } else if left.end == range.end {
+                line_run.inserted
+            } else {
+                None
+            };
+            pieces.push((left, inserted));
+        }
+        // With nothing before it, the ellipsis is carried by an empty
+        // piece preceding the remaining content.
+        if a == start && (range.contains(&b) || (b == end && range.end == end)) {
+            pieces.push((b..b, run_data.ellipsis));
+        }
+        if !right.is_empty() {
+            pieces.push((right, line_run.inserted));
+        }
+        for (cluster_range, inserted) in pieces {
+            line_advance +=
+                advance(cluster_range.clone()) + inserted.map(|g| g.advance).unwrap_or(0.);
+            lines.runs.push(LineRunData {
+                text_range: text_range(&cluster_range),
+                cluster_range,
+                inserted,
+                ..line_run.clone()
+            });
+        }
+    }
+    let line = lines.lines.last_mut().unwrap();
+    line.run_range.end = lines.runs.len();
+    if position == EllipsisPosition::End {
+        line.cluster_range.end = a;
+    }
+    line.break_reason = BreakReason::None;
+    line.num_spaces = 0;
+    line.metrics.advance = line_advance;
+    let removed_end = if position == EllipsisPosition::End || !tail.is_empty() {
+        layout.text_len
+    } else {
+        offset(b)
+    };
+    offset(a)..removed_end
+}
+
+/// Builds the maps between logical and visual cluster indices for a line
+/// whose runs are in visual order. Only the clusters of the runs are mapped,
+/// so clusters removed by truncation have no entries.
+fn build_cluster_maps<B: Brush>(layout: &mut LayoutData<B>, line: &LineData, runs: &[LineRunData]) {
+    let base = line.cluster_range.start;
+    for index in line.cluster_range.clone() {
+        layout.visual_map[index] = u32::MAX;
+        layout.logical_map[index] = u32::MAX;
+    }
+    let mut visual = 0;
+    for run in runs {
+        let range = run.cluster_range.clone();
+        let mut push = |index: usize| {
+            layout.visual_map[base + visual] = (index - base) as u32;
+            layout.logical_map[index] = visual as u32;
+            visual += 1;
+        };
+        if run.bidi_level & 1 != 0 {
+            range.rev().for_each(&mut push);
+        } else {
+            range.for_each(&mut push);
+        }
+    }
+}
+
 fn reorder_runs(runs: &mut [LineRunData]) {
     let mut max_level = 0;
     let mut lowest_odd_level = 255;
@@ -603,3 +1115,314 @@ fn reorder_runs(runs: &mut [LineRunData]) {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::layout::{Alignment, BaseDirection, EllipsisPosition, Layout, Line, Truncation};
+    use crate::style::{StyleProperty, TabAlignment, TabStop, TabStops};
+    use crate::testing;
+
+    /// Returns the logical cluster indices of the line in visual order.
+    fn visual_order(line: &Line<[u8; 4]>) -> Vec<usize> {
+        (0..line.cluster_count())
+            .map(|i| line.visual_to_logical(i).unwrap())
+            .collect()
+    }
+
+    fn break_lines(text: &str, fit: &str) -> Layout<[u8; 4]> {
+        let mut measure = testing::layout(fit);
+        measure.break_all_lines(None, Alignment::Start);
+        let mut layout = testing::layout(text);
+        layout.break_all_lines(Some(measure.width() + 1.), Alignment::Start);
+        layout
+    }
+
+    #[test]
+    fn right_to_left_runs_are_reordered() {
+        let layout = break_lines(
+            "abc \u{5d0}\u{5d1}\u{5d2} def",
+            "abc \u{5d0}\u{5d1}\u{5d2} def",
+        );
+        let line = layout.get(0).unwrap();
+        assert_eq!(visual_order(&line), vec![0, 1, 2, 3, 6, 5, 4, 7, 8, 9, 10]);
+        for (visual, logical) in visual_order(&line).into_iter().enumerate() {
+            assert_eq!(line.logical_to_visual(logical), Some(visual));
+        }
+    }
+
+    #[test]
+    fn trailing_whitespace_is_reset_to_paragraph_level() {
+        // The space between the Hebrew words is resolved to level 1, but
+        // moves to the end of the line when it trails the line.
+        let layout = break_lines(
+            "abc \u{5d0}\u{5d1}\u{5d2} \u{5d3}\u{5d4}\u{5d5}",
+            "abc \u{5d0}\u{5d1}\u{5d2}",
+        );
+        assert_eq!(layout.len(), 2);
+        let line = layout.get(0).unwrap();
+        assert_eq!(visual_order(&line), vec![0, 1, 2, 3, 6, 5, 4, 7]);
+    }
+
+    #[test]
+    fn trailing_whitespace_is_measured_at_the_logical_end() {
+        let mut space = testing::layout("a b");
+        space.break_all_lines(None, Alignment::Start);
+        let mut ab = testing::layout("ab");
+        ab.break_all_lines(None, Alignment::Start);
+        let space = space.width() - ab.width();
+        let mut measure = testing::layout("abc \u{5d0}\u{5d1}\u{5d2}");
+        measure.break_all_lines(None, Alignment::Start);
+        let max_advance = measure.width() + 1.;
+        let mut layout = testing::layout_with("abc \u{5d0}\u{5d1}\u{5d2} def", |builder| {
+            builder.set_base_direction(BaseDirection::RightToLeft);
+        });
+        layout.break_all_lines(Some(max_advance), Alignment::Start);
+        assert_eq!(layout.len(), 2);
+        let line = layout.get(0).unwrap();
+        assert!(line.is_rtl());
+        let metrics = line.metrics();
+        // The trailing space is in the right-to-left run on the left, not in
+        // the visually last run.
+        assert!((metrics.trailing_whitespace - space).abs() < 0.01);
+        // The visible content is aligned to the right edge.
+        assert!((metrics.offset + metrics.advance - max_advance).abs() < 0.01);
+    }
+
+    #[test]
+    fn decorations_exclude_trailing_whitespace_of_right_to_left_lines() {
+        let text = "\u{5d0}\u{5d1}\u{5d2} \u{5d3}\u{5d4}\u{5d5}";
+        let mut measure = testing::layout("\u{5d0}\u{5d1}\u{5d2}");
+        measure.break_all_lines(None, Alignment::Start);
+        let mut layout = testing::layout_with(text, |builder| {
+            builder.push_default(&StyleProperty::Underline(true));
+        });
+        layout.break_all_lines(Some(measure.width() + 1.), Alignment::Start);
+        assert_eq!(layout.len(), 2);
+        let line = layout.get(0).unwrap();
+        assert!(line.is_rtl());
+        let metrics = line.metrics();
+        assert!(metrics.trailing_whitespace > 0.);
+        let decorations = line.decorations();
+        assert_eq!(decorations.len(), 1);
+        let rect = decorations[0].rect;
+        assert!((rect.x - metrics.offset - metrics.trailing_whitespace).abs() < 0.01);
+        assert!((rect.x + rect.width - metrics.offset - metrics.advance).abs() < 0.01);
+    }
+
+    #[test]
+    fn decorations_exclude_trailing_whitespace_of_mixed_right_to_left_lines() {
+        let text = "\u{5d0}\u{5d1}\u{5d2} abc def";
+        let mut measure = testing::layout("\u{5d0}\u{5d1}\u{5d2} abc");
+        measure.break_all_lines(None, Alignment::Start);
+        let mut layout = testing::layout_with(text, |builder| {
+            builder.set_base_direction(BaseDirection::RightToLeft);
+            builder.push_default(&StyleProperty::Underline(true));
+        });
+        layout.break_all_lines(Some(measure.width() + 1.), Alignment::Start);
+        assert_eq!(layout.len(), 2);
+        let line = layout.get(0).unwrap();
+        assert!(line.is_rtl());
+        assert!(line.runs().any(|run| !run.is_rtl()));
+        let metrics = line.metrics();
+        assert!(metrics.trailing_whitespace > 0.);
+        let decorations = line.decorations();
+        assert_eq!(decorations.len(), 1);
+        let rect = decorations[0].rect;
+        assert!((rect.x - metrics.offset - metrics.trailing_whitespace).abs() < 0.01);
+        assert!((rect.x + rect.width - metrics.offset - metrics.advance).abs() < 0.01);
+    }
+
+    #[test]
+    fn truncated_lines_map_visible_clusters() {
+        let text = "The quick brown fox jumps over the lazy dog";
+        for &ellipsis in &[EllipsisPosition::Start, EllipsisPosition::Middle] {
+            let mut layout = testing::layout(text);
+            let truncation = Truncation {
+                max_lines: Some(1),
+                ellipsis,
+                ..Default::default()
+            };
+            let removed =
+                layout.break_all_lines_truncated(Some(100.), Alignment::Start, truncation);
+            let removed = removed.unwrap();
+            let line = layout.get(0).unwrap();
+            let count = line.cluster_count();
+            assert_eq!(count, text.len() - removed.len());
+            let order = visual_order(&line);
+            let mut sorted = order.clone();
+            sorted.sort_unstable();
+            sorted.dedup();
+            assert_eq!(sorted.len(), count);
+            assert_eq!(line.visual_to_logical(count), None);
+            for logical in 0..text.len() {
+                let expected = order.iter().position(|&i| i == logical);
+                assert_eq!(line.logical_to_visual(logical), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn inserted_glyphs_are_resolved_on_demand() {
+        let layout = testing::layout("hyphen\u{ad}ation");
+        assert!(layout.data.runs[0].hyphen.is_some());
+        let mut layout = testing::layout("The quick brown fox");
+        assert!(layout
+            .data
+            .runs
+            .iter()
+            .all(|run| run.hyphen.is_none() && run.ellipsis.is_none()));
+        let truncation = Truncation {
+            max_lines: Some(1),
+            ..Default::default()
+        };
+        let removed = layout.break_all_lines_truncated(Some(60.), Alignment::Start, truncation);
+        assert!(removed.is_some());
+        assert!(layout.data.runs[0].ellipsis.is_some());
+        assert!(layout.data.runs.iter().all(|run| run.hyphen.is_none()));
+    }
+
+    #[test]
+    fn truncation_stops_at_limits() {
+        let text = "The quick brown fox jumps over the lazy dog";
+        let mut full = testing::layout(text);
+        full.break_all_lines(Some(100.), Alignment::Start);
+        assert!(full.len() > 2);
+        let line_size = full.get(0).unwrap().metrics().size();
+        let limits = [
+            (Some(2), None),
+            (None, Some(line_size * 2.5)),
+            (Some(5), Some(line_size * 2.)),
+        ];
+        for &(max_lines, max_height) in limits.iter() {
+            let mut layout = testing::layout(text);
+            let truncation = Truncation {
+                max_lines,
+                max_height,
+                ellipsis: EllipsisPosition::End,
+            };
+            let removed = layout
+                .break_all_lines_truncated(Some(100.), Alignment::Start, truncation)
+                .unwrap();
+            assert_eq!(layout.len(), 2);
+            assert_eq!(layout.truncated_range(), Some(removed.clone()));
+            assert_eq!(removed.end, text.len());
+            let last = layout.get(1).unwrap();
+            assert!(last.metrics().advance <= 100.);
+            assert!(last.runs().last().unwrap().inserted_glyph().is_some());
+        }
+    }
+
+    #[test]
+    fn truncation_of_fitting_text_returns_none() {
+        let text = "The quick brown fox";
+        for &ellipsis in &[
+            EllipsisPosition::Start,
+            EllipsisPosition::Middle,
+            EllipsisPosition::End,
+        ] {
+            let mut layout = testing::layout(text);
+            let truncation = Truncation {
+                max_lines: Some(2),
+                ellipsis,
+                ..Default::default()
+            };
+            let removed = layout.break_all_lines_truncated(None, Alignment::Start, truncation);
+            assert_eq!(removed, None);
+            assert_eq!(layout.truncated_range(), None);
+            assert_eq!(layout.len(), 1);
+        }
+        let mut layout = testing::layout("abc\ndef");
+        let truncation = Truncation {
+            max_lines: Some(1),
+            ..Default::default()
+        };
+        let removed = layout.break_all_lines_truncated(None, Alignment::Start, truncation);
+        assert_eq!(removed, Some(3..7));
+    }
+
+    #[test]
+    fn start_and_middle_truncation_stops_at_mandatory_break() {
+        for &ellipsis in &[EllipsisPosition::Start, EllipsisPosition::Middle] {
+            let truncation = Truncation {
+                max_lines: Some(1),
+                ellipsis,
+                ..Default::default()
+            };
+            // The paragraph fits, so only the following text is removed.
+            let mut layout = testing::layout("abc\ndef");
+            let removed = layout.break_all_lines_truncated(None, Alignment::Start, truncation);
+            assert_eq!(removed, Some(4..7));
+            assert_eq!(layout.len(), 1);
+            assert_eq!(layout.get(0).unwrap().text_range(), 0..4);
+            // The end of the paragraph stays visible when it is shortened.
+            let text = "The quick brown fox\njumps";
+            let mut layout = testing::layout(text);
+            let removed = layout
+                .break_all_lines_truncated(Some(80.), Alignment::Start, truncation)
+                .unwrap();
+            assert!(removed.start < text.find('x').unwrap());
+            assert_eq!(removed.end, text.len());
+            assert_eq!(layout.len(), 1);
+            let line = layout.get(0).unwrap();
+            assert_eq!(line.text_range().end, text.find('\n').unwrap() + 1);
+            assert!(line.metrics().advance <= 80.);
+        }
+    }
+
+    #[test]
+    fn tabs_advance_to_stops_of_wrapped_lines() {
+        let text = "aaaa bbbb\tc";
+        let tab = text.find('\t').unwrap();
+        let width = |text: &str| {
+            let mut layout = testing::layout(text);
+            layout.break_all_lines(None, Alignment::Start);
+            layout.width()
+        };
+        // The tab is 10 units from the first stop on the wrapped line, but
+        // not in the unwrapped text.
+        let interval = width("bbbb") + 10.;
+        let max_advance = width("aaaa bbbb") - 1.;
+        let mut layout = testing::layout_with(text, |builder| {
+            builder.push_default(&StyleProperty::TabStops(Some(TabStops::uniform(interval))));
+        });
+        for &optimal in &[false, true] {
+            if optimal {
+                layout.break_all_lines_optimal(Some(max_advance), Alignment::Start);
+            } else {
+                layout.break_all_lines(Some(max_advance), Alignment::Start);
+            }
+            assert_eq!(layout.len(), 2);
+            assert_eq!(layout.get(1).unwrap().text_range(), 5..text.len());
+            assert!((layout.data.clusters[tab].advance - 10.).abs() < 1e-3);
+        }
+        // Without wrapping, the tab is measured from the start of the text.
+        layout.break_all_lines(None, Alignment::Start);
+        let x = width("aaaa bbbb");
+        let expected = interval * ((x / interval).floor() + 1.) - x;
+        assert!((layout.data.clusters[tab].advance - expected).abs() < 1e-3);
+    }
+
+    #[test]
+    fn tab_stops_are_sorted() {
+        let stops = [
+            TabStop {
+                position: 60.,
+                alignment: TabAlignment::Left,
+            },
+            TabStop {
+                position: 30.,
+                alignment: TabAlignment::Left,
+            },
+        ];
+        let mut prefix = testing::layout("a");
+        prefix.break_all_lines(None, Alignment::Start);
+        let mut layout = testing::layout_with("a\tb\tc", |builder| {
+            builder.push_default(&StyleProperty::TabStops(Some(TabStops::new(&stops, 0.))));
+        });
+        layout.break_all_lines(None, Alignment::Start);
+        let clusters = &layout.data.clusters;
+        assert!((clusters[1].advance - (30. - prefix.width())).abs() < 1e-3);
+        let x: f32 = clusters[..4].iter().map(|c| c.advance).sum();
+        assert!((x - 60.).abs() < 1e-3);
+    }
+}
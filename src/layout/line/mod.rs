@@ -1,6 +1,8 @@
 use super::*;
+use crate::util::nearly_eq;
 
 pub mod greedy;
+pub mod optimal;
 
 impl<'a, B: Brush> Line<'a, B> {
     /// Returns the metrics for the line.
@@ -13,6 +15,50 @@ impl<'a, B: Brush> Line<'a, B> {
         self.data.text_range.clone()
     }
 
+    /// Returns true if the paragraph containing the line has a
+    /// right-to-left base direction.
+    pub fn is_rtl(&self) -> bool {
+        self.data.base_level & 1 != 0
+    }
+
+    /// Returns the number of clusters in the line. This excludes clusters
+    /// removed by truncation.
+    pub fn cluster_count(&self) -> usize {
+        self.layout.line_runs[self.data.run_range.clone()]
+            .iter()
+            .map(|run| run.cluster_range.len())
+            .sum()
+    }
+
+    /// Returns the logical index of the cluster at the specified visual
+    /// position. Both are relative to the start of the line.
+    pub fn visual_to_logical(&self, visual_index: usize) -> Option<usize> {
+        if visual_index >= self.data.cluster_range.len() {
+            return None;
+        }
+        let index = self.data.cluster_range.start + visual_index;
+        self.layout
+            .visual_map
+            .get(index)
+            .filter(|&&i| i != u32::MAX)
+            .map(|&i| i as usize)
+    }
+
+    /// Returns the visual position of the cluster at the specified logical
+    /// index. Both are relative to the start of the line. Returns `None`
+    /// for clusters removed by truncation.
+    pub fn logical_to_visual(&self, logical_index: usize) -> Option<usize> {
+        if logical_index >= self.data.cluster_range.len() {
+            return None;
+        }
+        let index = self.data.cluster_range.start + logical_index;
+        self.layout
+            .logical_map
+            .get(index)
+            .filter(|&&i| i != u32::MAX)
+            .map(|&i| i as usize)
+    }
+
     /// Returns the number of runs in the line.
     pub fn len(&self) -> usize {
         self.data.run_range.len()
@@ -48,14 +94,150 @@ impl<'a, B: Brush> Line<'a, B> {
         })
     }
 
+    /// Returns an iterator over the ruby annotations whose base text starts
+    /// on the line.
+    pub fn ruby_annotations(&self) -> impl Iterator<Item = RubyAnnotation<'a, B>> + 'a + Clone {
+        let line = self.clone();
+        let text_range = self.data.text_range.clone();
+        self.layout
+            .rubies
+            .iter()
+            .filter(move |ruby| text_range.contains(&ruby.text_range.start))
+            .map(move |ruby| {
+                // Compute the visual extent of the base.
+                let mut x = line.data.metrics.offset;
+                let mut start = f32::MAX;
+                let mut end = f32::MIN;
+                for run in line.runs() {
+                    let inserted = run.inserted_glyph().map(|g| g.advance).unwrap_or(0.);
+                    if run.is_rtl() {
+                        x += inserted;
+                    }
+                    for cluster in run.visual_clusters() {
+                        let range = cluster.text_range();
+                        let advance = cluster.advance();
+                        if range.start >= ruby.text_range.start && range.end <= ruby.text_range.end
+                        {
+                            start = start.min(x);
+                            end = end.max(x + advance);
+                        }
+                        x += advance;
+                    }
+                    if !run.is_rtl() {
+                        x += inserted;
+                    }
+                }
+                if start > end {
+                    start = line.data.metrics.offset;
+                    end = start;
+                }
+                let runs = &line.layout.line_runs[line.data.run_range.clone()];
+                let extent = greedy::base_extent(line.layout, runs, ruby);
+                let baseline = line.data.metrics.baseline;
+                let inline = (start + end - ruby.layout.data.width) * 0.5;
+                let (x, y) = match line.layout.writing_mode {
+                    WritingMode::HorizontalTb => {
+                        (inline, baseline - extent - ruby.layout.data.height)
+                    }
+                    // Vertical annotations are set to the right of the
+                    // central baseline of the column.
+                    WritingMode::VerticalRl => (line.layout.height - baseline + extent, inline),
+                    WritingMode::VerticalLr => (baseline + extent, inline),
+                };
+                RubyAnnotation {
+                    layout: &ruby.layout,
+                    text_range: ruby.text_range.clone(),
+                    x,
+                    y,
+                }
+            })
+    }
+
     /// Returns an iterator over the glyph runs for the line.
     pub fn glyph_runs(&self) -> impl Iterator<Item = GlyphRun<'a, B>> + 'a + Clone {
-        GlyphRunIter {
+        self.items().filter_map(|item| match item {
+            LineItem::GlyphRun(glyph_run) => Some(glyph_run),
+            _ => None,
+        })
+    }
+
+    /// Returns an iterator over the glyph runs and inline boxes for the
+    /// line in visual order.
+    pub fn items(&self) -> impl Iterator<Item = LineItem<'a, B>> + 'a + Clone {
+        LineItemIter {
             line: self.clone(),
             run_index: 0,
             glyph_start: 0,
             offset: 0.,
+            inserted_done: false,
+        }
+    }
+
+    /// Returns the underline and strikethrough rectangles for the line.
+    ///
+    /// Decorations of adjacent glyph runs with the same brush and geometry
+    /// are merged into a single rectangle and hanging trailing whitespace
+    /// is excluded. Trailing whitespace is at the logical end of the line,
+    /// which is the visual end of the run that holds it: the left edge of
+    /// the run if it is right-to-left. Like the line
+    /// metrics, rectangles are expressed along the logical axes of the
+    /// line.
+    pub fn decorations(&self) -> Vec<DecorationRect<'a, B>> {
+        let mut rects: Vec<DecorationRect<'a, B>> = vec![];
+        for glyph_run in self.glyph_runs() {
+            for rect in glyph_run.decorations() {
+                let prev = rects.iter_mut().rev().find(|prev| prev.kind == rect.kind);
+                match prev {
+                    Some(prev)
+                        if prev.brush == rect.brush
+                            && nearly_eq(prev.rect.y, rect.rect.y)
+                            && nearly_eq(prev.rect.height, rect.rect.height)
+                            && (prev.rect.x + prev.rect.width - rect.rect.x).abs() < 0.01 =>
+                    {
+                        prev.rect.width = rect.rect.x + rect.rect.width - prev.rect.x;
+                    }
+                    _ => rects.push(rect),
+                }
+            }
         }
+        // Trailing whitespace is at the logical end of the line, which is at
+        // the visual end of the run that holds it.
+        let metrics = &self.data.metrics;
+        let mut x = metrics.offset;
+        let mut trailing = None;
+        for run in self.runs() {
+            let advance = run.advance();
+            if let Some(data) = run.line_data {
+                if !data.cluster_range.is_empty()
+                    && data.cluster_range.end == self.data.cluster_range.end
+                {
+                    let inserted = data.inserted.map(|g| g.advance).unwrap_or(0.);
+                    let start = if run.is_rtl() {
+                        x + inserted
+                    } else {
+                        x + advance - inserted - metrics.trailing_whitespace
+                    };
+                    trailing = Some(start..start + metrics.trailing_whitespace);
+                }
+            }
+            x += advance;
+        }
+        if let Some(trailing) = trailing.filter(|range| range.end > range.start) {
+            let mut split = Vec::with_capacity(rects.len());
+            for rect in rects {
+                let (start, end) = (rect.rect.x, rect.rect.x + rect.rect.width);
+                let mut before = rect.clone();
+                before.rect.width = end.min(trailing.start) - start;
+                let mut after = rect;
+                after.rect.x = start.max(trailing.end);
+                after.rect.width = end - after.rect.x;
+                split.push(before);
+                split.push(after);
+            }
+            rects = split;
+        }
+        rects.retain(|rect| rect.rect.width > 0.01);
+        rects
     }
 }
 
@@ -85,6 +267,63 @@ impl LineMetrics {
     }
 }
 
+/// Positioned content of a line.
+#[derive(Clone)]
+pub enum LineItem<'a, B: Brush> {
+    /// Sequence of glyphs with the same style.
+    GlyphRun(GlyphRun<'a, B>),
+    /// Inline box.
+    InlineBox(PositionedInlineBox),
+}
+
+/// Inline box with its final position in the layout.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct PositionedInlineBox {
+    /// User defined identifier for the box.
+    pub id: u64,
+    /// Offset to the left edge of the box.
+    pub x: f32,
+    /// Offset to the top edge of the box.
+    pub y: f32,
+    /// Width of the box.
+    pub width: f32,
+    /// Height of the box.
+    pub height: f32,
+}
+
+/// Ruby annotation positioned over its base text: above it in horizontal
+/// text and to its right in vertical text.
+#[derive(Clone)]
+pub struct RubyAnnotation<'a, B: Brush> {
+    layout: &'a Layout<B>,
+    text_range: Range<usize>,
+    x: f32,
+    y: f32,
+}
+
+impl<'a, B: Brush> RubyAnnotation<'a, B> {
+    /// Returns the layout of the annotation text. Glyph positions in this
+    /// layout are relative to the offset of the annotation.
+    pub fn layout(&self) -> &'a Layout<B> {
+        self.layout
+    }
+
+    /// Returns the range of the base text.
+    pub fn text_range(&self) -> Range<usize> {
+        self.text_range.clone()
+    }
+
+    /// Returns the offset to the left edge of the annotation.
+    pub fn x(&self) -> f32 {
+        self.x
+    }
+
+    /// Returns the offset to the top edge of the annotation.
+    pub fn y(&self) -> f32 {
+        self.y
+    }
+}
+
 /// Sequence of fully positioned glyphs with the same style.
 #[derive(Clone)]
 pub struct GlyphRun<'a, B: Brush> {
@@ -95,6 +334,7 @@ pub struct GlyphRun<'a, B: Brush> {
     offset: f32,
     baseline: f32,
     advance: f32,
+    inserted: Option<Glyph>,
 }
 
 impl<'a, B: Brush> GlyphRun<'a, B> {
@@ -123,6 +363,58 @@ impl<'a, B: Brush> GlyphRun<'a, B> {
         self.advance
     }
 
+    /// Returns the horizontal scale factor that should be applied to the
+    /// glyphs when rendered. This is less than 1 for text that was
+    /// compressed to be combined upright in vertical text.
+    pub fn horizontal_scale(&self) -> f32 {
+        self.run.data.combine.map(|c| c.scale).unwrap_or(1.)
+    }
+
+    /// Returns true if the run is part of a vertical layout and its glyphs
+    /// are set sideways. Such glyphs should be rotated 90 degrees clockwise
+    /// around their positions when rendered.
+    pub fn is_sideways(&self) -> bool {
+        self.run.layout.writing_mode.is_vertical() && !self.run.data.upright
+    }
+
+    /// Returns an iterator over the underline and strikethrough rectangles
+    /// for the run, with offsets and thicknesses that are not specified by
+    /// the style resolved from the metrics of the run.
+    pub fn decorations(&self) -> impl Iterator<Item = DecorationRect<'a, B>> + 'a + Clone {
+        let metrics = self.run.data.metrics;
+        let (x, baseline, width) = (self.offset, self.baseline, self.advance);
+        let rect = move |kind, decoration: &'a Decoration<B>, offset: f32, size: f32| {
+            let offset = decoration.offset.unwrap_or(offset);
+            DecorationRect {
+                kind,
+                brush: &decoration.brush,
+                rect: Rect {
+                    x,
+                    y: baseline - offset,
+                    width,
+                    height: decoration.size.unwrap_or(size),
+                },
+            }
+        };
+        let underline = self.style.underline.as_ref().map(move |decoration| {
+            rect(
+                DecorationKind::Underline,
+                decoration,
+                metrics.underline_offset,
+                metrics.underline_size,
+            )
+        });
+        let strikethrough = self.style.strikethrough.as_ref().map(move |decoration| {
+            rect(
+                DecorationKind::Strikethrough,
+                decoration,
+                metrics.strikethrough_offset,
+                metrics.strikethrough_size,
+            )
+        });
+        underline.into_iter().chain(strikethrough)
+    }
+
     /// Returns an iterator over the glyphs in the run.
     pub fn glyphs(&'a self) -> impl Iterator<Item = Glyph> + 'a + Clone {
         self.run
@@ -131,21 +423,109 @@ impl<'a, B: Brush> GlyphRun<'a, B> {
             .flatten()
             .skip(self.glyph_start)
             .take(self.glyph_count)
+            .chain(self.inserted_glyphs())
+    }
+
+    /// Returns an iterator over the clusters whose glyphs belong to the run,
+    /// in visual order. Inserted glyphs belong to no cluster.
+    pub(crate) fn visual_clusters(&'a self) -> impl Iterator<Item = Cluster<'a, B>> + 'a + Clone {
+        let range = self.glyph_start..self.glyph_start + self.glyph_count;
+        let mut glyph_index = 0;
+        self.run.visual_clusters().filter(move |cluster| {
+            let first = glyph_index;
+            glyph_index += cluster.glyphs().count();
+            range.contains(&first)
+        })
+    }
+
+    /// Returns an iterator over the inserted glyph, repeated as many times as
+    /// it was inserted, with the total advance distributed evenly.
+    fn inserted_glyphs(&self) -> impl Iterator<Item = Glyph> + Clone {
+        let count = self
+            .run
+            .line_data
+            .and_then(|data| data.inserted)
+            .map(|inserted| inserted.count.max(1))
+            .unwrap_or(1);
+        self.inserted.into_iter().flat_map(move |mut glyph| {
+            glyph.advance /= count as f32;
+            (0..count).map(move |_| glyph)
+        })
     }
 
     /// Returns an iterator over the fully positioned glyphs in the run.
+    ///
+    /// In vertical layouts, glyph positions are physical coordinates of
+    /// the glyph origins. Upright glyphs are centered on the column and
+    /// sideways glyphs are positioned for rotation.
     pub fn positioned_glyphs(&'a self) -> impl Iterator<Item = Glyph> + 'a + Clone {
         let mut offset = self.offset;
         let baseline = self.baseline;
+        let layout = self.run.layout;
+        let data = self.run.data();
+        let vertical = match layout.writing_mode {
+            WritingMode::HorizontalTb => None,
+            mode => {
+                // Position of the central baseline across columns.
+                let center = if mode == WritingMode::VerticalRl {
+                    layout.height - baseline
+                } else {
+                    baseline
+                };
+                let metrics = if data.upright {
+                    let font = layout.fonts[data.font_index].as_ref();
+                    let coords = &layout.coords[data.coords_range.clone()];
+                    Some(font.glyph_metrics(coords).scale(data.font_size))
+                } else {
+                    None
+                };
+                Some((center, metrics))
+            }
+        };
+        // Combined text is centered in the em box with a horizontal
+        // baseline.
+        let combined = data.combine.map(|combine| {
+            let ascent = data.metrics.ascent;
+            let descent = data.metrics.descent;
+            let y = (data.font_size + ascent - descent) * 0.5;
+            (combine, y)
+        });
         self.run
             .visual_clusters()
             .map(|cluster| cluster.glyphs())
             .flatten()
             .skip(self.glyph_start)
             .take(self.glyph_count)
+            .chain(self.inserted_glyphs())
             .map(move |mut g| {
-                g.x += offset;
-                g.y += baseline;
+                match vertical {
+                    Some((center, _)) if combined.is_some() => {
+                        let (combine, y) = combined.unwrap();
+                        let (scale, width) = (combine.scale, combine.width * combine.scale);
+                        g.x = center - width * 0.5 + (combine.offset + g.x) * scale;
+                        g.y += offset + y;
+                        // The combined cluster occupies a single advance.
+                        return g;
+                    }
+                    None => {
+                        g.x += offset;
+                        g.y += baseline;
+                    }
+                    Some((center, Some(metrics))) => {
+                        // Move from the vertical origin to the horizontal
+                        // origin of the glyph.
+                        g.x += center - metrics.advance_width(g.id) * 0.5;
+                        g.y += offset + metrics.vertical_origin(g.id);
+                    }
+                    Some((center, None)) => {
+                        // Center the em box of the rotated glyph on the
+                        // column.
+                        let shift = (data.metrics.ascent - data.metrics.descent) * 0.5;
+                        let (x, y) = (g.x, g.y);
+                        g.x = center - shift - y;
+                        g.y = offset + x;
+                    }
+                }
                 offset += g.advance;
                 g
             })
@@ -153,19 +533,69 @@ impl<'a, B: Brush> GlyphRun<'a, B> {
 }
 
 #[derive(Clone)]
-struct GlyphRunIter<'a, B: Brush> {
+struct LineItemIter<'a, B: Brush> {
     line: Line<'a, B>,
     run_index: usize,
     glyph_start: usize,
     offset: f32,
+    inserted_done: bool,
 }
 
-impl<'a, B: Brush> Iterator for GlyphRunIter<'a, B> {
-    type Item = GlyphRun<'a, B>;
+impl<'a, B: Brush> LineItemIter<'a, B> {
+    /// Returns a glyph run containing only the inserted glyph.
+    fn inserted_run(&mut self, run: Run<'a, B>, glyph: Glyph) -> Option<LineItem<'a, B>> {
+        self.inserted_done = true;
+        let style = run.layout.styles.get(glyph.style_index())?;
+        // The glyph may come from a fallback font that is carried by a
+        // separate run.
+        let run = match run.line_data.and_then(|data| data.inserted?.font_run) {
+            Some(index) => Run::new(run.layout, &run.layout.fallback_runs[index], run.line_data),
+            None => run,
+        };
+        let offset = self.offset;
+        self.offset += glyph.advance;
+        Some(LineItem::GlyphRun(GlyphRun {
+            run,
+            style,
+            glyph_start: 0,
+            glyph_count: 0,
+            offset: offset + self.line.data.metrics.offset,
+            baseline: self.line.data.metrics.baseline,
+            advance: glyph.advance,
+            inserted: Some(glyph),
+        }))
+    }
+}
+
+impl<'a, B: Brush> Iterator for LineItemIter<'a, B> {
+    type Item = LineItem<'a, B>;
 
     fn next(&mut self) -> Option<Self::Item> {
         loop {
             let run = self.line.get(self.run_index)?;
+            if let Some(inline_box) = run.inline_box() {
+                let offset = self.offset;
+                self.offset += run.advance();
+                self.run_index += 1;
+                return Some(LineItem::InlineBox(PositionedInlineBox {
+                    id: inline_box.id,
+                    x: offset + self.line.data.metrics.offset,
+                    y: self.line.data.metrics.baseline - inline_box.baseline,
+                    width: inline_box.width,
+                    height: inline_box.height,
+                }));
+            }
+            let inserted = if self.inserted_done {
+                None
+            } else {
+                run.inserted_glyph()
+            };
+            if let Some(glyph) = inserted {
+                // The logical end of a right-to-left run is visually first.
+                if run.is_rtl() {
+                    return self.inserted_run(run, glyph);
+                }
+            }
             let mut iter = run
                 .visual_clusters()
                 .map(|c| c.glyphs())
@@ -175,16 +605,23 @@ impl<'a, B: Brush> Iterator for GlyphRunIter<'a, B> {
                 let mut advance = first.advance;
                 let style_index = first.style_index();
                 let mut glyph_count = 1;
-                for glyph in iter.take_while(|g| g.style_index() == style_index) {
-                    glyph_count += 1;
-                    advance += glyph.advance;
+                if run.data.combine.is_some() {
+                    // Text combined upright is a single cluster that is
+                    // emitted as a whole with the advance of the cluster.
+                    glyph_count += iter.count();
+                    advance = run.advance();
+                } else {
+                    for glyph in iter.take_while(|g| g.style_index() == style_index) {
+                        glyph_count += 1;
+                        advance += glyph.advance;
+                    }
                 }
                 let style = run.layout.styles.get(style_index)?;
                 let glyph_start = self.glyph_start;
                 self.glyph_start += glyph_count;
                 let offset = self.offset;
                 self.offset += advance;
-                return Some(GlyphRun {
+                return Some(LineItem::GlyphRun(GlyphRun {
                     run,
                     style,
                     glyph_start,
@@ -192,10 +629,87 @@ impl<'a, B: Brush> Iterator for GlyphRunIter<'a, B> {
                     offset: offset + self.line.data.metrics.offset,
                     baseline: self.line.data.metrics.baseline,
                     advance,
-                });
+                    inserted: None,
+                }));
+            }
+            drop(iter);
+            if let Some(glyph) = inserted {
+                return self.inserted_run(run, glyph);
             }
             self.run_index += 1;
             self.glyph_start = 0;
+            self.inserted_done = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::layout::{Alignment, Glyph, Layout, WritingMode};
+    use crate::style::{StyleProperty, TextOrientation};
+    use crate::testing;
+
+    fn vertical_layout(text: &str, mode: WritingMode) -> Layout<[u8; 4]> {
+        let mut layout = testing::layout_with(text, |builder| {
+            builder.set_writing_mode(mode);
+        });
+        layout.break_all_lines(None, Alignment::Start);
+        layout
+    }
+
+    fn line_glyphs(layout: &Layout<[u8; 4]>, index: usize) -> Vec<Glyph> {
+        let line = layout.get(index).unwrap();
+        let runs = line.glyph_runs().collect::<Vec<_>>();
+        runs.iter()
+            .flat_map(|run| run.positioned_glyphs().collect::<Vec<_>>())
+            .collect()
+    }
+
+    #[test]
+    fn runs_are_classified_by_text_orientation() {
+        let upright = |layout: &Layout<[u8; 4]>| {
+            layout
+                .data
+                .runs
+                .iter()
+                .map(|run| run.upright)
+                .collect::<Vec<_>>()
+        };
+        let layout = vertical_layout("ab\u{a9}", WritingMode::VerticalRl);
+        assert_eq!(upright(&layout), vec![false, true]);
+        for &(orientation, expected) in &[
+            (TextOrientation::Upright, true),
+            (TextOrientation::Sideways, false),
+        ] {
+            let layout = testing::layout_with("ab\u{a9}", |builder| {
+                builder.set_writing_mode(WritingMode::VerticalRl);
+                builder.push_default(&StyleProperty::TextOrientation(orientation));
+            });
+            assert!(upright(&layout).iter().all(|&upright| upright == expected));
+        }
+        // Horizontal text is never upright.
+        let layout = vertical_layout("ab\u{a9}", WritingMode::HorizontalTb);
+        assert!(upright(&layout).iter().all(|&upright| !upright));
+    }
+
+    #[test]
+    fn columns_are_stacked_in_the_direction_of_the_writing_mode() {
+        for &mode in &[WritingMode::VerticalRl, WritingMode::VerticalLr] {
+            let layout = vertical_layout("ab\ncd", mode);
+            assert_eq!(layout.len(), 2);
+            let first = line_glyphs(&layout, 0);
+            let second = line_glyphs(&layout, 1);
+            for glyphs in [&first, &second].iter() {
+                // Glyphs advance down a column.
+                assert!(glyphs[0].y < glyphs[1].y);
+                assert!((glyphs[0].x - glyphs[1].x).abs() < 0.01);
+                assert!(glyphs[0].x >= 0. && glyphs[0].x <= layout.width());
+            }
+            if mode == WritingMode::VerticalRl {
+                assert!(first[0].x > second[0].x);
+            } else {
+                assert!(first[0].x < second[0].x);
+            }
         }
     }
 }
@@ -0,0 +1,635 @@
+//! Optimal (total-fit) line breaking.
+
+use super::greedy::{
+    commit_line, finish_layout, finish_lines, resolve_tabs, unjustify, LineLayout, LineState,
+};
+use crate::layout::*;
+use crate::style::Brush;
+
+/// Parameters for the badness and penalty model of the optimal line breaker.
+///
+/// The defaults are modeled after the values used by TeX, except that spaces
+/// do not shrink.
+#[derive(Copy, Clone, Debug)]
+pub struct BreakPenalties {
+    /// Maximum adjustment ratio for a line to be considered feasible. A ratio
+    /// of 1 means that all spaces on the line are stretched by their maximum
+    /// stretch.
+    pub tolerance: f32,
+    /// Demerits added to every line. Larger values favor fewer lines.
+    pub line_penalty: f32,
+    /// Demerits added when adjacent lines have incompatible tightness.
+    pub fitness_demerits: f32,
+    /// Maximum stretch of a space as a fraction of its advance.
+    pub space_stretch: f32,
+    /// Maximum shrink of a space as a fraction of its advance. Spaces are
+    /// only ever stretched when lines are laid out, so a value above zero
+    /// accepts lines that are wider than the maximum advance.
+    pub space_shrink: f32,
+    /// Penalty for breaking a line at a hyphenation point.
+    pub hyphen_penalty: f32,
+    /// Demerits added when two consecutive lines end with a hyphen.
+    pub double_hyphen_demerits: f32,
+}
+
+impl Default for BreakPenalties {
+    fn default() -> Self {
+        Self {
+            tolerance: 2.,
+            line_penalty: 10.,
+            fitness_demerits: 10000.,
+            space_stretch: 0.5,
+            space_shrink: 0.,
+            hyphen_penalty: 50.,
+            double_hyphen_demerits: 3000.,
+        }
+    }
+}
+
+/// Badness assigned to lines that cannot be stretched.
+const INFINITE_BADNESS: f32 = 10000.;
+
+/// Demerits assigned to overfull lines that are accepted as a last resort.
+const OVERFULL_DEMERITS: f64 = 1e12;
+
+/// Optimal line breaking support for a paragraph.
+///
+/// This considers all feasible break points in the paragraph at once and
+/// selects the sequence that minimizes the total demerits of all lines.
+pub struct OptimalBreakLines<'a, B: Brush> {
+    layout: &'a mut LayoutData<B>,
+    penalties: BreakPenalties,
+}
+
+impl<'a, B: Brush> OptimalBreakLines<'a, B> {
+    pub(crate) fn new(layout: &'a mut LayoutData<B>) -> Self {
+        Self {
+            layout,
+            penalties: BreakPenalties::default(),
+        }
+    }
+
+    /// Sets the badness and penalty model for the line breaker.
+    pub fn penalties(mut self, penalties: BreakPenalties) -> Self {
+        self.penalties = penalties;
+        self
+    }
+
+    /// Breaks all lines with the specified maximum advance and alignment.
+    /// This consumes the line breaker.
+    pub fn break_all(self, max_advance: f32, alignment: Alignment) {
+        if self.layout.clusters.is_empty() || !max_advance.is_finite() || max_advance >= f32::MAX {
+            // There is nothing to optimize, so defer to the greedy breaker.
+            BreakLines::new(self.layout).break_remaining(max_advance, alignment);
+            return;
+        }
+        unjustify(self.layout);
+        // Tab advances depend on where lines are broken. They are estimated
+        // from the unwrapped paragraphs for choosing breaks and resolved
+        // again for each line as it is committed.
+        resolve_tabs(self.layout, 0..self.layout.clusters.len());
+        self.layout.width = 0.;
+        self.layout.height = 0.;
+        self.layout.truncated = None;
+        let mut lines = LineLayout::default();
+        lines.swap(self.layout);
+        lines.lines.clear();
+        lines.runs.clear();
+        let items = Items::new(self.layout, &self.penalties);
+        let breaks = items.compute_breaks(max_advance, &self.penalties);
+        let mut start = 0;
+        for (index, brk) in breaks.iter().enumerate() {
+            let is_last = index + 1 == breaks.len();
+            let end = brk.pos;
+            let break_reason = if is_last {
+                BreakReason::None
+            } else if brk.is_emergency {
+                BreakReason::Emergency
+            } else if items.is_mandatory(end) {
+                BreakReason::Explicit
+            } else {
+                BreakReason::Regular
+            };
+            let mut state = LineState {
+                x: resolve_tabs(self.layout, start..end),
+                runs: items.run_index(start)..items.run_index(end - 1) + 1,
+                clusters: start..end,
+                hyphen: brk.hyphen,
+                ..Default::default()
+            };
+            if commit_line(
+                self.layout,
+                &mut lines,
+                &mut state,
+                max_advance,
+                alignment,
+                break_reason,
+                is_last,
+            ) {
+                if let Some(line) = lines.lines.last_mut() {
+                    line.num_spaces = items.num_spaces(start, items.trim(start, end));
+                }
+            }
+            start = end;
+        }
+        finish_lines(self.layout, &mut lines);
+        finish_layout(self.layout, &mut lines);
+    }
+}
+
+/// Prefix sums and break opportunities for the clusters of a paragraph.
+struct Items {
+    /// Cumulative advance of clusters.
+    width: Vec<f32>,
+    /// Cumulative stretch of spaces.
+    stretch: Vec<f32>,
+    /// Cumulative shrink of spaces.
+    shrink: Vec<f32>,
+    /// Cumulative count of spaces.
+    spaces: Vec<usize>,
+    /// For each position, the end of content after removing trailing
+    /// whitespace.
+    trimmed: Vec<usize>,
+    /// Index of the run for each cluster.
+    runs: Vec<usize>,
+    /// For each cluster, true if an emergency break may occur before it.
+    breakable: Vec<bool>,
+    /// Candidate break positions.
+    candidates: Vec<Candidate>,
+}
+
+impl Items {
+    fn new<B: Brush>(layout: &LayoutData<B>, penalties: &BreakPenalties) -> Self {
+        let len = layout.clusters.len();
+        let mut items = Self {
+            width: Vec::with_capacity(len + 1),
+            stretch: Vec::with_capacity(len + 1),
+            shrink: Vec::with_capacity(len + 1),
+            spaces: Vec::with_capacity(len + 1),
+            trimmed: Vec::with_capacity(len + 1),
+            runs: vec![0; len],
+            breakable: Vec::with_capacity(len),
+            candidates: vec![],
+        };
+        for (run_index, run) in layout.runs.iter().enumerate() {
+            for index in run.cluster_range.clone() {
+                items.runs[index] = run_index;
+            }
+        }
+        items.width.push(0.);
+        items.stretch.push(0.);
+        items.shrink.push(0.);
+        items.spaces.push(0);
+        items.trimmed.push(0);
+        let mut content_end = 0;
+        for (i, cluster) in layout.clusters.iter().enumerate() {
+            if i != 0 && !cluster.is_ligature_component() {
+                match cluster.boundary {
+                    Boundary::Mandatory => items.candidates.push(Candidate::new(i, true)),
+                    _ if cluster.is_hyphen_break() => {
+                        // The hyphen is taken from the run of the preceding
+                        // cluster since that run ends the line.
+                        if let Some(hyphen) = layout.runs[items.runs[i - 1]].hyphen {
+                            items.candidates.push(Candidate {
+                                pos: i,
+                                is_forced: false,
+                                hyphen: Some(hyphen),
+                            });
+                        }
+                    }
+                    Boundary::Line => items.candidates.push(Candidate::new(i, false)),
+                    _ => {}
+                }
+            }
+            let is_space = cluster.info.whitespace().is_space_or_nbsp();
+            let (stretch, shrink) = if is_space {
+                (
+                    cluster.advance * penalties.space_stretch,
+                    cluster.advance * penalties.space_shrink,
+                )
+            } else {
+                (0., 0.)
+            };
+            if !cluster.info.is_whitespace() {
+                content_end = i + 1;
+            }
+            items.breakable.push(!cluster.is_ligature_component());
+            items.width.push(items.width[i] + cluster.advance);
+            items.stretch.push(items.stretch[i] + stretch);
+            items.shrink.push(items.shrink[i] + shrink);
+            items.spaces.push(items.spaces[i] + is_space as usize);
+            items.trimmed.push(content_end);
+        }
+        items.candidates.push(Candidate::new(len, true));
+        items
+    }
+
+    /// Returns the number of clusters.
+    fn len(&self) -> usize {
+        self.runs.len()
+    }
+
+    fn is_mandatory(&self, pos: usize) -> bool {
+        self.candidates
+            .binary_search_by(|c| c.pos.cmp(&pos))
+            .map(|i| self.candidates[i].is_forced)
+            .unwrap_or(false)
+    }
+
+    fn run_index(&self, cluster: usize) -> usize {
+        self.runs.get(cluster).copied().unwrap_or(0)
+    }
+
+    /// Returns the end of the line content excluding trailing whitespace.
+    fn trim(&self, start: usize, end: usize) -> usize {
+        self.trimmed[end].max(start)
+    }
+
+    fn width(&self, start: usize, end: usize) -> f32 {
+        self.width[end] - self.width[start]
+    }
+
+    fn num_spaces(&self, start: usize, end: usize) -> usize {
+        self.spaces[end] - self.spaces[start]
+    }
+
+    /// Computes the optimal sequence of breaks.
+    fn compute_breaks(&self, max_advance: f32, penalties: &BreakPenalties) -> Vec<Break> {
+        let mut nodes = vec![Node {
+            pos: 0,
+            fitness: 1,
+            demerits: 0.,
+            prev: None,
+            is_emergency: false,
+            hyphen: None,
+        }];
+        let mut active = vec![0usize];
+        let mut next_active = vec![];
+        for candidate in &self.candidates {
+            // Best node for each fitness class at this position.
+            let mut best: [Option<Node>; 4] = [None; 4];
+            // Best deactivated node in case no feasible line exists.
+            let mut fallback: Option<Node> = None;
+            next_active.clear();
+            self.try_break(
+                &nodes,
+                &active,
+                candidate,
+                max_advance,
+                penalties,
+                &mut best,
+                &mut fallback,
+                &mut next_active,
+            );
+            // If no feasible line ends here, break the best overfull line
+            // the same way as the greedy breaker and continue from there.
+            while best.iter().all(Option::is_none)
+                && (candidate.is_forced || next_active.is_empty())
+            {
+                let node = match fallback.take() {
+                    Some(node) => node,
+                    None => break,
+                };
+                let start = node.prev.map(|prev| nodes[prev].pos).unwrap_or(0);
+                let brk = self.greedy_break(start, candidate.pos, max_advance);
+                if brk.pos >= candidate.pos {
+                    next_active.push(nodes.len());
+                    nodes.push(node);
+                    break;
+                }
+                let index = nodes.len();
+                nodes.push(Node {
+                    pos: brk.pos,
+                    is_emergency: brk.is_emergency,
+                    hyphen: brk.hyphen,
+                    ..node
+                });
+                self.try_break(
+                    &nodes,
+                    &[index],
+                    candidate,
+                    max_advance,
+                    penalties,
+                    &mut best,
+                    &mut fallback,
+                    &mut next_active,
+                );
+            }
+            for node in best.iter().flatten() {
+                next_active.push(nodes.len());
+                nodes.push(*node);
+            }
+            core::mem::swap(&mut active, &mut next_active);
+        }
+        let last = active
+            .iter()
+            .copied()
+            .filter(|&i| nodes[i].pos == self.len())
+            .min_by(|&a, &b| {
+                nodes[a]
+                    .demerits
+                    .partial_cmp(&nodes[b].demerits)
+                    .unwrap_or(core::cmp::Ordering::Equal)
+            });
+        let mut breaks = vec![];
+        let mut cur = last;
+        while let Some(index) = cur {
+            let node = &nodes[index];
+            if node.prev.is_none() {
+                break;
+            }
+            breaks.push(Break {
+                pos: node.pos,
+                is_emergency: node.is_emergency,
+                hyphen: node.hyphen,
+            });
+            cur = node.prev;
+        }
+        breaks.reverse();
+        if breaks.is_empty() {
+            breaks.push(Break {
+                pos: self.len(),
+                is_emergency: false,
+                hyphen: None,
+            });
+        }
+        breaks
+    }
+
+    /// Evaluates the lines from the specified nodes to a candidate break,
+    /// recording the best feasible node for each fitness class, the best
+    /// overfull node and the nodes that remain active.
+    #[allow(clippy::too_many_arguments)]
+    fn try_break(
+        &self,
+        nodes: &[Node],
+        active: &[usize],
+        candidate: &Candidate,
+        max_advance: f32,
+        penalties: &BreakPenalties,
+        best: &mut [Option<Node>; 4],
+        fallback: &mut Option<Node>,
+        next_active: &mut Vec<usize>,
+    ) {
+        let pos = candidate.pos;
+        let is_forced = candidate.is_forced;
+        let hyphen_advance = candidate.hyphen.map(|g| g.advance).unwrap_or(0.);
+        for &node_index in active {
+            let node = &nodes[node_index];
+            let start = node.pos;
+            let end = self.trim(start, pos);
+            let width = self.width(start, end) + hyphen_advance;
+            let stretch = self.stretch[end] - self.stretch[start];
+            let shrink = self.shrink[end] - self.shrink[start];
+            let (ratio, badness) = if width > max_advance {
+                if shrink > 0. {
+                    let ratio = (max_advance - width) / shrink;
+                    (ratio, 100. * ratio.abs().powi(3))
+                } else {
+                    (f32::NEG_INFINITY, INFINITE_BADNESS)
+                }
+            } else if pos == self.len() || is_forced {
+                // The last line of a paragraph has infinite stretch.
+                (0., 0.)
+            } else if stretch > 0. {
+                let ratio = (max_advance - width) / stretch;
+                (ratio, (100. * ratio.powi(3)).min(INFINITE_BADNESS))
+            } else {
+                // A line that cannot be stretched is infeasible. If no
+                // feasible line remains, the overfull fallback is broken
+                // by the greedy rules instead.
+                (f32::INFINITY, INFINITE_BADNESS)
+            };
+            if ratio < -1. {
+                // This line and every longer one starting at this node
+                // are overfull.
+                let demerits = node.demerits + OVERFULL_DEMERITS;
+                if fallback.map(|f| demerits < f.demerits).unwrap_or(true) {
+                    *fallback = Some(Node {
+                        pos,
+                        fitness: 1,
+                        demerits,
+                        prev: Some(node_index),
+                        is_emergency: true,
+                        hyphen: candidate.hyphen,
+                    });
+                }
+                continue;
+            }
+            if !is_forced {
+                // Only nodes at a forced break may start subsequent
+                // lines.
+                next_active.push(node_index);
+            }
+            if ratio > penalties.tolerance {
+                continue;
+            }
+            let fitness = if ratio < -0.5 {
+                0
+            } else if ratio <= 0.5 {
+                1
+            } else if ratio <= 1. {
+                2
+            } else {
+                3
+            };
+            let mut demerits = (penalties.line_penalty as f64 + badness as f64).powi(2);
+            if candidate.hyphen.is_some() {
+                demerits += (penalties.hyphen_penalty as f64).powi(2);
+                if node.hyphen.is_some() {
+                    demerits += penalties.double_hyphen_demerits as f64;
+                }
+            }
+            if (fitness as i32 - node.fitness as i32).abs() > 1 {
+                demerits += penalties.fitness_demerits as f64;
+            }
+            let demerits = node.demerits + demerits;
+            let slot = &mut best[fitness];
+            if slot.map(|n| demerits < n.demerits).unwrap_or(true) {
+                *slot = Some(Node {
+                    pos,
+                    fitness,
+                    demerits,
+                    prev: Some(node_index),
+                    is_emergency: false,
+                    hyphen: candidate.hyphen,
+                });
+            }
+        }
+    }
+
+    /// Returns the first break of an overfull line from `start` to `end`
+    /// chosen like the greedy breaker: the last candidate where the line
+    /// fits or, if there is none, the last cluster that fits.
+    fn greedy_break(&self, start: usize, end: usize, max_advance: f32) -> Break {
+        let first = self.candidates.partition_point(|c| c.pos <= start);
+        let last = self.candidates.partition_point(|c| c.pos < end);
+        let candidates = &self.candidates[first..last];
+        let fits = |candidate: &&Candidate| {
+            let width = self.width(start, self.trim(start, candidate.pos));
+            width + candidate.hyphen.map(|g| g.advance).unwrap_or(0.) <= max_advance
+        };
+        if let Some(candidate) = candidates.iter().rev().find(fits) {
+            return Break {
+                pos: candidate.pos,
+                is_emergency: false,
+                hyphen: candidate.hyphen,
+            };
+        }
+        // The first word does not fit on a line by itself, so break it
+        // after the last cluster that fits, but after at least one.
+        let limit = candidates.first().map(|c| c.pos).unwrap_or(end);
+        let mut pos = start + 1;
+        while pos < limit && !self.breakable[pos] {
+            pos += 1;
+        }
+        for next in pos + 1..limit {
+            if !self.breakable[next] {
+                continue;
+            }
+            if self.width(start, next) > max_advance {
+                break;
+            }
+            pos = next;
+        }
+        Break {
+            pos,
+            is_emergency: pos < limit,
+            hyphen: None,
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Node {
+    pos: usize,
+    fitness: usize,
+    demerits: f64,
+    prev: Option<usize>,
+    /// True if the line ending at this node was broken as a last resort.
+    is_emergency: bool,
+    hyphen: Option<InsertedGlyph>,
+}
+
+struct Break {
+    pos: usize,
+    is_emergency: bool,
+    hyphen: Option<InsertedGlyph>,
+}
+
+/// Position where a line may be broken.
+struct Candidate {
+    pos: usize,
+    /// True for mandatory breaks.
+    is_forced: bool,
+    /// Hyphen inserted when breaking at this position.
+    hyphen: Option<InsertedGlyph>,
+}
+
+impl Candidate {
+    fn new(pos: usize, is_forced: bool) -> Self {
+        Self {
+            pos,
+            is_forced,
+            hyphen: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::layout::{Alignment, Layout};
+    use crate::testing;
+    use core::ops::Range;
+
+    const TEXT: &str = "The quick brown fox jumps over the lazy dog. Pack my box with five \
+                        dozen liquor jugs. How vexingly quick daft zebras jump!";
+
+    fn text_ranges(layout: &Layout<[u8; 4]>) -> Vec<Range<usize>> {
+        layout.lines().map(|line| line.text_range()).collect()
+    }
+
+    fn break_optimal(layout: &mut Layout<[u8; 4]>, max_advance: f32) {
+        layout.break_all_lines_optimal(Some(max_advance), Alignment::Start);
+    }
+
+    fn assert_fits(layout: &Layout<[u8; 4]>, max_advance: f32) {
+        for line in layout.lines() {
+            let metrics = line.metrics();
+            assert!(metrics.advance - metrics.trailing_whitespace <= max_advance);
+        }
+    }
+
+    #[test]
+    fn lines_fit_within_max_advance() {
+        let mut layout = testing::layout(TEXT);
+        break_optimal(&mut layout, 150.);
+        assert!(layout.len() > 1);
+        assert_fits(&layout, 150.);
+        let ranges = text_ranges(&layout);
+        assert_eq!(ranges.first().unwrap().start, 0);
+        assert_eq!(ranges.last().unwrap().end, TEXT.len());
+        for pair in ranges.windows(2) {
+            assert_eq!(pair[0].end, pair[1].start);
+        }
+    }
+
+    #[test]
+    fn justified_lines_fit_with_default_penalties() {
+        let max_advance = 150.;
+        let mut layout = testing::layout(TEXT);
+        layout.break_all_lines_optimal(Some(max_advance), Alignment::Justified);
+        assert!(layout.len() > 1);
+        assert_fits(&layout, max_advance);
+        for line in layout.lines() {
+            let advance: f32 = line.glyph_runs().map(|run| run.advance()).sum();
+            let trailing = line.metrics().trailing_whitespace;
+            assert!(advance - trailing <= max_advance + 0.01);
+        }
+    }
+
+    #[test]
+    fn mandatory_breaks_are_kept() {
+        let mut layout = testing::layout("one\ntwo three");
+        layout.break_all_lines_optimal(Some(1000.), Alignment::Start);
+        assert_eq!(text_ranges(&layout), vec![0..4, 4..13]);
+    }
+
+    #[test]
+    fn balances_lines() {
+        // The greedy breaker fills the first line and leaves the second one
+        // loose while the optimal breaker spreads the slack between them.
+        let text = "aaa bb cc ddddd ee f ggg hh iiii jj kkk l mm nnnn o pp";
+        let max_advance = 170.;
+        let mut greedy = testing::layout(text);
+        greedy.break_all_lines(Some(max_advance), Alignment::Start);
+        let mut optimal = testing::layout(text);
+        break_optimal(&mut optimal, max_advance);
+        assert_eq!(greedy.len(), optimal.len());
+        assert_ne!(text_ranges(&greedy), text_ranges(&optimal));
+        let max_slack = |layout: &Layout<[u8; 4]>| {
+            layout
+                .lines()
+                .take(layout.len() - 1)
+                .map(|line| {
+                    let metrics = line.metrics();
+                    max_advance - (metrics.advance - metrics.trailing_whitespace)
+                })
+                .fold(0., f32::max)
+        };
+        assert!(max_slack(&optimal) < max_slack(&greedy));
+        assert_fits(&optimal, max_advance);
+    }
+
+    #[test]
+    fn overfull_words_are_broken_at_clusters() {
+        let text = "a supercalifragilisticexpialidocious word";
+        let mut layout = testing::layout(text);
+        break_optimal(&mut layout, 60.);
+        assert!(layout.len() > 3);
+        assert_fits(&layout, 60.);
+        let ranges = text_ranges(&layout);
+        assert_eq!(ranges.first(), Some(&(0..2)));
+        assert_eq!(ranges.last(), Some(&(37..41)));
+    }
+}
@@ -2,6 +2,7 @@
 
 mod cluster;
 mod line;
+mod outline;
 mod run;
 
 pub(crate) mod data;
@@ -15,9 +16,11 @@ use data::*;
 use swash::text::cluster::{Boundary, ClusterInfo};
 use swash::{GlyphId, NormalizedCoord, Synthesis};
 
-pub use cursor::Cursor;
+pub use cursor::{Affinity, Cursor, Selection};
 pub use line::greedy::BreakLines;
-pub use line::{GlyphRun, LineMetrics};
+pub use line::optimal::{BreakPenalties, OptimalBreakLines};
+pub use line::{GlyphRun, LineItem, LineMetrics, PositionedInlineBox, RubyAnnotation};
+pub use outline::GlyphOutline;
 pub use run::RunMetrics;
 
 /// Alignment of a layout.
@@ -36,6 +39,74 @@ impl Default for Alignment {
     }
 }
 
+/// Base direction of a paragraph.
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+pub enum BaseDirection {
+    /// Determined by the first strong directional character in the text,
+    /// defaulting to left-to-right.
+    #[default]
+    Auto,
+    /// Left-to-right.
+    LeftToRight,
+    /// Right-to-left.
+    RightToLeft,
+}
+
+/// Direction in which lines are laid out and stacked.
+///
+/// In vertical modes, line metrics, run offsets and hit testing are
+/// expressed along the logical axes of the line: offsets advance from the
+/// top of a column and baselines advance across columns. Positioned glyphs
+/// are reported in physical coordinates.
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+pub enum WritingMode {
+    /// Horizontal lines stacked from top to bottom.
+    #[default]
+    HorizontalTb,
+    /// Vertical lines stacked from right to left.
+    VerticalRl,
+    /// Vertical lines stacked from left to right.
+    VerticalLr,
+}
+
+impl WritingMode {
+    /// Returns true for the vertical writing modes.
+    pub fn is_vertical(self) -> bool {
+        self != Self::HorizontalTb
+    }
+}
+
+/// Position of the ellipsis in a truncated line.
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+pub enum EllipsisPosition {
+    /// Text is removed from the start of the line.
+    Start,
+    /// Text is removed from the middle of the line, which keeps both ends
+    /// of a file path or similar identifier visible.
+    Middle,
+    /// Text is removed from the end of the line.
+    #[default]
+    End,
+}
+
+/// Limits for truncating a layout.
+///
+/// When the lines of a layout exceed either limit, the final line that fits
+/// is shortened to make room for an ellipsis. With the start and middle
+/// positions, the final line holds the remaining text of its paragraph
+/// before it is shortened and any text after the next mandatory break is
+/// removed.
+#[derive(Copy, Clone, PartialEq, Default, Debug)]
+pub struct Truncation {
+    /// Maximum number of lines.
+    pub max_lines: Option<usize>,
+    /// Maximum extent of the lines in the block direction. This is the
+    /// height for horizontal layouts.
+    pub max_height: Option<f32>,
+    /// Position of the ellipsis on the final line.
+    pub ellipsis: EllipsisPosition,
+}
+
 /// Text layout.
 #[derive(Clone)]
 pub struct Layout<B: Brush> {
@@ -58,20 +129,42 @@ impl<B: Brush> Layout<B> {
         &self.data.styles
     }
 
+    /// Returns true if the base direction of the layout is right-to-left.
+    pub fn is_rtl(&self) -> bool {
+        self.data.base_level & 1 != 0
+    }
+
+    /// Returns the writing mode of the layout.
+    pub fn writing_mode(&self) -> WritingMode {
+        self.data.writing_mode
+    }
+
     /// Returns the width of the layout.
     pub fn width(&self) -> f32 {
-        self.data.width
+        if self.data.writing_mode.is_vertical() {
+            self.data.height
+        } else {
+            self.data.width
+        }
     }
 
     /// Returns the width of the layout, including the width of any trailing
     /// whitespace.
     pub fn full_width(&self) -> f32 {
-        self.data.full_width
+        if self.data.writing_mode.is_vertical() {
+            self.data.height
+        } else {
+            self.data.full_width
+        }
     }
 
     /// Returns the height of the layout.
     pub fn height(&self) -> f32 {
-        self.data.height
+        if self.data.writing_mode.is_vertical() {
+            self.data.width
+        } else {
+            self.data.height
+        }
     }
 
     /// Returns the number of lines in the layout.
@@ -111,6 +204,46 @@ impl<B: Brush> Layout<B> {
             .break_remaining(max_advance.unwrap_or(f32::MAX), alignment)
     }
 
+    /// Breaks all lines with the specified maximum advance and alignment,
+    /// truncating the layout to the specified limits. Returns the range of
+    /// text that was removed, or `None` if the text fits. When text after
+    /// the paragraph of the final line is removed along with text replaced
+    /// by a start or middle ellipsis, the range extends from the replaced
+    /// text to the end of the text.
+    ///
+    /// Lines are broken in a single pass that stops at the first line that
+    /// exceeds the limits. At least one line is always kept.
+    pub fn break_all_lines_truncated(
+        &mut self,
+        max_advance: Option<f32>,
+        alignment: Alignment,
+        truncation: Truncation,
+    ) -> Option<Range<usize>> {
+        self.break_lines().break_remaining_truncated(
+            max_advance.unwrap_or(f32::MAX),
+            alignment,
+            truncation,
+        )
+    }
+
+    /// Returns the range of text that was removed when the layout was
+    /// truncated.
+    pub fn truncated_range(&self) -> Option<Range<usize>> {
+        self.data.truncated.clone()
+    }
+
+    /// Returns an optimal line breaker to compute lines for the layout.
+    pub fn break_lines_optimal(&mut self) -> OptimalBreakLines<'_, B> {
+        OptimalBreakLines::new(&mut self.data)
+    }
+
+    /// Breaks all lines with the specified maximum advance and alignment,
+    /// minimizing the total badness of the paragraph.
+    pub fn break_all_lines_optimal(&mut self, max_advance: Option<f32>, alignment: Alignment) {
+        self.break_lines_optimal()
+            .break_all(max_advance.unwrap_or(f32::MAX), alignment)
+    }
+
     /// Returns an iterator over the runs in the layout.
     pub fn runs(&self) -> impl Iterator<Item = Run<B>> + '_ + Clone {
         self.data.runs.iter().map(move |data| Run {
@@ -161,6 +294,35 @@ impl Glyph {
     }
 }
 
+/// Atomic inline object, such as an image or icon, that is laid out along
+/// with the text.
+#[derive(Copy, Clone, PartialEq, Default, Debug)]
+pub struct InlineBox {
+    /// User defined identifier for the box.
+    pub id: u64,
+    /// Byte offset of the text position before which the box is placed.
+    pub index: usize,
+    /// Width of the box.
+    pub width: f32,
+    /// Height of the box.
+    pub height: f32,
+    /// Distance from the top of the box to the baseline of the line.
+    pub baseline: f32,
+}
+
+/// Axis aligned rectangle.
+#[derive(Copy, Clone, PartialEq, Default, Debug)]
+pub struct Rect {
+    /// Offset to the left edge.
+    pub x: f32,
+    /// Offset to the top edge.
+    pub y: f32,
+    /// Width of the rectangle.
+    pub width: f32,
+    /// Height of the rectangle.
+    pub height: f32,
+}
+
 /// Line in a text layout.
 #[derive(Copy, Clone)]
 pub struct Line<'a, B: Brush> {
@@ -179,6 +341,8 @@ pub struct Style<B: Brush> {
     pub strikethrough: Option<Decoration<B>>,
     /// Multiplicative line height factor.
     pub(crate) line_height: f32,
+    /// Tab stops applied by the line breaker.
+    pub(crate) tab_stops: Option<TabStopsData>,
 }
 
 /// Underline or strikethrough decoration.
@@ -193,3 +357,21 @@ pub struct Decoration<B: Brush> {
     /// containing run.
     pub size: Option<f32>,
 }
+
+/// Kind of a text decoration.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum DecorationKind {
+    Underline,
+    Strikethrough,
+}
+
+/// Underline or strikethrough decoration with its final geometry.
+#[derive(Clone, Debug)]
+pub struct DecorationRect<'a, B: Brush> {
+    /// Kind of the decoration.
+    pub kind: DecorationKind,
+    /// Brush used to draw the decoration.
+    pub brush: &'a B,
+    /// Rectangle covered by the decoration.
+    pub rect: Rect,
+}
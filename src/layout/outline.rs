@@ -0,0 +1,165 @@
+//! Glyph outlines of runs.
+
+use super::*;
+use swash::scale::outline::Outline;
+use swash::scale::ScaleContext;
+use swash::zeno::{Angle, Command, PathData, Point, Transform};
+
+/// Outline of a positioned glyph.
+#[derive(Clone, Default, Debug)]
+pub struct GlyphOutline {
+    /// Glyph identifier.
+    pub id: GlyphId,
+    /// Offset to the glyph origin in the layout.
+    pub x: f32,
+    /// Offset to the glyph origin in the layout.
+    pub y: f32,
+    /// Path commands in layout coordinates, with the y axis pointing down.
+    pub commands: Vec<Command>,
+}
+
+impl<'a, B: Brush> GlyphRun<'a, B> {
+    /// Returns the outlines of the positioned glyphs in the run.
+    ///
+    /// Outlines are scaled to the font size with the variation coordinates
+    /// of the run, and synthetic emboldening and oblique are applied as
+    /// suggested by the synthesis of the run. Glyphs without outlines, such
+    /// as those of bitmap fonts, produce an empty path.
+    pub fn outlines(&self, context: &mut ScaleContext) -> Vec<GlyphOutline> {
+        let run = self.run();
+        let mut scaler = context
+            .builder(run.font().as_ref())
+            .size(run.font_size())
+            .normalized_coords(run.normalized_coords())
+            .build();
+        let transform = self.glyph_transform();
+        let embolden = self.embolden_strength();
+        let mut outline = Outline::new();
+        self.positioned_glyphs()
+            .map(|glyph| {
+                let mut commands = vec![];
+                outline.clear();
+                if scaler.scale_outline_into(glyph.id, &mut outline) {
+                    if embolden != 0. {
+                        outline.embolden(embolden, embolden);
+                    }
+                    if let Some(transform) = &transform {
+                        outline.transform(transform);
+                    }
+                    let map = |p: Point| Point::new(glyph.x + p.x, glyph.y - p.y);
+                    commands.extend(outline.path().commands().map(|command| match command {
+                        Command::MoveTo(p) => Command::MoveTo(map(p)),
+                        Command::LineTo(p) => Command::LineTo(map(p)),
+                        Command::CurveTo(a, b, p) => Command::CurveTo(map(a), map(b), map(p)),
+                        Command::QuadTo(a, p) => Command::QuadTo(map(a), map(p)),
+                        Command::Close => Command::Close,
+                    }));
+                }
+                GlyphOutline {
+                    id: glyph.id,
+                    x: glyph.x,
+                    y: glyph.y,
+                    commands,
+                }
+            })
+            .collect()
+    }
+
+    /// Returns the transform that is applied to the outlines of the glyphs
+    /// in the run for synthetic oblique, compressed text and sideways
+    /// glyphs.
+    pub(crate) fn glyph_transform(&self) -> Option<Transform> {
+        let mut transform = None;
+        if let Some(angle) = self.run().synthesis().skew() {
+            transform = Some(Transform::skew(
+                Angle::from_degrees(angle),
+                Angle::from_degrees(0.),
+            ));
+        }
+        let scale = self.horizontal_scale();
+        if scale != 1. {
+            transform = Some(
+                transform
+                    .unwrap_or(Transform::IDENTITY)
+                    .then_scale(scale, 1.),
+            );
+        }
+        if self.is_sideways() {
+            let rotation = Transform::rotation(Angle::from_degrees(-90.));
+            transform = Some(transform.unwrap_or(Transform::IDENTITY).then(&rotation));
+        }
+        transform
+    }
+
+    /// Returns the strength of synthetic emboldening for the run in pixels.
+    /// Outlines are expanded by 1/48 em on each side, which thickens stems
+    /// by 1/24 em like FreeType does.
+    pub(crate) fn embolden_strength(&self) -> f32 {
+        if self.run().synthesis().embolden() {
+            self.run().font_size() / 48.
+        } else {
+            0.
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    /// Returns the minimum and maximum points of the path.
+    fn bounds(commands: &[Command]) -> (Point, Point) {
+        let mut min = Point::new(f32::MAX, f32::MAX);
+        let mut max = Point::new(f32::MIN, f32::MIN);
+        for command in commands {
+            let points = match *command {
+                Command::MoveTo(p) | Command::LineTo(p) => vec![p],
+                Command::CurveTo(a, b, p) => vec![a, b, p],
+                Command::QuadTo(a, p) => vec![a, p],
+                Command::Close => vec![],
+            };
+            for p in points {
+                min = Point::new(min.x.min(p.x), min.y.min(p.y));
+                max = Point::new(max.x.max(p.x), max.y.max(p.y));
+            }
+        }
+        (min, max)
+    }
+
+    #[test]
+    fn outlines_are_placed_at_glyph_origins_with_y_down() {
+        let mut layout = testing::layout("Tg\nT");
+        layout.break_all_lines(None, Alignment::Start);
+        let mut context = ScaleContext::new();
+        let mut origins = vec![];
+        for line in layout.lines() {
+            let baseline = line.metrics().baseline;
+            for glyph_run in line.glyph_runs() {
+                let glyphs: Vec<_> = glyph_run.positioned_glyphs().collect();
+                let outlines = glyph_run.outlines(&mut context);
+                assert_eq!(outlines.len(), glyphs.len());
+                for (outline, glyph) in outlines.iter().zip(&glyphs) {
+                    assert_eq!(
+                        (outline.id, outline.x, outline.y),
+                        (glyph.id, glyph.x, glyph.y)
+                    );
+                    assert_eq!(glyph.y, baseline);
+                    let (min, max) = bounds(&outline.commands);
+                    assert!(min.x >= glyph.x - 1. && max.x <= glyph.x + glyph.advance + 1.);
+                    // Glyphs extend upwards from the baseline toward smaller y.
+                    assert!(min.y < glyph.y - testing::FONT_SIZE / 2.);
+                    origins.push((glyph.x, glyph.y, max.y));
+                }
+            }
+        }
+        assert_eq!(origins.len(), 3);
+        let (t, g, next) = (origins[0], origins[1], origins[2]);
+        assert!(g.0 > t.0);
+        assert!((t.2 - t.1).abs() < 0.5);
+        // The descender of g extends below the baseline.
+        assert!(g.2 > g.1 + 1.);
+        assert_eq!(next.0, t.0);
+        assert!(next.1 > t.1 + testing::FONT_SIZE);
+    }
+}
@@ -59,7 +59,11 @@ impl<'a, B: Brush> Run<'a, B> {
 
     /// Returns true if the run has right-to-left directionality.
     pub fn is_rtl(&self) -> bool {
-        self.data.bidi_level & 1 != 0
+        self.line_data
+            .map(|d| d.bidi_level)
+            .unwrap_or(self.data.bidi_level)
+            & 1
+            != 0
     }
 
     /// Returns the number of clusters in the run.
@@ -116,6 +120,42 @@ impl<'a, B: Brush> Run<'a, B> {
         }
     }
 
+    /// Returns the inline box if the run represents one. Such runs contain
+    /// a single cluster without glyphs and the font properties of the run
+    /// are not meaningful.
+    pub fn inline_box(&self) -> Option<InlineBox> {
+        self.layout.inline_boxes.get(self.data.inline_box?).copied()
+    }
+
+    /// Returns the glyph that was inserted at the logical end of the run by
+    /// the line breaker, such as a hyphen or an ellipsis. The advance is the
+    /// total advance if the glyph is repeated.
+    ///
+    /// The glyph may be taken from a fallback font when the font of the run
+    /// does not support it. The glyph runs of a line carry the correct font
+    /// for rendering.
+    pub fn inserted_glyph(&self) -> Option<Glyph> {
+        let line_data = self.line_data?;
+        let inserted = line_data.inserted?;
+        let range = &line_data.cluster_range;
+        let index = if range.is_empty() {
+            Some(range.start)
+        } else {
+            range.end.checked_sub(1)
+        };
+        let style_index = index
+            .and_then(|index| self.layout.clusters.get(index))
+            .map(|cluster| cluster.style_index)
+            .unwrap_or(0);
+        Some(Glyph {
+            id: inserted.id,
+            style_index,
+            x: 0.,
+            y: 0.,
+            advance: inserted.advance,
+        })
+    }
+
     pub(crate) fn data(&self) -> &'a RunData {
         self.data
     }
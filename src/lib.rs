@@ -1,14 +1,24 @@
 pub use swash;
 
 mod bidi;
+mod hyphen;
+mod orientation;
 mod resolve;
 mod shape;
 mod util;
 
+#[cfg(test)]
+mod testing;
+
 pub mod context;
+pub mod editor;
 pub mod font;
 pub mod layout;
 pub mod style;
+pub mod svg;
+
+#[cfg(feature = "render")]
+pub mod render;
 
 pub use context::LayoutContext;
 pub use font::{Font, FontContext};
@@ -0,0 +1,67 @@
+//! Vertical orientation of characters (UAX #50).
+
+/// Returns true if the character is set upright in vertical text with
+/// mixed orientation. This includes characters with the transformed
+/// orientations (Tu and Tr) which rely on the `vert` feature for rotated
+/// or repositioned glyphs.
+pub fn is_upright(ch: char) -> bool {
+    let cp = ch as u32;
+    if cp < 0xA7 {
+        return false;
+    }
+    UPRIGHT
+        .binary_search_by(|&(start, end)| {
+            if cp < start {
+                core::cmp::Ordering::Greater
+            } else if cp > end {
+                core::cmp::Ordering::Less
+            } else {
+                core::cmp::Ordering::Equal
+            }
+        })
+        .is_ok()
+}
+
+/// Sorted ranges of characters with the U, Tu or Tr vertical orientation.
+#[rustfmt::skip]
+const UPRIGHT: &[(u32, u32)] = &[
+    (0xA7, 0xA7), (0xA9, 0xA9), (0xAE, 0xAE), (0xB1, 0xB1), (0xBC, 0xBE),
+    (0xD7, 0xD7), (0xF7, 0xF7), (0x2EA, 0x2EB), (0x1100, 0x11FF),
+    (0x1401, 0x167F), (0x18B0, 0x18FF), (0x2016, 0x2016), (0x2020, 0x2021),
+    (0x2030, 0x2031), (0x203B, 0x203C), (0x2042, 0x2042), (0x2047, 0x2049),
+    (0x2051, 0x2051), (0x2065, 0x2065), (0x20DD, 0x20E0), (0x20E2, 0x20E4),
+    (0x2100, 0x2101), (0x2103, 0x2109), (0x210F, 0x210F), (0x2113, 0x2114),
+    (0x2116, 0x2117), (0x211E, 0x2123), (0x2125, 0x2125), (0x2127, 0x2127),
+    (0x2129, 0x2129), (0x212E, 0x212E), (0x2135, 0x213F), (0x2145, 0x214A),
+    (0x214C, 0x214D), (0x214F, 0x2189), (0x218C, 0x218F), (0x221E, 0x221E),
+    (0x2234, 0x2235), (0x2300, 0x2307), (0x230C, 0x231F), (0x2324, 0x232B),
+    (0x237D, 0x239A), (0x23BE, 0x23CD), (0x23CF, 0x23CF), (0x23D1, 0x23DB),
+    (0x23E2, 0x2422), (0x2424, 0x24FF), (0x25A0, 0x2619), (0x2620, 0x2767),
+    (0x2776, 0x2793), (0x2B12, 0x2B2F), (0x2B50, 0x2B59), (0x2BB8, 0x2BD1),
+    (0x2BD3, 0x2BEB), (0x2BF0, 0x2BFF), (0x2E50, 0x2E51), (0x2E80, 0xA4CF),
+    (0xA960, 0xA97F), (0xAC00, 0xD7FF), (0xE000, 0xFAFF), (0xFE10, 0xFE1F),
+    (0xFE30, 0xFE6F), (0xFF00, 0xFF60), (0xFFE0, 0xFFE7), (0xFFF0, 0xFFFD),
+    (0x10980, 0x1099F), (0x11580, 0x115FF), (0x11A00, 0x11AAF),
+    (0x13000, 0x1343F), (0x14400, 0x1467F), (0x16FE0, 0x18AFF),
+    (0x1B000, 0x1B2FF), (0x1D000, 0x1D1FF), (0x1D2E0, 0x1D37F),
+    (0x1D800, 0x1DAAF), (0x1F000, 0x1F7FF), (0x1F900, 0x1FAFF),
+    (0x20000, 0x3FFFD), (0xF0000, 0x10FFFD),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::is_upright;
+
+    #[test]
+    fn characters_are_classified_by_vertical_orientation() {
+        // Latin letters, digits and Hebrew are set sideways.
+        for &ch in &['a', 'Z', '0', ' ', '\u{5d0}'] {
+            assert!(!is_upright(ch), "{:?}", ch);
+        }
+        // Han, kana, hangul and symbols such as the copyright sign are set
+        // upright.
+        for &ch in &['\u{4e2d}', '\u{3042}', '\u{30a2}', '\u{ac00}', '\u{a9}'] {
+            assert!(is_upright(ch), "{:?}", ch);
+        }
+    }
+}
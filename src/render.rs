@@ -0,0 +1,347 @@
+//! Rasterization of layouts into pixel buffers.
+//!
+//! This module is only compiled with the `render` feature. It is built on
+//! the scaler of swash and requires no dependencies beyond those of the
+//! crate.
+
+mod atlas;
+
+use super::layout::{GlyphRun, Layout};
+use super::style::Brush;
+use swash::scale::image::{Content, Image};
+use swash::scale::{Render, ScaleContext, Source, StrikeWith};
+use swash::zeno::{Format, Vector};
+
+pub use atlas::{AtlasGlyph, AtlasPage, GlyphCache};
+
+/// Number of horizontal subpixel positions used when subpixel positioning
+/// is enabled.
+const SUBPIXEL_STEPS: f32 = 4.;
+
+/// Sources used for rendering glyphs, in order of preference.
+const SOURCES: &[Source] = &[
+    Source::ColorOutline(0),
+    Source::ColorBitmap(StrikeWith::BestFit),
+    Source::Outline,
+];
+
+/// Caller supplied buffer of 8-bit RGBA pixels with straight alpha.
+pub struct Canvas<'a> {
+    data: &'a mut [u8],
+    width: usize,
+    height: usize,
+    stride: usize,
+}
+
+impl<'a> Canvas<'a> {
+    /// Creates a canvas for the specified buffer and dimensions in pixels.
+    /// Returns `None` if the buffer is too small.
+    pub fn new(data: &'a mut [u8], width: usize, height: usize) -> Option<Self> {
+        Self::with_stride(data, width, height, width * 4)
+    }
+
+    /// Creates a canvas for the specified buffer, dimensions in pixels and
+    /// number of bytes per row. Returns `None` if the buffer is too small.
+    pub fn with_stride(
+        data: &'a mut [u8],
+        width: usize,
+        height: usize,
+        stride: usize,
+    ) -> Option<Self> {
+        if stride < width * 4 || (height != 0 && data.len() < stride * (height - 1) + width * 4) {
+            return None;
+        }
+        Some(Self {
+            data,
+            width,
+            height,
+            stride,
+        })
+    }
+
+    /// Returns the width of the canvas in pixels.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Returns the height of the canvas in pixels.
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Blends a color into the pixel at the specified position with the
+    /// specified coverage.
+    fn blend(&mut self, x: i32, y: i32, color: [u8; 4], coverage: u8) {
+        if x < 0 || y < 0 || x as usize >= self.width || y as usize >= self.height {
+            return;
+        }
+        let alpha = color[3] as f32 * coverage as f32 / (255. * 255.);
+        if alpha <= 0. {
+            return;
+        }
+        let start = y as usize * self.stride + x as usize * 4;
+        let dst = &mut self.data[start..start + 4];
+        let dst_alpha = dst[3] as f32 / 255.;
+        let out_alpha = alpha + dst_alpha * (1. - alpha);
+        for i in 0..3 {
+            let c = color[i] as f32 * alpha + dst[i] as f32 * dst_alpha * (1. - alpha);
+            dst[i] = (c / out_alpha).round().min(255.) as u8;
+        }
+        dst[3] = (out_alpha * 255.).round() as u8;
+    }
+
+    /// Fills a rectangle, snapped to the pixel grid, with a color.
+    fn fill_rect(&mut self, x: f32, y: f32, width: f32, height: f32, color: [u8; 4]) {
+        let x0 = x.round() as i32;
+        let x1 = (x + width).round() as i32;
+        let y0 = y.round() as i32;
+        let y1 = ((y + height).round() as i32).max(y0 + 1);
+        for py in y0..y1 {
+            for px in x0..x1 {
+                self.blend(px, py, color, 255);
+            }
+        }
+    }
+
+    /// Composites a glyph image with its origin at the specified pixel.
+    fn draw_image(&mut self, image: &Image, x: i32, y: i32, color: [u8; 4]) {
+        let placement = image.placement;
+        let left = x + placement.left;
+        let top = y - placement.top;
+        let width = placement.width as usize;
+        for (row, py) in (top..top + placement.height as i32).enumerate() {
+            for (col, px) in (left..left + width as i32).enumerate() {
+                let offset = row * width + col;
+                match image.content {
+                    Content::Mask => self.blend(px, py, color, image.data[offset]),
+                    Content::SubpixelMask => {
+                        let a = &image.data[offset * 4..offset * 4 + 3];
+                        let coverage = ((a[0] as u32 + a[1] as u32 + a[2] as u32) / 3) as u8;
+                        self.blend(px, py, color, coverage);
+                    }
+                    Content::Color => {
+                        let c = &image.data[offset * 4..offset * 4 + 4];
+                        // Color outlines are composited by swash with
+                        // premultiplied alpha while color bitmaps are
+                        // decoded with straight alpha.
+                        let rgb = match image.source {
+                            Source::ColorOutline(_) => unpremultiply(c),
+                            _ => [c[0], c[1], c[2]],
+                        };
+                        self.blend(px, py, [rgb[0], rgb[1], rgb[2], 255], c[3]);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Renderer that rasterizes layouts into a canvas.
+///
+/// Glyphs are rendered from color outlines, color bitmaps or outlines, in
+/// that order, and synthetic bold and oblique are applied as suggested by
+/// the synthesis of each run. Inline boxes are not drawn.
+pub struct Renderer {
+    context: ScaleContext,
+    hint: bool,
+    subpixel: bool,
+}
+
+impl Renderer {
+    /// Creates a new renderer with hinting disabled and subpixel positioning
+    /// enabled.
+    pub fn new() -> Self {
+        Self {
+            context: ScaleContext::new(),
+            hint: false,
+            subpixel: true,
+        }
+    }
+
+    /// Sets whether glyph outlines are hinted.
+    pub fn set_hint(&mut self, yes: bool) {
+        self.hint = yes;
+    }
+
+    /// Sets whether glyphs are positioned at fractional horizontal offsets.
+    /// When disabled, glyph origins are snapped to whole pixels.
+    pub fn set_subpixel_positioning(&mut self, yes: bool) {
+        self.subpixel = yes;
+    }
+
+    /// Renders the layout into the canvas with the top left corner of the
+    /// layout at the specified position. The color function maps brushes
+    /// to RGBA colors with straight alpha.
+    ///
+    /// Glyphs of color fonts are drawn with their own colors. Underline and
+    /// strikethrough decorations are drawn for horizontal layouts.
+    pub fn render<B: Brush>(
+        &mut self,
+        layout: &Layout<B>,
+        x: f32,
+        y: f32,
+        canvas: &mut Canvas,
+        color: impl Fn(&B) -> [u8; 4],
+    ) {
+        self.render_layout(layout, x, y, canvas, &color);
+    }
+
+    fn render_layout<B: Brush>(
+        &mut self,
+        layout: &Layout<B>,
+        x: f32,
+        y: f32,
+        canvas: &mut Canvas,
+        color: &dyn Fn(&B) -> [u8; 4],
+    ) {
+        let horizontal = !layout.writing_mode().is_vertical();
+        for line in layout.lines() {
+            for glyph_run in line.glyph_runs() {
+                self.render_glyph_run(&glyph_run, x, y, canvas, color);
+            }
+            if horizontal {
+                for decoration in line.decorations() {
+                    let rect = decoration.rect;
+                    canvas.fill_rect(
+                        x + rect.x,
+                        y + rect.y,
+                        rect.width,
+                        rect.height,
+                        color(decoration.brush),
+                    );
+                }
+            }
+            for annotation in line.ruby_annotations() {
+                self.render_layout(
+                    annotation.layout(),
+                    x + annotation.x(),
+                    y + annotation.y(),
+                    canvas,
+                    color,
+                );
+            }
+        }
+    }
+
+    fn render_glyph_run<B: Brush>(
+        &mut self,
+        glyph_run: &GlyphRun<B>,
+        x: f32,
+        y: f32,
+        canvas: &mut Canvas,
+        color: &dyn Fn(&B) -> [u8; 4],
+    ) {
+        let run = glyph_run.run();
+        let brush_color = color(&glyph_run.style().brush);
+        let mut scaler = self
+            .context
+            .builder(run.font().as_ref())
+            .size(run.font_size())
+            .hint(self.hint)
+            .normalized_coords(run.normalized_coords())
+            .build();
+        let mut render = Render::new(SOURCES);
+        render
+            .format(Format::Alpha)
+            .transform(glyph_run.glyph_transform())
+            .embolden(glyph_run.embolden_strength())
+            .default_color(brush_color);
+        for glyph in glyph_run.positioned_glyphs() {
+            let (px, step) = snap(x + glyph.x, self.subpixel);
+            let py = (y + glyph.y).round() as i32;
+            render.offset(Vector::new(step as f32 / SUBPIXEL_STEPS, 0.));
+            if let Some(image) = render.render(&mut scaler, glyph.id) {
+                canvas.draw_image(&image, px, py, brush_color);
+            }
+        }
+    }
+}
+
+impl Default for Renderer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Converts the color channels of a pixel with premultiplied alpha to
+/// straight alpha.
+fn unpremultiply(pixel: &[u8]) -> [u8; 3] {
+    let alpha = pixel[3] as u32;
+    let mut rgb = [0; 3];
+    for (dst, src) in rgb.iter_mut().zip(pixel) {
+        let value = (*src as u32 * 255 + alpha / 2).checked_div(alpha);
+        *dst = value.unwrap_or(0).min(255) as u8;
+    }
+    rgb
+}
+
+/// Splits a horizontal position into a whole pixel and a subpixel step.
+fn snap(x: f32, subpixel: bool) -> (i32, u8) {
+    if subpixel {
+        let floor = x.floor();
+        let step = ((x - floor) * SUBPIXEL_STEPS) as u8;
+        (floor as i32, step.min(SUBPIXEL_STEPS as u8 - 1))
+    } else {
+        (x.round() as i32, 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn canvas_blends_straight_alpha_within_bounds() {
+        let mut data = [0; 16];
+        assert!(Canvas::with_stride(&mut data, 2, 2, 4).is_none());
+        assert!(Canvas::new(&mut data[..15], 2, 2).is_none());
+        let mut canvas = Canvas::new(&mut data, 2, 2).unwrap();
+        canvas.blend(-1, 0, [255, 255, 255, 255], 255);
+        canvas.blend(0, 2, [255, 255, 255, 255], 255);
+        canvas.blend(0, 0, [255, 0, 0, 255], 255);
+        canvas.blend(0, 0, [0, 0, 255, 255], 128);
+        canvas.blend(1, 0, [0, 255, 0, 128], 255);
+        canvas.blend(1, 1, [0, 255, 0, 255], 0);
+        assert_eq!(data[0..4], [127, 0, 128, 255]);
+        assert_eq!(data[4..8], [0, 255, 0, 128]);
+        assert_eq!(data[8..16], [0; 8]);
+    }
+
+    #[cfg(feature = "bundled-fonts")]
+    #[test]
+    fn glyph_coverage_lands_inside_the_layout() {
+        use crate::font::FontContext;
+        use crate::layout::{Alignment, Layout};
+        use crate::style::{FontFamily, FontStack, StyleProperty};
+        use crate::LayoutContext;
+
+        const MARGIN: usize = 8;
+        let mut fcx = FontContext::bundled();
+        let mut lcx = LayoutContext::new();
+        let mut builder = lcx.ranged_builder(&mut fcx, "Hello, world!\nParley", 1.);
+        builder.push_default(&StyleProperty::FontStack(FontStack::Single(
+            FontFamily::Named("DejaVu Serif"),
+        )));
+        builder.push_default(&StyleProperty::FontSize(24.));
+        builder.push_default(&StyleProperty::Brush([0, 0, 0, 255]));
+        let mut layout: Layout<[u8; 4]> = builder.build();
+        layout.break_all_lines(None, Alignment::Start);
+        let width = layout.width().ceil() as usize + MARGIN * 2;
+        let height = layout.height().ceil() as usize + MARGIN * 2;
+        let mut data = vec![0; width * height * 4];
+        let mut canvas = Canvas::new(&mut data, width, height).unwrap();
+        let origin = MARGIN as f32;
+        Renderer::new().render(&layout, origin, origin, &mut canvas, |brush| *brush);
+        let mut covered = 0;
+        for (i, pixel) in data.chunks(4).enumerate() {
+            if pixel[3] == 0 {
+                continue;
+            }
+            covered += 1;
+            let (x, y) = ((i % width) as f32, (i / width) as f32);
+            assert!(x >= origin - 1. && x <= origin + layout.width() + 1.);
+            assert!(y >= origin - 1. && y <= origin + layout.height() + 1.);
+        }
+        assert!(covered > 100);
+    }
+}
@@ -0,0 +1,666 @@
+//! Glyph cache backed by texture atlases.
+
+use super::{snap, unpremultiply, SOURCES, SUBPIXEL_STEPS};
+use crate::layout::GlyphRun;
+use crate::style::Brush;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use swash::scale::image::{Content, Image};
+use swash::scale::{Render, ScaleContext, Source};
+use swash::zeno::{Format, Transform, Vector};
+use swash::{GlyphId, NormalizedCoord};
+
+/// Padding in pixels between glyphs in an atlas.
+const PADDING: u32 = 1;
+
+/// Cache that rasterizes glyphs on demand and packs them into texture
+/// atlases.
+///
+/// Glyphs are keyed by font, size, variation coordinates, synthesis and
+/// subpixel offset. Atlas pages are stored in CPU memory as 8-bit RGBA
+/// pixels with straight alpha: masks are stored as white with coverage in
+/// the alpha channel and should be tinted with the brush color while color
+/// glyphs are drawn as is. Color outlines, which swash composites with
+/// premultiplied alpha, are converted to straight alpha when they are
+/// packed.
+///
+/// Pages are packed into shelves. When all pages are full, the least
+/// recently used shelf that has not been used in the current frame is
+/// evicted along with the variation coordinates that are no longer used by
+/// any cached glyph. If no such shelf is tall enough for a glyph, adjacent
+/// unused shelves are evicted and merged.
+pub struct GlyphCache {
+    context: ScaleContext,
+    hint: bool,
+    subpixel: bool,
+    atlas: Atlas,
+}
+
+impl GlyphCache {
+    /// Creates a new glyph cache with square pages of the specified size in
+    /// pixels and the maximum number of pages.
+    pub fn new(page_size: u32, max_pages: usize) -> Self {
+        Self {
+            context: ScaleContext::new(),
+            hint: false,
+            subpixel: true,
+            atlas: Atlas::new(page_size, max_pages),
+        }
+    }
+
+    /// Sets whether glyph outlines are hinted. Changing this setting clears
+    /// the cache.
+    pub fn set_hint(&mut self, yes: bool) {
+        if self.hint != yes {
+            self.hint = yes;
+            self.clear();
+        }
+    }
+
+    /// Sets whether glyphs are positioned at fractional horizontal offsets.
+    pub fn set_subpixel_positioning(&mut self, yes: bool) {
+        self.subpixel = yes;
+    }
+
+    /// Returns the atlas pages.
+    pub fn pages(&self) -> &[AtlasPage] {
+        &self.atlas.pages
+    }
+
+    /// Begins a new frame. Glyphs that were requested in the current frame
+    /// are never evicted, so atlas coordinates remain valid until the next
+    /// frame begins.
+    pub fn begin_frame(&mut self) {
+        self.atlas.frame += 1;
+    }
+
+    /// Removes all glyphs and pages from the cache.
+    pub fn clear(&mut self) {
+        self.atlas.pages.clear();
+        self.atlas.entries.clear();
+        self.atlas.blank.clear();
+        self.atlas.coords.clear();
+    }
+
+    /// Returns the atlas locations of the glyphs in the run, rasterizing
+    /// and packing glyphs that are not yet cached. The run is positioned
+    /// with the top left corner of its layout at the specified offset.
+    ///
+    /// Glyphs without an image, such as spaces, and glyphs that do not fit
+    /// into the atlas are omitted.
+    pub fn glyph_run<B: Brush>(
+        &mut self,
+        glyph_run: &GlyphRun<B>,
+        x: f32,
+        y: f32,
+    ) -> Vec<AtlasGlyph> {
+        let run = glyph_run.run();
+        let font = run.font().as_ref();
+        let synthesis = run.synthesis();
+        let transform = glyph_run.glyph_transform();
+        let coords = run.normalized_coords();
+        let coords_id = self.atlas.coords_id(coords);
+        let mut key = GlyphKey {
+            font: font.key.value(),
+            glyph_id: 0,
+            size: run.font_size().to_bits(),
+            coords: coords_id,
+            transform: {
+                let t = transform.unwrap_or(Transform::IDENTITY);
+                [
+                    t.xx.to_bits(),
+                    t.xy.to_bits(),
+                    t.yx.to_bits(),
+                    t.yy.to_bits(),
+                ]
+            },
+            embolden: synthesis.embolden(),
+            step: 0,
+        };
+        let mut scaler = self
+            .context
+            .builder(font)
+            .size(run.font_size())
+            .hint(self.hint)
+            .normalized_coords(coords)
+            .build();
+        let mut render = Render::new(SOURCES);
+        render
+            .format(Format::Alpha)
+            .transform(transform)
+            .embolden(glyph_run.embolden_strength())
+            .default_color([255; 4]);
+        let mut glyphs = vec![];
+        for glyph in glyph_run.positioned_glyphs() {
+            let (px, step) = snap(x + glyph.x, self.subpixel);
+            let py = (y + glyph.y).round() as i32;
+            key.glyph_id = glyph.id;
+            key.step = step;
+            let atlas = &mut self.atlas;
+            if atlas.blank.contains(&key) {
+                continue;
+            }
+            let entry = match atlas.entries.get(&key) {
+                Some(entry) => *entry,
+                None => {
+                    render.offset(Vector::new(step as f32 / SUBPIXEL_STEPS, 0.));
+                    let image = render.render(&mut scaler, glyph.id);
+                    let entry = match image {
+                        Some(image)
+                            if image.placement.width != 0 && image.placement.height != 0 =>
+                        {
+                            match atlas.insert(key, &image) {
+                                Some(entry) => entry,
+                                None => continue,
+                            }
+                        }
+                        _ => {
+                            atlas.blank.insert(key);
+                            continue;
+                        }
+                    };
+                    atlas.entries.insert(key, entry);
+                    entry
+                }
+            };
+            let page = &mut atlas.pages[entry.page as usize];
+            page.shelves[entry.shelf as usize].frame = atlas.frame;
+            glyphs.push(AtlasGlyph {
+                id: glyph.id,
+                page: entry.page as usize,
+                atlas_x: entry.x,
+                atlas_y: entry.y,
+                width: entry.width,
+                height: entry.height,
+                x: px + entry.left,
+                y: py - entry.top,
+                is_color: entry.is_color,
+            });
+        }
+        glyphs
+    }
+}
+
+/// Collection of atlas pages and the glyphs they contain.
+struct Atlas {
+    page_size: u32,
+    max_pages: usize,
+    pages: Vec<AtlasPage>,
+    entries: HashMap<GlyphKey, Entry>,
+    /// Glyphs without an image. They occupy no shelf and are dropped along
+    /// with their variation coordinates.
+    blank: HashSet<GlyphKey>,
+    /// Identifiers of the variation coordinates used by cached glyphs.
+    coords: HashMap<Vec<NormalizedCoord>, u32>,
+    next_coords_id: u32,
+    frame: u64,
+}
+
+impl Atlas {
+    fn new(page_size: u32, max_pages: usize) -> Self {
+        Self {
+            page_size,
+            max_pages: max_pages.max(1),
+            pages: vec![],
+            entries: HashMap::new(),
+            blank: HashSet::new(),
+            coords: HashMap::new(),
+            next_coords_id: 0,
+            frame: 0,
+        }
+    }
+
+    /// Returns the identifier of the variation coordinates, assigning a new
+    /// one if they are not in use.
+    fn coords_id(&mut self, coords: &[NormalizedCoord]) -> u32 {
+        if let Some(id) = self.coords.get(coords) {
+            return *id;
+        }
+        let id = self.next_coords_id;
+        self.next_coords_id = self.next_coords_id.wrapping_add(1);
+        self.coords.insert(coords.to_vec(), id);
+        id
+    }
+
+    /// Packs the non-empty image into an atlas page and returns the new
+    /// entry.
+    fn insert(&mut self, key: GlyphKey, image: &Image) -> Option<Entry> {
+        let width = image.placement.width;
+        let height = image.placement.height;
+        let is_color = matches!(image.content, Content::Color);
+        let (page_index, shelf_index, x, y) = self.allocate(width, height)?;
+        let page = &mut self.pages[page_index];
+        page.shelves[shelf_index].keys.push(key);
+        page.write(x, y, image);
+        Some(Entry {
+            page: page_index as u16,
+            shelf: shelf_index as u16,
+            x,
+            y,
+            width,
+            height,
+            left: image.placement.left,
+            top: image.placement.top,
+            is_color,
+        })
+    }
+
+    /// Allocates a rectangle of the specified size, adding pages or
+    /// evicting shelves as needed.
+    fn allocate(&mut self, width: u32, height: u32) -> Option<(usize, usize, u32, u32)> {
+        let padded_width = width + PADDING;
+        let padded_height = height + PADDING;
+        if padded_width > self.page_size || padded_height > self.page_size {
+            return None;
+        }
+        for (page_index, page) in self.pages.iter_mut().enumerate() {
+            if let Some((shelf_index, x, y)) = page.allocate(padded_width, padded_height) {
+                return Some((page_index, shelf_index, x, y));
+            }
+        }
+        if self.pages.len() < self.max_pages {
+            let mut page = AtlasPage::new(self.page_size, self.page_size);
+            let (shelf_index, x, y) = page.allocate(padded_width, padded_height)?;
+            self.pages.push(page);
+            return Some((self.pages.len() - 1, shelf_index, x, y));
+        }
+        // Evict the least recently used shelves that are tall enough,
+        // merging adjacent shelves when a single one is too short.
+        let frame = self.frame;
+        let (_, page_index, shelves) = self
+            .pages
+            .iter()
+            .enumerate()
+            .flat_map(|(page_index, page)| {
+                (0..page.shelves.len()).filter_map(move |start| {
+                    let (end, last_used) = page.unused_shelves(start, padded_height, frame)?;
+                    Some(((last_used, end - start), page_index, start..end))
+                })
+            })
+            .min_by_key(|(order, _, _)| *order)?;
+        let page = &mut self.pages[page_index];
+        let evicted = page.evict(shelves.clone());
+        for (index, shelf) in page.shelves.iter().enumerate().skip(shelves.start) {
+            for key in &shelf.keys {
+                if let Some(entry) = self.entries.get_mut(key) {
+                    entry.shelf = index as u16;
+                }
+            }
+        }
+        for key in &evicted {
+            self.entries.remove(key);
+        }
+        self.release_coords(&evicted);
+        let (shelf_index, x, y) = self.pages[page_index].allocate(padded_width, padded_height)?;
+        Some((page_index, shelf_index, x, y))
+    }
+
+    /// Removes the variation coordinates of the evicted glyphs that are no
+    /// longer used by any cached glyph.
+    fn release_coords(&mut self, evicted: &[GlyphKey]) {
+        let mut ids: Vec<u32> = evicted.iter().map(|key| key.coords).collect();
+        ids.sort_unstable();
+        ids.dedup();
+        ids.retain(|id| !self.entries.keys().any(|key| key.coords == *id));
+        if !ids.is_empty() {
+            self.coords.retain(|_, id| !ids.contains(id));
+            self.blank.retain(|key| !ids.contains(&key.coords));
+        }
+    }
+}
+
+/// Location of a cached glyph in an atlas page and its position in the
+/// target.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct AtlasGlyph {
+    /// Glyph identifier.
+    pub id: GlyphId,
+    /// Index of the atlas page.
+    pub page: usize,
+    /// Offset to the left edge of the glyph in the atlas.
+    pub atlas_x: u32,
+    /// Offset to the top edge of the glyph in the atlas.
+    pub atlas_y: u32,
+    /// Width of the glyph image in pixels.
+    pub width: u32,
+    /// Height of the glyph image in pixels.
+    pub height: u32,
+    /// Offset to the left edge of the glyph image in the target.
+    pub x: i32,
+    /// Offset to the top edge of the glyph image in the target.
+    pub y: i32,
+    /// True if the glyph image contains color rather than a mask.
+    pub is_color: bool,
+}
+
+/// Texture atlas containing glyph images.
+pub struct AtlasPage {
+    width: u32,
+    height: u32,
+    data: Vec<u8>,
+    shelves: Vec<Shelf>,
+    version: u64,
+}
+
+impl AtlasPage {
+    fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            data: vec![0; width as usize * height as usize * 4],
+            shelves: vec![],
+            version: 0,
+        }
+    }
+
+    /// Returns the width of the page in pixels.
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// Returns the height of the page in pixels.
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Returns the 8-bit RGBA pixels of the page.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Returns a value that changes whenever the contents of the page are
+    /// modified, which can be used to determine when the texture needs to be
+    /// uploaded again.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Allocates a rectangle in an existing or new shelf, choosing the
+    /// shelf that wastes the least height.
+    fn allocate(&mut self, width: u32, height: u32) -> Option<(usize, u32, u32)> {
+        let page_width = self.width;
+        let best = self
+            .shelves
+            .iter()
+            .enumerate()
+            .filter(|(_, shelf)| shelf.height >= height && shelf.x + width <= page_width)
+            .min_by_key(|(_, shelf)| shelf.height)
+            .map(|(index, _)| index);
+        if let Some(index) = best {
+            let (x, y) = self.shelves[index].allocate(width, page_width)?;
+            return Some((index, x, y));
+        }
+        let y = self.shelves.last().map(|s| s.y + s.height).unwrap_or(0);
+        // Round up to reduce the number of distinct shelf heights.
+        let shelf_height = ((height + 3) & !3).min(self.height - y.min(self.height));
+        if shelf_height < height {
+            return None;
+        }
+        self.shelves.push(Shelf {
+            y,
+            height: shelf_height,
+            x: 0,
+            frame: 0,
+            keys: vec![],
+        });
+        let index = self.shelves.len() - 1;
+        let (x, y) = self.shelves[index].allocate(width, page_width)?;
+        Some((index, x, y))
+    }
+
+    /// Returns the end of the shortest range of consecutive shelves
+    /// beginning at the specified index that were not used in the frame and
+    /// that span at least the specified height, including the free space at
+    /// the bottom of the page, along with the last frame in which any of the
+    /// shelves was used.
+    fn unused_shelves(&self, start: usize, height: u32, frame: u64) -> Option<(usize, u64)> {
+        let mut span = 0;
+        let mut last_used = 0;
+        for (index, shelf) in self.shelves.iter().enumerate().skip(start) {
+            if shelf.frame == frame {
+                return None;
+            }
+            span += shelf.height;
+            last_used = last_used.max(shelf.frame);
+            let free = if index + 1 == self.shelves.len() {
+                self.height - (shelf.y + shelf.height)
+            } else {
+                0
+            };
+            if span + free >= height {
+                return Some((index + 1, last_used));
+            }
+        }
+        None
+    }
+
+    /// Clears the shelves, merging them into a single empty shelf, and
+    /// returns the keys of the glyphs they contained. Shelves at the bottom
+    /// of the page are removed instead.
+    fn evict(&mut self, shelves: Range<usize>) -> Vec<GlyphKey> {
+        let y = self.shelves[shelves.start].y;
+        let last = &self.shelves[shelves.end - 1];
+        let height = last.y + last.height - y;
+        let stride = self.width as usize * 4;
+        let start = y as usize * stride;
+        let end = start + height as usize * stride;
+        for byte in &mut self.data[start..end] {
+            *byte = 0;
+        }
+        self.version += 1;
+        let keys = self.shelves[shelves.clone()]
+            .iter_mut()
+            .flat_map(|shelf| core::mem::take(&mut shelf.keys))
+            .collect();
+        if shelves.end == self.shelves.len() {
+            self.shelves.truncate(shelves.start);
+        } else {
+            let shelf = &mut self.shelves[shelves.start];
+            shelf.x = 0;
+            shelf.height = height;
+            self.shelves.drain(shelves.start + 1..shelves.end);
+        }
+        keys
+    }
+
+    /// Copies the image into the page at the specified position.
+    fn write(&mut self, x: u32, y: u32, image: &Image) {
+        let width = image.placement.width as usize;
+        let stride = self.width as usize * 4;
+        for row in 0..image.placement.height as usize {
+            let start = (y as usize + row) * stride + x as usize * 4;
+            let dst = &mut self.data[start..start + width * 4];
+            match image.content {
+                Content::Mask => {
+                    let src = &image.data[row * width..(row + 1) * width];
+                    for (pixel, coverage) in dst.chunks_exact_mut(4).zip(src) {
+                        pixel.copy_from_slice(&[255, 255, 255, *coverage]);
+                    }
+                }
+                Content::SubpixelMask => {
+                    let src = &image.data[row * width * 4..(row + 1) * width * 4];
+                    for (pixel, rgba) in dst.chunks_exact_mut(4).zip(src.chunks_exact(4)) {
+                        let coverage = (rgba[0] as u32 + rgba[1] as u32 + rgba[2] as u32) / 3;
+                        pixel.copy_from_slice(&[255, 255, 255, coverage as u8]);
+                    }
+                }
+                Content::Color => {
+                    let src = &image.data[row * width * 4..(row + 1) * width * 4];
+                    dst.copy_from_slice(src);
+                    if let Source::ColorOutline(_) = image.source {
+                        for pixel in dst.chunks_exact_mut(4) {
+                            let rgb = unpremultiply(pixel);
+                            pixel[..3].copy_from_slice(&rgb);
+                        }
+                    }
+                }
+            }
+        }
+        self.version += 1;
+    }
+}
+
+/// Horizontal strip of an atlas page holding glyphs of similar height.
+struct Shelf {
+    y: u32,
+    height: u32,
+    x: u32,
+    /// Frame in which a glyph in the shelf was last used.
+    frame: u64,
+    keys: Vec<GlyphKey>,
+}
+
+impl Shelf {
+    fn allocate(&mut self, width: u32, page_width: u32) -> Option<(u32, u32)> {
+        if self.x + width > page_width {
+            return None;
+        }
+        let x = self.x;
+        self.x += width;
+        Some((x, self.y))
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+struct GlyphKey {
+    font: u64,
+    glyph_id: GlyphId,
+    size: u32,
+    coords: u32,
+    transform: [u32; 4],
+    embolden: bool,
+    step: u8,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Entry {
+    page: u16,
+    shelf: u16,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    left: i32,
+    top: i32,
+    is_color: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use swash::scale::StrikeWith;
+
+    fn key(glyph_id: GlyphId, coords: u32) -> GlyphKey {
+        GlyphKey {
+            font: 0,
+            glyph_id,
+            size: 0,
+            coords,
+            transform: [0; 4],
+            embolden: false,
+            step: 0,
+        }
+    }
+
+    /// Inserts a mask of the specified size and marks it as used in the
+    /// current frame, like `GlyphCache::glyph_run`.
+    fn add(atlas: &mut Atlas, key: GlyphKey, size: u32) -> Option<Entry> {
+        let mut image = Image::new();
+        image.placement.width = size;
+        image.placement.height = size;
+        image.data = vec![255; (size * size) as usize];
+        let entry = atlas.insert(key, &image)?;
+        atlas.entries.insert(key, entry);
+        atlas.pages[entry.page as usize].shelves[entry.shelf as usize].frame = atlas.frame;
+        Some(entry)
+    }
+
+    #[test]
+    fn glyphs_are_packed_into_shelves() {
+        let mut atlas = Atlas::new(16, 2);
+        let positions: Vec<_> = (0..5)
+            .map(|id| add(&mut atlas, key(id, 0), 7).unwrap())
+            .map(|entry| (entry.page, entry.x, entry.y))
+            .collect();
+        assert_eq!(
+            positions,
+            [(0, 0, 0), (0, 8, 0), (0, 0, 8), (0, 8, 8), (1, 0, 0)]
+        );
+        let page = &atlas.pages[0];
+        let stride = page.width() as usize * 4;
+        assert_eq!(page.data()[8 * stride + 8 * 4..][..4], [255; 4]);
+        assert_eq!(page.data()[7 * stride + 7 * 4 + 3], 0);
+        assert!(add(&mut atlas, key(5, 0), 16).is_none());
+    }
+
+    #[test]
+    fn least_recently_used_shelf_is_evicted() {
+        let mut atlas = Atlas::new(16, 1);
+        let variable = atlas.coords_id(&[100]);
+        let default = atlas.coords_id(&[]);
+        atlas.frame = 1;
+        for id in 0..2 {
+            add(&mut atlas, key(id, variable), 7).unwrap();
+        }
+        for id in 2..4 {
+            add(&mut atlas, key(id, default), 7).unwrap();
+        }
+        atlas.blank.insert(key(7, variable));
+        atlas.blank.insert(key(8, default));
+        atlas.frame = 2;
+        atlas.pages[0].shelves[1].frame = atlas.frame;
+        let entry = add(&mut atlas, key(4, default), 7).unwrap();
+        assert_eq!((entry.shelf, entry.x, entry.y), (0, 0, 0));
+        assert!(!atlas.entries.contains_key(&key(0, variable)));
+        assert!(!atlas.entries.contains_key(&key(1, variable)));
+        assert!(atlas.entries.contains_key(&key(2, default)));
+        assert!(!atlas.coords.contains_key(&[100][..]));
+        assert!(atlas.coords.contains_key(&[][..]));
+        assert!(!atlas.blank.contains(&key(7, variable)));
+        assert!(atlas.blank.contains(&key(8, default)));
+        // Shelves used in the current frame are never evicted.
+        add(&mut atlas, key(5, default), 7).unwrap();
+        assert!(add(&mut atlas, key(6, default), 7).is_none());
+    }
+
+    #[test]
+    fn unused_shelves_are_merged_for_taller_glyphs() {
+        let mut atlas = Atlas::new(32, 1);
+        atlas.frame = 1;
+        for id in 0..8 {
+            add(&mut atlas, key(id, 0), 7).unwrap();
+        }
+        for id in 8..10 {
+            add(&mut atlas, key(id, 0), 15).unwrap();
+        }
+        assert_eq!(atlas.pages[0].shelves.len(), 3);
+        atlas.frame = 2;
+        atlas.pages[0].shelves[2].frame = atlas.frame;
+        let entry = add(&mut atlas, key(10, 0), 12).unwrap();
+        assert_eq!((entry.shelf, entry.x, entry.y), (0, 0, 0));
+        let shelves = &atlas.pages[0].shelves;
+        assert_eq!(shelves.len(), 2);
+        assert_eq!((shelves[0].y, shelves[0].height), (0, 16));
+        assert!((0..8).all(|id| !atlas.entries.contains_key(&key(id, 0))));
+        let moved = atlas.entries[&key(8, 0)];
+        assert_eq!((moved.shelf, moved.y), (1, 16));
+        assert_eq!(shelves[moved.shelf as usize].keys.len(), 2);
+        // Shelves used in the current frame are never merged.
+        assert!(add(&mut atlas, key(11, 0), 20).is_none());
+    }
+
+    #[test]
+    fn color_outlines_are_stored_with_straight_alpha() {
+        let mut page = AtlasPage::new(4, 4);
+        let mut image = Image::new();
+        image.content = Content::Color;
+        image.placement.width = 1;
+        image.placement.height = 1;
+        image.data = vec![64, 32, 0, 128];
+        image.source = Source::ColorBitmap(StrikeWith::BestFit);
+        page.write(0, 0, &image);
+        image.source = Source::ColorOutline(0);
+        page.write(1, 0, &image);
+        assert_eq!(page.data()[..8], [64, 32, 0, 128, 128, 64, 0, 128]);
+    }
+}
@@ -5,7 +5,7 @@ pub mod tree;
 
 use super::style::{
     Brush, FontFamily, FontFeature, FontSettings, FontStack, FontStretch, FontStyle, FontVariation,
-    FontWeight, StyleProperty,
+    FontWeight, Hyphens, StyleProperty, TabStop, TabStops, TextOrientation,
 };
 use crate::font::*;
 use crate::util::nearly_eq;
@@ -96,9 +96,11 @@ pub struct ResolveContext {
     families: Cache<FamilyId>,
     variations: Cache<Setting<f32>>,
     features: Cache<Setting<u16>>,
+    tab_stops: Cache<TabStop>,
     tmp_families: Vec<FamilyId>,
     tmp_variations: Vec<Setting<f32>>,
     tmp_features: Vec<Setting<u16>>,
+    tmp_tab_stops: Vec<TabStop>,
 }
 
 impl ResolveContext {
@@ -132,6 +134,13 @@ impl ResolveContext {
             StyleProperty::LineHeight(value) => LineHeight(*value),
             StyleProperty::WordSpacing(value) => WordSpacing(*value * scale),
             StyleProperty::LetterSpacing(value) => LetterSpacing(*value * scale),
+            StyleProperty::Hyphens(value) => Hyphens(*value),
+            StyleProperty::TextOrientation(value) => TextOrientation(*value),
+            StyleProperty::TextCombineUpright(value) => TextCombineUpright(*value),
+            StyleProperty::TabStops(value) => {
+                TabStops(value.map(|stops| self.resolve_tab_stops(stops, scale)))
+            }
+            StyleProperty::RubySize(value) => RubySize(*value),
         }
     }
 
@@ -143,39 +152,36 @@ impl ResolveContext {
                 for family in FontFamily::parse_list(source) {
                     match family {
                         FontFamily::Named(name) => {
-                            if let Some(family) = fcx.cache.context.family_by_name(name) {
+                            if let Some(family) = fcx.cache.family_by_name(name) {
                                 self.tmp_families.push(family.id());
                             }
                         }
                         FontFamily::Generic(family) => {
-                            self.tmp_families
-                                .extend_from_slice(fcx.cache.context.generic_families(family));
+                            fcx.cache.generic_families(family, &mut self.tmp_families);
                         }
                     }
                 }
             }
             FontStack::Single(family) => match family {
                 FontFamily::Named(name) => {
-                    if let Some(family) = fcx.cache.context.family_by_name(name) {
+                    if let Some(family) = fcx.cache.family_by_name(name) {
                         self.tmp_families.push(family.id());
                     }
                 }
                 FontFamily::Generic(family) => {
-                    self.tmp_families
-                        .extend_from_slice(fcx.cache.context.generic_families(family));
+                    fcx.cache.generic_families(family, &mut self.tmp_families);
                 }
             },
             FontStack::List(families) => {
                 for family in families {
                     match family {
                         FontFamily::Named(name) => {
-                            if let Some(family) = fcx.cache.context.family_by_name(name) {
+                            if let Some(family) = fcx.cache.family_by_name(name) {
                                 self.tmp_families.push(family.id());
                             }
                         }
                         FontFamily::Generic(family) => {
-                            self.tmp_families
-                                .extend_from_slice(fcx.cache.context.generic_families(*family));
+                            fcx.cache.generic_families(*family, &mut self.tmp_families);
                         }
                     }
                 }
@@ -235,6 +241,28 @@ impl ResolveContext {
         resolved
     }
 
+    /// Resolves tab stops.
+    pub fn resolve_tab_stops(&mut self, stops: TabStops, scale: f32) -> ResolvedTabStops {
+        self.tmp_tab_stops.clear();
+        self.tmp_tab_stops
+            .extend(stops.stops.iter().map(|stop| TabStop {
+                position: stop.position * scale,
+                alignment: stop.alignment,
+            }));
+        // Tabs advance to the first stop past their position.
+        self.tmp_tab_stops.sort_by(|a, b| {
+            a.position
+                .partial_cmp(&b.position)
+                .unwrap_or(core::cmp::Ordering::Equal)
+        });
+        let resolved = self.tab_stops.insert(&self.tmp_tab_stops);
+        self.tmp_tab_stops.clear();
+        ResolvedTabStops {
+            stops: resolved,
+            interval: stops.interval * scale,
+        }
+    }
+
     /// Returns the list of font families for the specified handle.
     pub fn stack(&self, stack: Resolved<FamilyId>) -> Option<&[FamilyId]> {
         self.families.get(stack)
@@ -250,11 +278,17 @@ impl ResolveContext {
         self.features.get(features)
     }
 
+    /// Returns the list of tab stops for the specified handle.
+    pub fn tab_stops(&self, stops: Resolved<TabStop>) -> Option<&[TabStop]> {
+        self.tab_stops.get(stops)
+    }
+
     /// Clears the resources in the context.
     pub fn clear(&mut self) {
         self.families.clear();
         self.variations.clear();
         self.features.clear();
+        self.tab_stops.clear();
     }
 }
 
@@ -301,6 +335,16 @@ pub enum ResolvedProperty<B: Brush> {
     WordSpacing(f32),
     /// Extra spacing between letters.
     LetterSpacing(f32),
+    /// Control over hyphenation.
+    Hyphens(Hyphens),
+    /// Orientation of characters in vertical writing modes.
+    TextOrientation(TextOrientation),
+    /// Horizontal composition within vertical text.
+    TextCombineUpright(bool),
+    /// Tab stops.
+    TabStops(Option<ResolvedTabStops>),
+    /// Font size multiplier for ruby annotations.
+    RubySize(f32),
 }
 
 /// Flattened group of style properties.
@@ -334,6 +378,16 @@ pub struct ResolvedStyle<B: Brush> {
     pub word_spacing: f32,
     /// Extra spacing between letters.
     pub letter_spacing: f32,
+    /// Control over hyphenation.
+    pub hyphens: Hyphens,
+    /// Orientation of characters in vertical writing modes.
+    pub text_orientation: TextOrientation,
+    /// Horizontal composition within vertical text.
+    pub text_combine_upright: bool,
+    /// Tab stops.
+    pub tab_stops: Option<ResolvedTabStops>,
+    /// Font size multiplier for ruby annotations.
+    pub ruby_size: f32,
 }
 
 impl<B: Brush> Default for ResolvedStyle<B> {
@@ -353,6 +407,11 @@ impl<B: Brush> Default for ResolvedStyle<B> {
             line_height: 1.,
             word_spacing: 0.,
             letter_spacing: 0.,
+            hyphens: Default::default(),
+            text_orientation: Default::default(),
+            text_combine_upright: false,
+            tab_stops: None,
+            ruby_size: 0.5,
         }
     }
 }
@@ -382,6 +441,11 @@ impl<B: Brush> ResolvedStyle<B> {
             LineHeight(value) => self.line_height = value,
             WordSpacing(value) => self.word_spacing = value,
             LetterSpacing(value) => self.letter_spacing = value,
+            Hyphens(value) => self.hyphens = value,
+            TextOrientation(value) => self.text_orientation = value,
+            TextCombineUpright(value) => self.text_combine_upright = value,
+            TabStops(value) => self.tab_stops = value,
+            RubySize(value) => self.ruby_size = value,
         }
     }
 
@@ -408,6 +472,11 @@ impl<B: Brush> ResolvedStyle<B> {
             LineHeight(value) => nearly_eq(self.line_height, *value),
             WordSpacing(value) => nearly_eq(self.word_spacing, *value),
             LetterSpacing(value) => nearly_eq(self.letter_spacing, *value),
+            Hyphens(value) => self.hyphens == *value,
+            TextOrientation(value) => self.text_orientation == *value,
+            TextCombineUpright(value) => self.text_combine_upright == *value,
+            TabStops(value) => self.tab_stops == *value,
+            RubySize(value) => nearly_eq(self.ruby_size, *value),
         }
     }
 }
@@ -424,3 +493,12 @@ pub struct ResolvedDecoration<B: Brush> {
     /// Brush for the decoration.
     pub brush: Option<B>,
 }
+
+/// Tab stops with resolved resources.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct ResolvedTabStops {
+    /// Explicit tab stops.
+    pub stops: Resolved<TabStop>,
+    /// Interval between the uniform tab stops.
+    pub interval: f32,
+}
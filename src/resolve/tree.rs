@@ -1 +1,103 @@
 //! Hierachical tree based style application.
+
+use super::range::RangedStyle;
+use super::*;
+
+/// Builder for constructing an ordered sequence of non-overlapping ranged
+/// styles from a tree of style spans and text nodes.
+#[derive(Clone)]
+pub struct TreeStyleBuilder<B: Brush> {
+    /// Stack of styles for the currently open spans. The first entry is
+    /// the root style.
+    tree: Vec<ResolvedStyle<B>>,
+    /// Flattened sequence of styles for the text pushed so far.
+    flat: Vec<RangedStyle<B>>,
+    /// Accumulated text.
+    text: String,
+}
+
+impl<B: Brush> Default for TreeStyleBuilder<B> {
+    fn default() -> Self {
+        Self {
+            tree: vec![],
+            flat: vec![],
+            text: String::new(),
+        }
+    }
+}
+
+impl<B: Brush> TreeStyleBuilder<B> {
+    /// Prepares the builder for accepting a new tree of styles and text.
+    pub fn begin(&mut self) {
+        self.tree.clear();
+        self.tree.push(ResolvedStyle::default());
+        self.flat.clear();
+        self.text.clear();
+    }
+
+    /// Pushes a property that applies to the root of the tree. This only
+    /// affects text that is pushed after the call.
+    pub fn push_default(&mut self, property: ResolvedProperty<B>) {
+        assert!(!self.tree.is_empty());
+        self.tree[0].apply(property);
+    }
+
+    /// Returns the style of the innermost open span.
+    pub fn current_style(&self) -> ResolvedStyle<B> {
+        self.tree.last().cloned().unwrap_or_default()
+    }
+
+    /// Opens a new span with the specified fully resolved style.
+    pub fn push_style_span(&mut self, style: ResolvedStyle<B>) {
+        assert!(!self.tree.is_empty());
+        self.tree.push(style);
+    }
+
+    /// Closes the innermost open span. The root span is never removed.
+    pub fn pop_style_span(&mut self) {
+        if self.tree.len() > 1 {
+            self.tree.pop();
+        }
+    }
+
+    /// Appends text with the style of the innermost open span.
+    pub fn push_text(&mut self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        let style = self.tree.last().expect("builder not initialized");
+        let start = self.text.len();
+        self.text.push_str(text);
+        let end = self.text.len();
+        if let Some(last) = self.flat.last_mut() {
+            if last.range.end == start && last.style == *style {
+                last.range.end = end;
+                return;
+            }
+        }
+        self.flat.push(RangedStyle {
+            style: style.clone(),
+            range: start..end,
+        });
+    }
+
+    /// Returns the length of the text pushed so far.
+    pub fn text_len(&self) -> usize {
+        self.text.len()
+    }
+
+    /// Computes the sequence of ranged styles and returns the accumulated
+    /// text. The builder is left unchanged so that the same tree can be
+    /// finished again.
+    pub fn finish(&self, styles: &mut Vec<RangedStyle<B>>) -> String {
+        if self.flat.is_empty() {
+            styles.push(RangedStyle {
+                style: self.tree.first().cloned().unwrap_or_default(),
+                range: 0..0,
+            });
+        } else {
+            styles.extend(self.flat.iter().cloned());
+        }
+        self.text.clone()
+    }
+}
@@ -1,13 +1,30 @@
 use super::font::{Font, FontContext};
-use super::layout::Layout;
+use super::layout::data::{LayoutData, HYPHENS};
+use super::layout::{InlineBox, Layout};
+use super::orientation;
 use super::resolve::range::RangedStyle;
-use super::resolve::{ResolveContext, Resolved};
-use super::style::{Brush, FontFeature, FontVariation};
+use super::resolve::{ResolveContext, Resolved, ResolvedStyle};
+use super::style::{Brush, FontFeature, FontVariation, TextOrientation};
 use crate::util::nearly_eq;
 use swash::shape::*;
-use swash::text::cluster::{CharCluster, CharInfo, Token};
+use swash::text::cluster::{Boundary, CharCluster, CharInfo, Parser, Token};
 use swash::text::{Language, Script};
-use swash::{Attributes, FontRef, Synthesis};
+use swash::{tag_from_bytes, Attributes, FontRef, Synthesis};
+
+/// Features applied to upright text in vertical layouts.
+const VERTICAL_FEATURES: [FontFeature; 2] = [
+    FontFeature {
+        tag: tag_from_bytes(b"vert"),
+        value: 1,
+    },
+    FontFeature {
+        tag: tag_from_bytes(b"vrt2"),
+        value: 1,
+    },
+];
+
+/// Width variant features for text combined upright, by character count.
+const COMBINE_FEATURES: [&[u8; 4]; 3] = [b"hwid", b"twid", b"qwid"];
 
 struct Item {
     style_index: u16,
@@ -19,14 +36,19 @@ struct Item {
     features: Resolved<FontFeature>,
     word_spacing: f32,
     letter_spacing: f32,
+    upright: bool,
+    combine: bool,
 }
 
+#[allow(unused_assignments)]
 pub fn shape_text<B: Brush>(
     rcx: &ResolveContext,
     fcx: &mut FontContext,
     styles: &[RangedStyle<B>],
     infos: &[(CharInfo, u16)],
     levels: &[u8],
+    inline_boxes: &[InlineBox],
+    hyphen_breaks: &[usize],
     scx: &mut ShapeContext,
     text: &str,
     layout: &mut Layout<B>,
@@ -34,6 +56,15 @@ pub fn shape_text<B: Brush>(
     if text.is_empty() || styles.is_empty() {
         return;
     }
+    let is_vertical = layout.data.writing_mode.is_vertical();
+    let is_upright = |ch: char, style: &ResolvedStyle<B>| {
+        is_vertical
+            && match style.text_orientation {
+                TextOrientation::Mixed => orientation::is_upright(ch),
+                TextOrientation::Upright => true,
+                TextOrientation::Sideways => false,
+            }
+    };
     let mut style = &styles[0].style;
     let mut item = Item {
         style_index: 0,
@@ -49,9 +80,16 @@ pub fn shape_text<B: Brush>(
         features: style.font_features,
         word_spacing: style.word_spacing,
         letter_spacing: style.letter_spacing,
+        upright: !style.text_combine_upright
+            && text.chars().next().map(|ch| is_upright(ch, style)) == Some(true),
+        combine: is_vertical && style.text_combine_upright,
     };
     let mut char_range = 0..0;
     let mut text_range = 0..0;
+    // Index of the next inline box to insert.
+    let mut next_box = 0;
+    // True if the current item immediately follows an inline box.
+    let mut follows_box = false;
     macro_rules! shape_item {
         () => {
             let item_text = &text[text_range.clone()];
@@ -65,19 +103,43 @@ pub fn shape_text<B: Brush>(
                 item.script,
                 item.locale,
             );
+            let mut vertical_features = vec![];
+            let mut features = rcx.features(item.features).unwrap_or(&[]);
+            if item.combine {
+                // Select glyphs that fit the em box for short sequences.
+                if let Some(tag) = char_range
+                    .len()
+                    .checked_sub(2)
+                    .and_then(|i| COMBINE_FEATURES.get(i))
+                {
+                    vertical_features.extend_from_slice(features);
+                    vertical_features.push(FontFeature {
+                        tag: tag_from_bytes(tag),
+                        value: 1,
+                    });
+                    features = &vertical_features;
+                }
+            } else if item.upright {
+                vertical_features.extend_from_slice(features);
+                vertical_features.extend_from_slice(&VERTICAL_FEATURES);
+                features = &vertical_features;
+            }
             let options = partition::SimpleShapeOptions {
                 size: item.size,
                 script: item.script,
                 language: item.locale,
-                direction: if item.level & 1 != 0 {
+                // Upright and combined text is always set in a left-to-right
+                // direction.
+                direction: if item.level & 1 != 0 && !item.upright && !item.combine {
                     Direction::RightToLeft
                 } else {
                     Direction::LeftToRight
                 },
                 variations: rcx.variations(item.variations).unwrap_or(&[]),
-                features: rcx.features(item.features).unwrap_or(&[]),
+                features,
                 insert_dotted_circles: false,
             };
+            let first_run = layout.data.runs.len();
             partition::shape(
                 scx,
                 &mut fs,
@@ -87,7 +149,12 @@ pub fn shape_text<B: Brush>(
                         ch,
                         offset: (text_range.start + offset) as u32,
                         len: ch.len_utf8() as u8,
-                        info: *info,
+                        info: if follows_box && offset == 0 {
+                            // Allow a line break after an inline box.
+                            CharInfo::new(info.properties(), Boundary::Line)
+                        } else {
+                            *info
+                        },
                         data: *style_index as _,
                     },
                 ),
@@ -103,6 +170,13 @@ pub fn shape_text<B: Brush>(
                     );
                 },
             );
+            if item.combine {
+                layout.data.combine_upright(first_run);
+            } else if item.upright {
+                layout.data.apply_vertical_advances(first_run);
+            }
+            resolve_hyphens(&mut fs, &mut layout.data, first_run, hyphen_breaks);
+            follows_box = false;
         };
     }
     for ((char_index, ch), (info, style_index)) in text.chars().enumerate().zip(infos) {
@@ -112,6 +186,34 @@ pub fn shape_text<B: Brush>(
             script = item.script;
         }
         let level = levels.get(char_index).copied().unwrap_or(0);
+        if inline_boxes
+            .get(next_box)
+            .map(|b| b.index <= text_range.end)
+            .unwrap_or(false)
+        {
+            if !text_range.is_empty() {
+                shape_item!();
+                text_range.start = text_range.end;
+                char_range.start = char_range.end;
+            }
+            // A box placed after a mandatory break begins the new line.
+            let mut boundary = if info.boundary() == Boundary::Mandatory {
+                Boundary::Mandatory
+            } else {
+                Boundary::Line
+            };
+            while let Some(inline_box) = inline_boxes
+                .get(next_box)
+                .filter(|b| b.index <= text_range.end)
+            {
+                layout
+                    .data
+                    .push_inline_box(*inline_box, *style_index, level, boundary);
+                boundary = Boundary::Line;
+                next_box += 1;
+            }
+            follows_box = true;
+        }
         if item.style_index != *style_index {
             item.style_index = *style_index;
             style = &styles[*style_index as usize].style;
@@ -125,11 +227,20 @@ pub fn shape_text<B: Brush>(
                 break_run = true;
             }
         }
-        if break_run || level != item.level || script != item.script {
+        let combine = is_vertical && style.text_combine_upright;
+        let upright = !combine && is_upright(ch, style);
+        if break_run
+            || level != item.level
+            || script != item.script
+            || upright != item.upright
+            || combine != item.combine
+        {
             shape_item!();
             item.size = style.font_size;
             item.level = level;
             item.script = script;
+            item.upright = upright;
+            item.combine = combine;
             item.locale = style.locale;
             item.variations = style.font_variations;
             item.features = style.font_features;
@@ -142,6 +253,52 @@ pub fn shape_text<B: Brush>(
     if !text_range.is_empty() {
         shape_item!();
     }
+    if next_box < inline_boxes.len() {
+        let style_index = infos.last().map(|info| info.1).unwrap_or(0);
+        let level = levels.last().copied().unwrap_or(item.level);
+        let mut boundary = if text.ends_with('\n') {
+            Boundary::Mandatory
+        } else {
+            Boundary::Line
+        };
+        for inline_box in &inline_boxes[next_box..] {
+            layout
+                .data
+                .push_inline_box(*inline_box, style_index, level, boundary);
+            boundary = Boundary::Line;
+        }
+    }
+}
+
+/// Resolves hyphens for the runs starting at the specified index that can
+/// end a line at one of the sorted hyphenation offsets, using the fallback
+/// fonts when the font of a run does not support them.
+fn resolve_hyphens<B: Brush>(
+    fs: &mut FontSelector<B>,
+    layout: &mut LayoutData<B>,
+    first_run: usize,
+    hyphen_breaks: &[usize],
+) {
+    for index in first_run..layout.runs.len() {
+        let run = &layout.runs[index];
+        // A break at the end of the run is taken at the start of the next.
+        let next = hyphen_breaks.partition_point(|&offset| offset <= run.text_range.start);
+        let ends_line = hyphen_breaks
+            .get(next)
+            .map(|&offset| offset <= run.text_range.end)
+            .unwrap_or(false);
+        if run.inline_box.is_some() || !ends_line {
+            continue;
+        }
+        let style_index = layout.clusters[run.cluster_range.start].style_index;
+        let hyphen = layout.inserted_glyph(index, HYPHENS).or_else(|| {
+            HYPHENS.iter().find_map(|&(ch, count)| {
+                let selected = fs.select_char(ch, style_index)?;
+                layout.push_fallback_glyph(index, selected.font, selected.synthesis, ch, count)
+            })
+        });
+        layout.runs[index].hyphen = hyphen;
+    }
 }
 
 fn real_script(script: Script) -> bool {
@@ -191,6 +348,25 @@ impl<'a, B: Brush> FontSelector<'a, B> {
     }
 }
 
+impl<'a, B: Brush> FontSelector<'a, B> {
+    /// Selects a font for a single character with the specified style.
+    fn select_char(&mut self, ch: char, style_index: u16) -> Option<SelectedFont> {
+        let token = Token {
+            ch,
+            offset: 0,
+            len: ch.len_utf8() as u8,
+            info: ch.into(),
+            data: style_index as _,
+        };
+        let mut cluster = CharCluster::new();
+        if !Parser::new(Script::Latin, core::iter::once(token)).next(&mut cluster) {
+            return None;
+        }
+        use partition::Selector;
+        self.select_font(&mut cluster)
+    }
+}
+
 impl<'a, B: Brush> partition::Selector for FontSelector<'a, B> {
     type SelectedFont = SelectedFont;
 
@@ -52,4 +52,99 @@ pub enum StyleProperty<'a, B: Brush> {
     WordSpacing(f32),
     /// Extra spacing between letters.
     LetterSpacing(f32),
+    /// Control over where words may be hyphenated.
+    Hyphens(Hyphens),
+    /// Orientation of characters in vertical writing modes.
+    TextOrientation(TextOrientation),
+    /// Composition of the text horizontally within a single upright em box
+    /// in vertical writing modes (tate-chu-yoko).
+    TextCombineUpright(bool),
+    /// Tab stops for positioning text that follows a tab character. If
+    /// `None`, tabs keep the advance provided by the font.
+    TabStops(Option<TabStops<'a>>),
+    /// Font size of ruby annotations as a multiple of the font size at the
+    /// start of their base text.
+    RubySize(f32),
+}
+
+/// Control over hyphenation of words at line boundaries.
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+pub enum Hyphens {
+    /// Words are never hyphenated, even at soft hyphens.
+    None,
+    /// Words are only hyphenated at soft hyphens (U+00AD).
+    #[default]
+    Manual,
+    /// Words are hyphenated at soft hyphens and at points determined by the
+    /// hyphenation patterns registered for the locale of the text.
+    Auto,
+}
+
+/// Orientation of characters in vertical writing modes. This has no effect
+/// on horizontal layouts.
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+pub enum TextOrientation {
+    /// Characters are set upright or sideways according to their vertical
+    /// orientation property (UAX #50).
+    #[default]
+    Mixed,
+    /// All characters are set upright.
+    Upright,
+    /// All characters are set sideways, rotated 90 degrees clockwise.
+    Sideways,
+}
+
+/// Positions to which tab characters advance the text that follows them.
+///
+/// Tab stops are measured from the start of the line, so a tab on a wrapped
+/// line advances to a stop of that line. Tabs are resolved again whenever
+/// lines are broken.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct TabStops<'a> {
+    /// Explicit tab stops. These are sorted by position when the style is
+    /// resolved.
+    pub stops: &'a [TabStop],
+    /// Interval between the uniform tab stops that follow the explicit
+    /// stops. Tabs beyond the last explicit stop collapse if this is zero.
+    pub interval: f32,
+}
+
+impl<'a> TabStops<'a> {
+    /// Creates tab stops at every multiple of the specified interval.
+    pub fn uniform(interval: f32) -> Self {
+        Self {
+            stops: &[],
+            interval,
+        }
+    }
+
+    /// Creates tab stops from a list, followed by uniform stops at the
+    /// specified interval.
+    pub fn new(stops: &'a [TabStop], interval: f32) -> Self {
+        Self { stops, interval }
+    }
+}
+
+/// Tab stop at a fixed position.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct TabStop {
+    /// Offset of the stop from the start of the line.
+    pub position: f32,
+    /// Alignment of the text following the tab relative to the stop.
+    pub alignment: TabAlignment,
+}
+
+/// Alignment of the text following a tab relative to its tab stop.
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+pub enum TabAlignment {
+    /// Text begins at the stop.
+    #[default]
+    Left,
+    /// Text is centered on the stop.
+    Center,
+    /// Text ends at the stop.
+    Right,
+    /// The first occurrence of the specified decimal separator is placed
+    /// at the stop. Text without a separator ends at the stop.
+    Decimal(char),
 }
@@ -0,0 +1,320 @@
+//! Export of layouts as SVG documents.
+
+use super::layout::{GlyphRun, Layout, Line, LineItem};
+use super::style::Brush;
+use core::fmt::Write;
+use swash::scale::ScaleContext;
+use swash::zeno::Command;
+
+/// Trait for brushes that can be written as SVG colors.
+pub trait SvgBrush: Brush {
+    /// Returns the RGBA color of the brush with straight alpha. If `None`,
+    /// no fill is written and the SVG default applies.
+    fn svg_color(&self) -> Option<[u8; 4]> {
+        None
+    }
+}
+
+impl SvgBrush for () {}
+
+impl SvgBrush for [u8; 4] {
+    fn svg_color(&self) -> Option<[u8; 4]> {
+        Some(*self)
+    }
+}
+
+impl SvgBrush for [u8; 3] {
+    fn svg_color(&self) -> Option<[u8; 4]> {
+        Some([self[0], self[1], self[2], 255])
+    }
+}
+
+/// Debug overlays drawn on top of the text of horizontal layouts.
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+pub struct DebugOverlays {
+    /// Draws the box of each line, including leading.
+    pub line_boxes: bool,
+    /// Draws the baseline of each line.
+    pub baselines: bool,
+    /// Draws the bounds of each cluster.
+    pub cluster_bounds: bool,
+}
+
+/// Exporter that writes layouts as SVG documents.
+///
+/// Glyphs are written as outlines, with one `<path>` element per glyph
+/// run, and decorations as `<rect>` elements. Ruby annotations are written
+/// as nested groups. Inline boxes are only drawn as line box overlays.
+pub struct SvgExporter {
+    context: ScaleContext,
+    overlays: DebugOverlays,
+}
+
+impl SvgExporter {
+    /// Creates a new exporter without debug overlays.
+    pub fn new() -> Self {
+        Self {
+            context: ScaleContext::new(),
+            overlays: DebugOverlays::default(),
+        }
+    }
+
+    /// Sets the debug overlays that are drawn on top of the text.
+    pub fn set_debug_overlays(&mut self, overlays: DebugOverlays) {
+        self.overlays = overlays;
+    }
+
+    /// Returns an SVG document for the layout.
+    pub fn export<B: SvgBrush>(&mut self, layout: &Layout<B>) -> String {
+        let width = layout.full_width().ceil();
+        let height = layout.height().ceil();
+        let mut svg = String::new();
+        let _ = writeln!(
+            svg,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" viewBox="0 0 {} {}">"#,
+            width, height, width, height
+        );
+        self.write_layout(&mut svg, layout);
+        svg.push_str("</svg>\n");
+        svg
+    }
+
+    fn write_layout<B: SvgBrush>(&mut self, svg: &mut String, layout: &Layout<B>) {
+        let horizontal = !layout.writing_mode().is_vertical();
+        for line in layout.lines() {
+            for item in line.items() {
+                match item {
+                    LineItem::GlyphRun(glyph_run) => self.write_glyph_run(svg, &glyph_run),
+                    LineItem::InlineBox(inline_box) => {
+                        if horizontal && self.overlays.line_boxes {
+                            write_rect(
+                                svg,
+                                inline_box.x,
+                                inline_box.y,
+                                inline_box.width,
+                                inline_box.height,
+                                r##"fill="none" stroke="#00a000" stroke-width="0.5""##,
+                            );
+                        }
+                    }
+                }
+            }
+            if horizontal {
+                for decoration in line.decorations() {
+                    let rect = decoration.rect;
+                    write_rect(
+                        svg,
+                        rect.x,
+                        rect.y,
+                        rect.width,
+                        rect.height,
+                        &fill(decoration.brush),
+                    );
+                }
+            }
+            for annotation in line.ruby_annotations() {
+                let _ = writeln!(
+                    svg,
+                    r#"<g transform="translate({} {})">"#,
+                    num(annotation.x()),
+                    num(annotation.y())
+                );
+                self.write_layout(svg, annotation.layout());
+                svg.push_str("</g>\n");
+            }
+            if horizontal {
+                self.write_overlays(svg, &line);
+            }
+        }
+    }
+
+    fn write_glyph_run<B: SvgBrush>(&mut self, svg: &mut String, glyph_run: &GlyphRun<B>) {
+        let mut data = String::new();
+        for outline in glyph_run.outlines(&mut self.context) {
+            for command in outline.commands {
+                let _ = match command {
+                    Command::MoveTo(p) => write!(data, "M{},{}", num(p.x), num(p.y)),
+                    Command::LineTo(p) => write!(data, "L{},{}", num(p.x), num(p.y)),
+                    Command::CurveTo(a, b, p) => write!(
+                        data,
+                        "C{},{} {},{} {},{}",
+                        num(a.x),
+                        num(a.y),
+                        num(b.x),
+                        num(b.y),
+                        num(p.x),
+                        num(p.y)
+                    ),
+                    Command::QuadTo(a, p) => {
+                        write!(data, "Q{},{} {},{}", num(a.x), num(a.y), num(p.x), num(p.y))
+                    }
+                    Command::Close => write!(data, "Z"),
+                };
+            }
+        }
+        if data.is_empty() {
+            return;
+        }
+        let _ = writeln!(
+            svg,
+            r#"<path d="{}"{}/>"#,
+            data,
+            fill(&glyph_run.style().brush)
+        );
+    }
+
+    fn write_overlays<B: SvgBrush>(&self, svg: &mut String, line: &Line<B>) {
+        let metrics = line.metrics();
+        if self.overlays.line_boxes {
+            write_rect(
+                svg,
+                metrics.offset,
+                metrics.baseline - metrics.ascent - metrics.leading * 0.5,
+                metrics.advance,
+                metrics.size(),
+                r##"fill="none" stroke="#0000ff" stroke-width="0.5""##,
+            );
+        }
+        if self.overlays.baselines {
+            let _ = writeln!(
+                svg,
+                r##"<line x1="{}" y1="{}" x2="{}" y2="{}" stroke="#ff0000" stroke-width="0.5"/>"##,
+                num(metrics.offset),
+                num(metrics.baseline),
+                num(metrics.offset + metrics.advance),
+                num(metrics.baseline)
+            );
+        }
+        if self.overlays.cluster_bounds {
+            let top = metrics.baseline - metrics.ascent;
+            let height = metrics.ascent + metrics.descent;
+            for glyph_run in line.glyph_runs() {
+                let mut x = glyph_run.offset();
+                for cluster in glyph_run.visual_clusters() {
+                    let advance = cluster.advance();
+                    write_rect(
+                        svg,
+                        x,
+                        top,
+                        advance,
+                        height,
+                        r##"fill="none" stroke="#ff00ff" stroke-width="0.25""##,
+                    );
+                    x += advance;
+                }
+            }
+        }
+    }
+}
+
+impl Default for SvgExporter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn write_rect(svg: &mut String, x: f32, y: f32, width: f32, height: f32, attributes: &str) {
+    let _ = writeln!(
+        svg,
+        r#"<rect x="{}" y="{}" width="{}" height="{}" {}/>"#,
+        num(x),
+        num(y),
+        num(width),
+        num(height),
+        attributes.trim()
+    );
+}
+
+/// Returns the fill attributes for a brush, with a leading space.
+fn fill<B: SvgBrush>(brush: &B) -> String {
+    match brush.svg_color() {
+        Some([r, g, b, 255]) => format!(r##" fill="#{:02x}{:02x}{:02x}""##, r, g, b),
+        Some([r, g, b, a]) => format!(
+            r##" fill="#{:02x}{:02x}{:02x}" fill-opacity="{}""##,
+            r,
+            g,
+            b,
+            num(a as f32 / 255.)
+        ),
+        None => String::new(),
+    }
+}
+
+/// Rounds a coordinate to two decimal places for compact output.
+fn num(value: f32) -> f32 {
+    (value * 100.).round() / 100.
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::{Alignment, InlineBox};
+    use crate::style::StyleProperty;
+    use crate::testing;
+
+    /// Returns the values of the attribute in the elements with the tag.
+    fn attributes(svg: &str, tag: &str, name: &str) -> Vec<String> {
+        svg.lines()
+            .filter(|line| line.starts_with(tag))
+            .filter_map(|line| {
+                let start = line.find(&format!(" {}=\"", name))? + name.len() + 3;
+                let len = line[start..].find('"')?;
+                Some(line[start..start + len].to_string())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn glyph_runs_are_written_as_filled_paths() {
+        let mut layout = testing::layout_with("ab c", |builder| {
+            builder.push(&StyleProperty::Brush([255, 0, 0, 255]), 0..1);
+            builder.push(&StyleProperty::Brush([0, 0, 255, 128]), 1..2);
+        });
+        layout.break_all_lines(None, Alignment::Start);
+        let svg = SvgExporter::new().export(&layout);
+        let (width, height) = (layout.full_width().ceil(), layout.height().ceil());
+        assert!(svg.starts_with(&format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" viewBox=\"0 0 {} {}\">\n",
+            width, height, width, height
+        )));
+        assert!(svg.ends_with("</svg>\n"));
+        let glyph_runs = layout.get(0).unwrap().glyph_runs().count();
+        assert_eq!(glyph_runs, 3);
+        assert_eq!(svg.matches("<path ").count(), glyph_runs);
+        assert_eq!(
+            attributes(&svg, "<path", "fill"),
+            ["#ff0000", "#0000ff", "#000000"]
+        );
+        assert_eq!(attributes(&svg, "<path", "fill-opacity"), ["0.5", "0"]);
+    }
+
+    #[test]
+    fn cluster_bounds_follow_inline_boxes_and_justification() {
+        let mut layout = testing::layout_with("a bb cc dd", |builder| {
+            builder.push_inline_box(InlineBox {
+                index: 2,
+                width: 10.,
+                height: 10.,
+                ..Default::default()
+            });
+        });
+        layout.break_all_lines(Some(60.), Alignment::Justified);
+        assert!(layout.len() > 1);
+        let mut exporter = SvgExporter::new();
+        exporter.set_debug_overlays(DebugOverlays {
+            cluster_bounds: true,
+            ..Default::default()
+        });
+        let svg = exporter.export(&layout);
+        let bounds = attributes(&svg, "<rect", "x");
+        let mut expected = vec![];
+        for line in layout.lines() {
+            for glyph_run in line.glyph_runs() {
+                for glyph in glyph_run.positioned_glyphs() {
+                    expected.push(num(glyph.x).to_string());
+                }
+            }
+        }
+        assert_eq!(bounds, expected);
+    }
+}
@@ -0,0 +1,41 @@
+//! Helpers for unit tests.
+
+use crate::font::FontContext;
+use crate::style::{FontFamily, FontStack, StyleProperty};
+use crate::{Layout, LayoutContext};
+
+/// Font size used by test layouts.
+pub const FONT_SIZE: f32 = 16.;
+
+/// Font stack that selects the DejaVu Sans font.
+pub const FONT_STACK: StyleProperty<'static, [u8; 4]> =
+    StyleProperty::FontStack(FontStack::Single(FontFamily::Named("DejaVu Sans")));
+
+/// Returns a font context that only contains the DejaVu Sans and DejaVu
+/// Serif fonts.
+pub fn font_context() -> FontContext {
+    let mut fcx = FontContext::empty();
+    fcx.register(include_bytes!("../fonts/DejaVuSans.ttf").to_vec());
+    fcx.register(include_bytes!("../fonts/DejaVuSerif.ttf").to_vec());
+    fcx
+}
+
+/// Builds a layout of the text in DejaVu Sans without breaking lines.
+pub fn layout(text: &str) -> Layout<[u8; 4]> {
+    layout_with(text, |_| {})
+}
+
+/// Builds a layout of the text in DejaVu Sans, applying additional
+/// properties with the specified function, without breaking lines.
+pub fn layout_with(
+    text: &str,
+    f: impl FnOnce(&mut crate::context::RangedBuilder<[u8; 4], &str>),
+) -> Layout<[u8; 4]> {
+    let mut fcx = font_context();
+    let mut lcx = LayoutContext::new();
+    let mut builder = lcx.ranged_builder(&mut fcx, text, 1.);
+    builder.push_default(&FONT_STACK);
+    builder.push_default(&StyleProperty::FontSize(FONT_SIZE));
+    f(&mut builder);
+    builder.build()
+}
@@ -7,3 +7,13 @@ pub fn nearly_eq(x: f32, y: f32) -> bool {
 pub fn nearly_zero(x: f32) -> bool {
     nearly_eq(x, 0.)
 }
+
+/// Returns the largest character boundary in the text that is not greater
+/// than the specified byte offset.
+pub fn floor_char_boundary(text: &str, offset: usize) -> usize {
+    let mut offset = offset.min(text.len());
+    while !text.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    offset
+}